@@ -7,7 +7,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let descriptor_file = out.join("descriptors.bin");
 
 	tonic_build::configure()
-		.build_client(false)
+		.build_client(true)
 		.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
 		.extern_path(".google.protobuf.Any", "::prost_wkt_types::Any")
 		.extern_path(".google.protobuf.Timestamp", "::prost_wkt_types::Timestamp")
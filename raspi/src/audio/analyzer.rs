@@ -0,0 +1,194 @@
+use std::{collections::VecDeque, f32::consts::PI, sync::Arc, time::Instant};
+
+use eyre::{ensure, Result};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::audio::{AudioFrame, BAND_RANGES};
+
+/// Number of analysis frames used as the reference for onset and beat detection.
+const HISTORY_LEN: usize = 43;
+/// Minimum time between two onsets (or two beats) in seconds.
+const MIN_ONSET_INTERVAL: f32 = 0.1;
+/// How fast the automatic gain follows the level down (per analysis frame).
+const GAIN_DECAY: f32 = 0.995;
+/// Lower bound for the automatic gain so silence doesn't get amplified into noise.
+const MIN_GAIN: f32 = 0.001;
+
+/// Spectrum, level and onset analysis over a stream of mono samples.
+pub struct Analyzer {
+	fft:             Arc<dyn Fft<f32>>,
+	fft_size:        usize,
+	hop:             usize,
+	window:          Vec<f32>,
+	onset_threshold: f32,
+
+	samples: VecDeque<f32>,
+	pending: usize,
+	buffer:  Vec<Complex<f32>>,
+	scratch: Vec<Complex<f32>>,
+
+	gain:          f32,
+	previous:      Vec<f32>,
+	flux_history:  VecDeque<f32>,
+	bass_history:  VecDeque<f32>,
+	onset_elapsed: f32,
+	beat_elapsed:  f32,
+
+	frame: AudioFrame,
+}
+
+impl Analyzer {
+	pub fn new(fft_size: usize, onset_threshold: f32) -> Result<Self> {
+		ensure!(
+			fft_size.is_power_of_two() && fft_size >= 64,
+			"fft size has to be a power of two >= 64, got {}",
+			fft_size
+		);
+
+		let fft = FftPlanner::new().plan_fft_forward(fft_size);
+		let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+
+		// hann window
+		let window = (0..fft_size)
+			.map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / fft_size as f32).cos())
+			.collect();
+
+		Ok(Analyzer {
+			fft,
+			fft_size,
+			hop: fft_size / 2,
+			window,
+			onset_threshold,
+
+			samples: VecDeque::from(vec![0.0; fft_size]),
+			pending: 0,
+			buffer: vec![Complex::default(); fft_size],
+			scratch,
+
+			gain: MIN_GAIN,
+			previous: vec![0.0; fft_size / 2],
+			flux_history: VecDeque::with_capacity(HISTORY_LEN),
+			bass_history: VecDeque::with_capacity(HISTORY_LEN),
+			onset_elapsed: f32::MAX,
+			beat_elapsed: f32::MAX,
+
+			frame: AudioFrame {
+				spectrum: vec![0.0; fft_size / 2],
+				..Default::default()
+			},
+		})
+	}
+
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.frame.sample_rate = sample_rate;
+	}
+
+	pub fn frame(&self) -> &AudioFrame {
+		&self.frame
+	}
+
+	/// Feeds new samples into the analyzer, returns `true` if the frame was updated.
+	pub fn process(&mut self, samples: &[f32]) -> bool {
+		let mut updated = false;
+
+		for sample in samples {
+			self.samples.pop_front();
+			self.samples.push_back(*sample);
+			self.pending += 1;
+
+			if self.pending >= self.hop {
+				self.analyze();
+				self.pending = 0;
+				updated = true;
+			}
+		}
+
+		updated
+	}
+
+	fn analyze(&mut self) {
+		let now = Instant::now();
+		let dt = self.hop as f32 / self.frame.sample_rate.max(1) as f32;
+
+		// level over the new samples
+		let (sum, peak) = self
+			.samples
+			.iter()
+			.skip(self.fft_size - self.hop)
+			.fold((0.0, 0.0f32), |(sum, peak), s| {
+				(sum + s * s, peak.max(s.abs()))
+			});
+		self.frame.rms = (sum / self.hop as f32).sqrt();
+		self.frame.peak = peak;
+
+		for ((out, sample), window) in self
+			.buffer
+			.iter_mut()
+			.zip(self.samples.iter())
+			.zip(self.window.iter())
+		{
+			*out = Complex::new(sample * window, 0.0);
+		}
+		self.fft
+			.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+		let scale = 2.0 / self.fft_size as f32;
+		let max = self.buffer[..self.fft_size / 2]
+			.iter()
+			.fold(0.0f32, |max, c| max.max(c.norm() * scale));
+		self.gain = (self.gain * GAIN_DECAY).max(max).max(MIN_GAIN);
+
+		let mut flux = 0.0;
+		for (i, c) in self.buffer[..self.fft_size / 2].iter().enumerate() {
+			let magnitude = c.norm() * scale / self.gain;
+
+			flux += (magnitude - self.previous[i]).max(0.0);
+			self.previous[i] = magnitude;
+			self.frame.spectrum[i] = magnitude;
+		}
+
+		self.frame.bands = BAND_RANGES.map(|(from, to)| self.frame.energy(from, to));
+
+		self.onset_elapsed += dt;
+		if detect(
+			&mut self.flux_history,
+			flux,
+			self.onset_threshold,
+			self.onset_elapsed,
+		) {
+			self.frame.onsets += 1;
+			self.frame.last_onset = Some(now);
+			self.onset_elapsed = 0.0;
+		}
+
+		self.beat_elapsed += dt;
+		if detect(
+			&mut self.bass_history,
+			self.frame.bass(),
+			self.onset_threshold,
+			self.beat_elapsed,
+		) {
+			self.frame.beats += 1;
+			self.frame.last_beat = Some(now);
+			self.beat_elapsed = 0.0;
+		}
+
+		self.frame.updated = Some(now);
+	}
+}
+
+/// Compares the value against the average of the history and records it.
+fn detect(history: &mut VecDeque<f32>, value: f32, threshold: f32, elapsed: f32) -> bool {
+	let average = if history.is_empty() {
+		f32::MAX
+	} else {
+		history.iter().sum::<f32>() / history.len() as f32
+	};
+
+	if history.len() == HISTORY_LEN {
+		history.pop_front();
+	}
+	history.push_back(value);
+
+	value > average * threshold && value > 0.01 && elapsed >= MIN_ONSET_INTERVAL
+}
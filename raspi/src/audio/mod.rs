@@ -0,0 +1,368 @@
+use std::{
+	net::SocketAddr,
+	path::PathBuf,
+	sync::{Arc, Mutex},
+	thread::JoinHandle,
+	time::{Duration, Instant},
+};
+
+use educe::Educe;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+pub use crate::audio::analyzer::Analyzer;
+use crate::audio::source::{AudioSource, PcmSource, UdpSource, WavSource};
+
+mod analyzer;
+#[cfg(feature = "pulse")]
+mod pulse;
+mod source;
+
+/// Frequency ranges (in Hz) of the bands in [`AudioFrame::bands`].
+///
+/// sub bass, bass, low mids, mids, high mids, presence, brilliance
+pub const BAND_RANGES: [(f32, f32); 7] = [
+	(20.0, 60.0),
+	(60.0, 250.0),
+	(250.0, 500.0),
+	(500.0, 2000.0),
+	(2000.0, 4000.0),
+	(4000.0, 6000.0),
+	(6000.0, 20000.0),
+];
+
+/// How long an analysis result is considered current.
+const STALE_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Serialize, Deserialize, Educe)]
+#[educe(Default)]
+pub struct AudioConfig {
+	#[serde(default)]
+	pub source: AudioSourceConfig,
+
+	/// Number of samples per FFT, has to be a power of two.
+	#[educe(Default = 1024)]
+	#[serde(default = "default_fft_size")]
+	pub fft_size: usize,
+
+	/// How far above the recent average the spectral flux has to be to count as an onset.
+	#[educe(Default = 1.5)]
+	#[serde(default = "default_onset_threshold")]
+	pub onset_threshold: f32,
+}
+
+fn default_fft_size() -> usize {
+	AudioConfig::default().fft_size
+}
+
+fn default_onset_threshold() -> f32 {
+	AudioConfig::default().onset_threshold
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AudioSourceConfig {
+	#[default]
+	None,
+	/// Plays back a wav file in real time.
+	Wav {
+		path:   PathBuf,
+		#[serde(default)]
+		looped: bool,
+	},
+	/// Raw interleaved PCM from a file or FIFO, or stdin if no path is given.
+	Pcm {
+		path:   Option<PathBuf>,
+		#[serde(flatten)]
+		format: PcmFormat,
+	},
+	/// Raw interleaved PCM, one or more frames per datagram.
+	Udp {
+		bind:   SocketAddr,
+		#[serde(flatten)]
+		format: PcmFormat,
+	},
+	#[cfg(feature = "pulse")]
+	Pulse {
+		device:      Option<String>,
+		#[serde(default = "default_sample_rate")]
+		sample_rate: u32,
+	},
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Educe)]
+#[educe(Default)]
+pub struct PcmFormat {
+	#[serde(default)]
+	pub sample_format: SampleFormat,
+	#[educe(Default = 44100)]
+	#[serde(default = "default_sample_rate")]
+	pub sample_rate:   u32,
+	#[educe(Default = 2)]
+	#[serde(default = "default_channels")]
+	pub channels:      u16,
+}
+
+fn default_sample_rate() -> u32 {
+	PcmFormat::default().sample_rate
+}
+
+fn default_channels() -> u16 {
+	PcmFormat::default().channels
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleFormat {
+	#[default]
+	S16Le,
+	F32Le,
+}
+
+impl SampleFormat {
+	pub fn bytes(&self) -> usize {
+		match self {
+			SampleFormat::S16Le => 2,
+			SampleFormat::F32Le => 4,
+		}
+	}
+}
+
+impl AudioSourceConfig {
+	fn open(&self) -> Result<Option<Box<dyn AudioSource>>> {
+		let source: Box<dyn AudioSource> = match self {
+			AudioSourceConfig::None => return Ok(None),
+			AudioSourceConfig::Wav { path, looped } => Box::new(WavSource::open(path, *looped)?),
+			AudioSourceConfig::Pcm { path: None, format } => {
+				Box::new(PcmSource::new(std::io::stdin(), *format))
+			}
+			AudioSourceConfig::Pcm {
+				path: Some(path),
+				format,
+			} => Box::new(PcmSource::new(std::fs::File::open(path)?, *format)),
+			AudioSourceConfig::Udp { bind, format } => Box::new(UdpSource::bind(*bind, *format)?),
+			#[cfg(feature = "pulse")]
+			AudioSourceConfig::Pulse {
+				device,
+				sample_rate,
+			} => Box::new(pulse::PulseSource::connect(
+				device.as_deref(),
+				*sample_rate,
+			)?),
+		};
+
+		Ok(Some(source))
+	}
+
+	/// Whether the source should be opened again after it ran out of samples (e.g. the writer of a
+	/// FIFO went away).
+	///
+	/// A wav file that isn't looped just ends, a looped one never runs out.
+	fn reopen_on_end(&self) -> bool {
+		!matches!(
+			self,
+			AudioSourceConfig::None
+				| AudioSourceConfig::Wav { looped: false, .. }
+				| AudioSourceConfig::Pcm { path: None, .. }
+		)
+	}
+}
+
+/// The latest analysis result of the audio input.
+#[derive(Clone, Debug, Default)]
+pub struct AudioFrame {
+	pub sample_rate: u32,
+	/// Magnitudes of the FFT bins up to the nyquist frequency, normalized to roughly `0.0..=1.0`.
+	pub spectrum:    Vec<f32>,
+	/// Average magnitude per band of [`BAND_RANGES`].
+	pub bands:       [f32; BAND_RANGES.len()],
+	pub rms:         f32,
+	pub peak:        f32,
+	/// Number of onsets detected since the start, compare with a previous value to detect new ones.
+	pub onsets:      u64,
+	/// Number of beats (onsets in the bass) detected since the start.
+	pub beats:       u64,
+	pub last_onset:  Option<Instant>,
+	pub last_beat:   Option<Instant>,
+	pub updated:     Option<Instant>,
+}
+
+impl AudioFrame {
+	/// Whether there is a running audio source that recently delivered samples.
+	pub fn is_active(&self) -> bool {
+		self.updated
+			.is_some_and(|updated| updated.elapsed() < STALE_AFTER)
+	}
+
+	pub fn bin_frequency(&self, bin: usize) -> f32 {
+		if self.spectrum.is_empty() {
+			return 0.0;
+		}
+
+		bin as f32 * self.sample_rate as f32 / (2 * self.spectrum.len()) as f32
+	}
+
+	pub fn frequency_bin(&self, frequency: f32) -> usize {
+		if self.sample_rate == 0 {
+			return 0;
+		}
+
+		let bin = frequency * (2 * self.spectrum.len()) as f32 / self.sample_rate as f32;
+		(bin.round() as usize).min(self.spectrum.len().saturating_sub(1))
+	}
+
+	/// Average magnitude of the spectrum between the two frequencies.
+	pub fn energy(&self, from_hz: f32, to_hz: f32) -> f32 {
+		if self.spectrum.is_empty() {
+			return 0.0;
+		}
+
+		let from = self.frequency_bin(from_hz);
+		let to = self
+			.frequency_bin(to_hz)
+			.max(from + 1)
+			.min(self.spectrum.len());
+
+		self.spectrum[from..to].iter().sum::<f32>() / (to - from) as f32
+	}
+
+	pub fn bass(&self) -> f32 {
+		(self.bands[0] + self.bands[1]) / 2.0
+	}
+
+	pub fn since_beat(&self) -> Option<Duration> {
+		self.last_beat.map(|last| last.elapsed())
+	}
+
+	pub fn since_onset(&self) -> Option<Duration> {
+		self.last_onset.map(|last| last.elapsed())
+	}
+}
+
+/// Handle to the audio analysis thread.
+pub struct Audio {
+	frame:   Arc<Mutex<AudioFrame>>,
+	_handle: Option<JoinHandle<()>>,
+}
+
+impl Audio {
+	/// A handle without any source, always returns a silent frame.
+	pub fn disabled() -> Self {
+		Audio {
+			frame:   Default::default(),
+			_handle: None,
+		}
+	}
+
+	/// Starts reading and analysing audio from the configured source on a separate thread.
+	///
+	/// Changes to the config require a restart.
+	pub fn start(config: &AudioConfig) -> Result<Self> {
+		if matches!(config.source, AudioSourceConfig::None) {
+			return Ok(Self::disabled());
+		}
+
+		let frame = Arc::new(Mutex::new(AudioFrame::default()));
+		let mut analyzer = Analyzer::new(config.fft_size, config.onset_threshold)?;
+
+		let handle = {
+			let frame = frame.clone();
+			let config = config.clone();
+
+			std::thread::Builder::new()
+				.name("audio".to_string())
+				.spawn(move || {
+					info!("starting audio analysis from {:?}", config.source);
+					run_source(&config.source, &mut analyzer, &frame);
+					info!("audio analysis stopped");
+				})?
+		};
+
+		Ok(Audio {
+			frame,
+			_handle: Some(handle),
+		})
+	}
+
+	pub fn frame(&self) -> AudioFrame {
+		self.frame
+			.lock()
+			.map(|frame| frame.clone())
+			.unwrap_or_default()
+	}
+}
+
+fn run_source(config: &AudioSourceConfig, analyzer: &mut Analyzer, frame: &Mutex<AudioFrame>) {
+	let mut samples = Vec::new();
+
+	loop {
+		let mut source = match config.open() {
+			Ok(Some(source)) => source,
+			Ok(None) => return,
+			Err(err) => {
+				error!("failed to open audio source: {:#}", err);
+				std::thread::sleep(Duration::from_secs(5));
+				continue;
+			}
+		};
+
+		analyzer.set_sample_rate(source.sample_rate());
+
+		loop {
+			match source.read(&mut samples) {
+				Ok(true) => {}
+				Ok(false) => break,
+				Err(err) => {
+					error!("failed reading from audio source: {:#}", err);
+					break;
+				}
+			}
+
+			if analyzer.process(&samples) {
+				if let Ok(mut frame) = frame.lock() {
+					frame.clone_from(analyzer.frame());
+				}
+			}
+		}
+
+		if !config.reopen_on_end() {
+			return;
+		}
+
+		warn!("audio source ended, reopening");
+		std::thread::sleep(Duration::from_secs(1));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reopen_on_end() {
+		let wav = |looped| AudioSourceConfig::Wav {
+			path: "audio.wav".into(),
+			looped,
+		};
+		assert!(!wav(false).reopen_on_end());
+		assert!(wav(true).reopen_on_end());
+
+		assert!(!AudioSourceConfig::None.reopen_on_end());
+		assert!(!AudioSourceConfig::Pcm {
+			path:   None,
+			format: PcmFormat::default(),
+		}
+		.reopen_on_end());
+		assert!(AudioSourceConfig::Pcm {
+			path:   Some("audio.fifo".into()),
+			format: PcmFormat::default(),
+		}
+		.reopen_on_end());
+		assert!(AudioSourceConfig::Udp {
+			bind:   "127.0.0.1:0".parse().unwrap(),
+			format: PcmFormat::default(),
+		}
+		.reopen_on_end());
+	}
+}
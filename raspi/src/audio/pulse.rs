@@ -0,0 +1,80 @@
+use eyre::{eyre, Result};
+use libpulse_binding::{
+	sample::{Format, Spec},
+	stream::Direction,
+};
+use libpulse_simple_binding::Simple;
+
+use crate::{
+	audio::{source::AudioSource, PcmFormat, SampleFormat},
+	APP_NAME,
+};
+
+const BLOCK_FRAMES: usize = 256;
+
+/// Records from a PulseAudio source, which also works on PipeWire through `pipewire-pulse`.
+///
+/// Use the monitor of a sink as the device to analyse what is currently playing.
+pub struct PulseSource {
+	simple: Simple,
+	format: PcmFormat,
+	buffer: Vec<u8>,
+}
+
+// `Simple` only wraps a pointer to the connection, which is safe to move to the audio thread.
+unsafe impl Send for PulseSource {}
+
+impl PulseSource {
+	pub fn connect(device: Option<&str>, sample_rate: u32) -> Result<Self> {
+		let format = PcmFormat {
+			sample_format: SampleFormat::F32Le,
+			sample_rate,
+			channels: 1,
+		};
+
+		let spec = Spec {
+			format:   Format::F32le,
+			channels: 1,
+			rate:     sample_rate,
+		};
+
+		let simple = Simple::new(
+			None,
+			APP_NAME,
+			Direction::Record,
+			device,
+			"audio analysis",
+			&spec,
+			None,
+			None,
+		)
+		.map_err(|err| eyre!("failed connecting to pulseaudio: {}", err))?;
+
+		Ok(PulseSource {
+			simple,
+			format,
+			buffer: vec![0; BLOCK_FRAMES * format.sample_format.bytes()],
+		})
+	}
+}
+
+impl AudioSource for PulseSource {
+	fn sample_rate(&self) -> u32 {
+		self.format.sample_rate
+	}
+
+	fn read(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+		self.simple
+			.read(&mut self.buffer)
+			.map_err(|err| eyre!("failed reading from pulseaudio: {}", err))?;
+
+		out.clear();
+		out.extend(
+			self.buffer
+				.chunks_exact(4)
+				.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+		);
+
+		Ok(true)
+	}
+}
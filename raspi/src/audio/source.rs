@@ -0,0 +1,209 @@
+use std::{
+	io::{BufReader, ErrorKind, Read},
+	net::{SocketAddr, UdpSocket},
+	path::Path,
+	time::{Duration, Instant},
+};
+
+use eyre::{bail, Result, WrapErr};
+use hound::{SampleFormat as WavSampleFormat, WavReader};
+
+use crate::audio::{PcmFormat, SampleFormat};
+
+/// Number of frames returned per read by sources that don't have a natural block size.
+const BLOCK_FRAMES: usize = 256;
+
+pub trait AudioSource: Send {
+	fn sample_rate(&self) -> u32;
+
+	/// Reads the next block of samples (downmixed to mono) into `out`, replacing its contents.
+	///
+	/// Returns `false` once the source is exhausted.
+	fn read(&mut self, out: &mut Vec<f32>) -> Result<bool>;
+}
+
+fn downmix(out: &mut Vec<f32>, samples: impl Iterator<Item = f32>, channels: usize) {
+	out.clear();
+
+	let channels = channels.max(1);
+	let mut acc = 0.0;
+	for (i, sample) in samples.enumerate() {
+		acc += sample;
+
+		if (i + 1) % channels == 0 {
+			out.push(acc / channels as f32);
+			acc = 0.0;
+		}
+	}
+}
+
+fn decode(out: &mut Vec<f32>, bytes: &[u8], format: &PcmFormat) {
+	match format.sample_format {
+		SampleFormat::S16Le => downmix(
+			out,
+			bytes
+				.chunks_exact(2)
+				.map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32),
+			format.channels as usize,
+		),
+		SampleFormat::F32Le => downmix(
+			out,
+			bytes
+				.chunks_exact(4)
+				.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+			format.channels as usize,
+		),
+	}
+}
+
+/// Plays back a wav file, paced to the sample rate of the file.
+pub struct WavSource {
+	samples:     Vec<f32>,
+	sample_rate: u32,
+	looped:      bool,
+
+	pos:     usize,
+	played:  u64,
+	started: Instant,
+}
+
+impl WavSource {
+	pub fn open(path: &Path, looped: bool) -> Result<Self> {
+		let reader = WavReader::open(path)
+			.wrap_err_with(|| format!("opening wav file {}", path.display()))?;
+		let spec = reader.spec();
+
+		let interleaved: Vec<f32> = match spec.sample_format {
+			WavSampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+			WavSampleFormat::Int => {
+				let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
+				reader
+					.into_samples::<i32>()
+					.map(|s| s.map(|s| s as f32 / max))
+					.collect::<Result<_, _>>()?
+			}
+		};
+
+		let mut samples = Vec::with_capacity(interleaved.len() / spec.channels as usize);
+		downmix(
+			&mut samples,
+			interleaved.into_iter(),
+			spec.channels as usize,
+		);
+
+		if samples.is_empty() {
+			bail!("wav file {} does not contain any samples", path.display());
+		}
+
+		Ok(WavSource {
+			samples,
+			sample_rate: spec.sample_rate,
+			looped,
+
+			pos: 0,
+			played: 0,
+			started: Instant::now(),
+		})
+	}
+}
+
+impl AudioSource for WavSource {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn read(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+		if self.pos >= self.samples.len() {
+			if !self.looped {
+				return Ok(false);
+			}
+			self.pos = 0;
+		}
+
+		let end = (self.pos + BLOCK_FRAMES).min(self.samples.len());
+		out.clear();
+		out.extend_from_slice(&self.samples[self.pos..end]);
+
+		self.played += (end - self.pos) as u64;
+		self.pos = end;
+
+		// don't run ahead of real time
+		let target = Duration::from_secs_f64(self.played as f64 / self.sample_rate as f64);
+		if let Some(ahead) = target.checked_sub(self.started.elapsed()) {
+			std::thread::sleep(ahead);
+		}
+
+		Ok(true)
+	}
+}
+
+/// Raw interleaved PCM from anything readable (stdin, a file or a FIFO).
+pub struct PcmSource<R> {
+	reader: BufReader<R>,
+	format: PcmFormat,
+	buffer: Vec<u8>,
+}
+
+impl<R: Read> PcmSource<R> {
+	pub fn new(reader: R, format: PcmFormat) -> Self {
+		let frame_bytes = format.sample_format.bytes() * format.channels.max(1) as usize;
+
+		PcmSource {
+			reader: BufReader::new(reader),
+			format,
+			buffer: vec![0; BLOCK_FRAMES * frame_bytes],
+		}
+	}
+}
+
+impl<R: Read + Send> AudioSource for PcmSource<R> {
+	fn sample_rate(&self) -> u32 {
+		self.format.sample_rate
+	}
+
+	fn read(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+		match self.reader.read_exact(&mut self.buffer) {
+			Ok(()) => {}
+			Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+			Err(err) => return Err(err.into()),
+		}
+
+		decode(out, &self.buffer, &self.format);
+
+		Ok(true)
+	}
+}
+
+/// Raw interleaved PCM received over UDP, every datagram has to contain whole frames.
+pub struct UdpSource {
+	socket: UdpSocket,
+	format: PcmFormat,
+	buffer: Vec<u8>,
+}
+
+impl UdpSource {
+	pub fn bind(addr: SocketAddr, format: PcmFormat) -> Result<Self> {
+		let socket =
+			UdpSocket::bind(addr).wrap_err_with(|| format!("binding audio udp socket {}", addr))?;
+
+		Ok(UdpSource {
+			socket,
+			format,
+			buffer: vec![0; u16::MAX as usize],
+		})
+	}
+}
+
+impl AudioSource for UdpSource {
+	fn sample_rate(&self) -> u32 {
+		self.format.sample_rate
+	}
+
+	fn read(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+		let len = self.socket.recv(&mut self.buffer)?;
+
+		decode(out, &self.buffer[..len], &self.format);
+
+		Ok(true)
+	}
+}
@@ -0,0 +1,598 @@
+//! Access control for the grpc and http apis.
+//!
+//! Clients authenticate with a token from the auth file, either as `Authorization: Bearer <token>`,
+//! as the password of basic auth (so a token can be shared as password and entered in the browser),
+//! or as `token` query parameter for websockets. Every token has a [`Role`], requests without a
+//! valid token get the `anonymous` role or are rejected.
+
+use std::{
+	fmt,
+	fs,
+	path::Path,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::ValueEnum;
+use eyre::{bail, Result, WrapErr};
+use futures::future::{self, Either, Ready};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::{debug, warn};
+
+/// Roles include the permissions of the ones before them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+	/// Can read everything.
+	Viewer,
+	/// Can change the state, presets and power and stream pixels.
+	Operator,
+	/// Can change the segments, groups, global config, scripts and restore backups.
+	Admin,
+}
+
+impl fmt::Display for Role {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Role::Viewer => "viewer",
+			Role::Operator => "operator",
+			Role::Admin => "admin",
+		};
+		f.write_str(name)
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Token {
+	/// Shown in the history for changes made with the token.
+	pub name:  String,
+	pub token: String,
+	pub role:  Role,
+}
+
+/// Contents of the auth file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+	/// Role of requests without a token, they are rejected if not set.
+	pub anonymous: Option<Role>,
+	pub tokens:    Vec<Token>,
+}
+
+impl AuthConfig {
+	pub fn load(path: &Path) -> Result<Self> {
+		let data =
+			fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+		let config: AuthConfig =
+			toml::from_str(&data).wrap_err_with(|| format!("parsing {}", path.display()))?;
+
+		for (i, token) in config.tokens.iter().enumerate() {
+			if token.token.len() < 16 {
+				bail!("token {} ({}) is shorter than 16 characters", i, token.name);
+			}
+		}
+
+		Ok(config)
+	}
+}
+
+/// A random token to put in the auth file.
+pub fn generate_token() -> String {
+	rand::thread_rng()
+		.sample_iter(&Alphanumeric)
+		.take(32)
+		.map(char::from)
+		.collect()
+}
+
+/// Who made a request, added to the request extensions by [`AuthLayer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+	/// Name of the token, `None` for anonymous requests.
+	pub name: Option<String>,
+	pub role: Role,
+}
+
+impl Identity {
+	/// Everyone is an admin if auth is disabled.
+	fn unrestricted() -> Self {
+		Identity {
+			name: None,
+			role: Role::Admin,
+		}
+	}
+}
+
+/// Compares in constant time to not leak how much of a token matched.
+fn secure_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn credential(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+	if let Some(value) = headers
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+	{
+		if let Some(token) = value.strip_prefix("Bearer ") {
+			return Some(token.trim().to_string());
+		}
+
+		if let Some(encoded) = value.strip_prefix("Basic ") {
+			let decoded = BASE64.decode(encoded.trim()).ok()?;
+			let decoded = String::from_utf8(decoded).ok()?;
+			let (_user, password) = decoded.split_once(':')?;
+			return Some(password.to_string());
+		}
+	}
+
+	uri.query()?
+		.split('&')
+		.find_map(|pair| pair.strip_prefix("token="))
+		.map(|token| token.to_string())
+}
+
+#[derive(Debug)]
+enum AuthError {
+	/// No or an unknown token.
+	Unauthenticated,
+	PermissionDenied {
+		required: Role,
+		role:     Role,
+	},
+}
+
+#[derive(Debug, Default)]
+pub struct Auth {
+	/// `None` disables auth.
+	config: Option<AuthConfig>,
+}
+
+impl Auth {
+	pub fn new(config: AuthConfig) -> Self {
+		Auth {
+			config: Some(config),
+		}
+	}
+
+	/// Every request is allowed.
+	pub fn disabled() -> Self {
+		Auth { config: None }
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.config.is_some()
+	}
+
+	/// Loads the auth file, auth is disabled if it doesn't exist.
+	pub fn load(path: &Path) -> Result<Self> {
+		if !path.exists() {
+			warn!(
+				"{} doesn't exist, the api is open to everyone on the network",
+				path.display()
+			);
+			return Ok(Auth::disabled());
+		}
+
+		Ok(Auth::new(AuthConfig::load(path)?))
+	}
+
+	fn identify(&self, headers: &HeaderMap, uri: &Uri) -> Result<Identity, AuthError> {
+		let Some(config) = &self.config else {
+			return Ok(Identity::unrestricted());
+		};
+
+		let Some(credential) = credential(headers, uri) else {
+			return config
+				.anonymous
+				.map(|role| Identity { name: None, role })
+				.ok_or(AuthError::Unauthenticated);
+		};
+
+		config
+			.tokens
+			.iter()
+			.find(|token| secure_eq(token.token.as_bytes(), credential.as_bytes()))
+			.map(|token| Identity {
+				name: Some(token.name.clone()),
+				role: token.role,
+			})
+			.ok_or(AuthError::Unauthenticated)
+	}
+
+	fn authorize(
+		&self,
+		headers: &HeaderMap,
+		uri: &Uri,
+		required: Option<Role>,
+	) -> Result<Identity, AuthError> {
+		let identity = match self.identify(headers, uri) {
+			Ok(identity) => identity,
+			// public routes don't need a valid token
+			Err(_) if required.is_none() => {
+				return Ok(Identity {
+					name: None,
+					role: Role::Viewer,
+				})
+			}
+			Err(err) => return Err(err),
+		};
+
+		match required {
+			Some(required) if identity.role < required => Err(AuthError::PermissionDenied {
+				required,
+				role: identity.role,
+			}),
+			_ => Ok(identity),
+		}
+	}
+}
+
+/// The grpc method of a request path, e.g. `SetState` for `/lighting.Controller/SetState`.
+fn grpc_method(path: &str) -> Option<&str> {
+	path.strip_prefix("/lighting.Controller/")
+}
+
+/// Role needed for a grpc method, unknown methods need admin.
+pub fn method_role(method: &str) -> Role {
+	match method {
+		"GetConfig" | "ListSegments" | "ListGroups" | "ListEffects" | "ListPresets"
+		| "GetState" | "StreamState" | "GetPower" | "ListScripts" | "ListBackups"
+		| "ListHistory" | "GetRenderStats" | "ExportBundle" | "ValidateLayout" => Role::Viewer,
+
+		// undoing or redoing segment and group changes is checked in the handler
+		"SetState" | "SetStateEffect" | "LoadPreset" | "SavePreset" | "SetPreset"
+		| "DeletePreset" | "SetPower" | "PushFrames" | "Undo" | "Redo" => Role::Operator,
+
+		_ => Role::Admin,
+	}
+}
+
+/// Role needed for a request, `None` for public ones like the frontend.
+fn required_role(path: &str) -> Option<Role> {
+	if let Some(method) = grpc_method(path) {
+		return Some(method_role(method));
+	}
+
+	match path {
+		"/api/stream" => Some(Role::Operator),
+		"/metrics" => Some(Role::Viewer),
+		_ if path.starts_with("/api/") => Some(Role::Admin),
+		_ => None,
+	}
+}
+
+fn is_grpc(headers: &HeaderMap) -> bool {
+	headers
+		.get(header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+fn reject<B: Default>(err: AuthError, grpc_content_type: Option<&HeaderValue>) -> Response<B> {
+	let message = match &err {
+		AuthError::Unauthenticated => "missing or invalid token".to_string(),
+		AuthError::PermissionDenied { required, role } => {
+			format!("requires the {} role, the token has {}", required, role)
+		}
+	};
+
+	if let Some(content_type) = grpc_content_type {
+		let status = match err {
+			AuthError::Unauthenticated => Status::unauthenticated(message),
+			AuthError::PermissionDenied { .. } => Status::permission_denied(message),
+		};
+
+		// grpc-web clients expect their own content type back
+		let (mut parts, _) = status.into_http().into_parts();
+		parts
+			.headers
+			.insert(header::CONTENT_TYPE, content_type.clone());
+		return Response::from_parts(parts, B::default());
+	}
+
+	let mut response = Response::new(B::default());
+	match err {
+		AuthError::Unauthenticated => {
+			*response.status_mut() = StatusCode::UNAUTHORIZED;
+			response.headers_mut().insert(
+				header::WWW_AUTHENTICATE,
+				HeaderValue::from_static("Basic realm=\"robolab\""),
+			);
+		}
+		AuthError::PermissionDenied { .. } => *response.status_mut() = StatusCode::FORBIDDEN,
+	}
+	debug!("rejected request: {}", message);
+
+	response
+}
+
+/// Checks the token of requests and adds the [`Identity`] to the extensions.
+#[derive(Clone)]
+pub struct AuthLayer {
+	auth: Arc<Auth>,
+}
+
+impl AuthLayer {
+	pub fn new(auth: Arc<Auth>) -> Self {
+		AuthLayer { auth }
+	}
+}
+
+impl<S> Layer<S> for AuthLayer {
+	type Service = AuthService<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		AuthService {
+			auth: self.auth.clone(),
+			inner,
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+	auth:  Arc<Auth>,
+	inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AuthService<S>
+where
+	S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+	ResBody: Default,
+{
+	type Response = Response<ResBody>;
+	type Error = S::Error;
+	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+		// cors preflights never carry credentials, they're answered by the cors handling inside
+		if request.method() == Method::OPTIONS {
+			return Either::Left(self.inner.call(request));
+		}
+
+		let required = required_role(request.uri().path());
+
+		match self
+			.auth
+			.authorize(request.headers(), request.uri(), required)
+		{
+			Ok(identity) => {
+				request.extensions_mut().insert(identity);
+				Either::Left(self.inner.call(request))
+			}
+			Err(err) => {
+				let content_type = is_grpc(request.headers())
+					.then(|| request.headers().get(header::CONTENT_TYPE))
+					.flatten();
+				Either::Right(future::ready(Ok(reject(err, content_type))))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::convert::Infallible;
+
+	use tower::{service_fn, ServiceExt};
+
+	use super::*;
+
+	fn auth() -> Auth {
+		Auth::new(AuthConfig {
+			anonymous: None,
+			tokens:    vec![Token {
+				name:  "operator".to_string(),
+				token: "operator-token-0123".to_string(),
+				role:  Role::Operator,
+			}],
+		})
+	}
+
+	async fn send(request: Request<String>) -> Response<String> {
+		let service =
+			AuthLayer::new(Arc::new(auth())).layer(service_fn(|_: Request<String>| async {
+				Ok::<_, Infallible>(Response::new("ok".to_string()))
+			}));
+
+		service.oneshot(request).await.unwrap()
+	}
+
+	fn headers(authorization: &str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+		headers
+	}
+
+	#[test]
+	fn roles_are_ordered() {
+		assert!(Role::Viewer < Role::Operator);
+		assert!(Role::Operator < Role::Admin);
+	}
+
+	#[test]
+	fn method_roles() {
+		for (method, role) in [
+			("GetState", Role::Viewer),
+			("ListHistory", Role::Viewer),
+			("ExportBundle", Role::Viewer),
+			("SetState", Role::Operator),
+			("PushFrames", Role::Operator),
+			("Undo", Role::Operator),
+			("SetSegments", Role::Admin),
+			("SetScript", Role::Admin),
+			("ImportBundle", Role::Admin),
+			("Unknown", Role::Admin),
+		] {
+			assert_eq!(method_role(method), role, "{}", method);
+		}
+	}
+
+	#[test]
+	fn required_roles() {
+		for (path, role) in [
+			("/lighting.Controller/GetState", Some(Role::Viewer)),
+			("/lighting.Controller/SetPower", Some(Role::Operator)),
+			("/lighting.Controller/RestoreBackup", Some(Role::Admin)),
+			("/api/stream", Some(Role::Operator)),
+			("/api/state", Some(Role::Admin)),
+			("/metrics", Some(Role::Viewer)),
+			("/", None),
+			("/index.html", None),
+		] {
+			assert_eq!(required_role(path), role, "{}", path);
+		}
+	}
+
+	#[test]
+	fn credentials() {
+		let uri = Uri::from_static("/api/stream");
+
+		assert_eq!(
+			credential(&headers("Bearer abc "), &uri).as_deref(),
+			Some("abc")
+		);
+		// user:abc
+		assert_eq!(
+			credential(&headers("Basic dXNlcjphYmM="), &uri).as_deref(),
+			Some("abc")
+		);
+		assert_eq!(credential(&headers("Basic !!!"), &uri), None);
+		// no colon
+		assert_eq!(credential(&headers("Basic dXNlcg=="), &uri), None);
+		assert_eq!(credential(&headers("Digest abc"), &uri), None);
+
+		let uri = Uri::from_static("/api/stream?a=1&token=abc");
+		assert_eq!(credential(&HeaderMap::new(), &uri).as_deref(), Some("abc"));
+		// the header takes precedence
+		assert_eq!(
+			credential(&headers("Bearer def"), &uri).as_deref(),
+			Some("def")
+		);
+	}
+
+	#[test]
+	fn authorize() {
+		let auth = auth();
+		let uri = Uri::from_static("/");
+		let token = headers("Bearer operator-token-0123");
+
+		let identity = auth.authorize(&token, &uri, Some(Role::Operator)).unwrap();
+		assert_eq!(identity.name.as_deref(), Some("operator"));
+		assert_eq!(identity.role, Role::Operator);
+
+		assert!(matches!(
+			auth.authorize(&token, &uri, Some(Role::Admin)),
+			Err(AuthError::PermissionDenied {
+				required: Role::Admin,
+				role:     Role::Operator,
+			})
+		));
+		assert!(matches!(
+			auth.authorize(&headers("Bearer wrong"), &uri, Some(Role::Viewer)),
+			Err(AuthError::Unauthenticated)
+		));
+		assert!(matches!(
+			auth.authorize(&HeaderMap::new(), &uri, Some(Role::Viewer)),
+			Err(AuthError::Unauthenticated)
+		));
+		// public routes work without a valid token
+		assert!(auth.authorize(&headers("Bearer wrong"), &uri, None).is_ok());
+
+		let anonymous = Auth::new(AuthConfig {
+			anonymous: Some(Role::Viewer),
+			..AuthConfig::default()
+		});
+		assert!(anonymous
+			.authorize(&HeaderMap::new(), &uri, Some(Role::Viewer))
+			.is_ok());
+		assert!(anonymous
+			.authorize(&HeaderMap::new(), &uri, Some(Role::Operator))
+			.is_err());
+
+		assert_eq!(
+			Auth::disabled()
+				.authorize(&HeaderMap::new(), &uri, Some(Role::Admin))
+				.unwrap(),
+			Identity::unrestricted()
+		);
+	}
+
+	#[test]
+	fn rejections() {
+		let response: Response<String> = reject(AuthError::Unauthenticated, None);
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+		assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+		let response: Response<String> = reject(
+			AuthError::PermissionDenied {
+				required: Role::Admin,
+				role:     Role::Viewer,
+			},
+			None,
+		);
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+		let content_type = HeaderValue::from_static("application/grpc-web+proto");
+		let response: Response<String> = reject(AuthError::Unauthenticated, Some(&content_type));
+		// grpc errors are sent as status in the headers of a successful response
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+		let status = Status::from_header_map(response.headers()).unwrap();
+		assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+		let response: Response<String> = reject(
+			AuthError::PermissionDenied {
+				required: Role::Admin,
+				role:     Role::Viewer,
+			},
+			Some(&content_type),
+		);
+		let status = Status::from_header_map(response.headers()).unwrap();
+		assert_eq!(status.code(), tonic::Code::PermissionDenied);
+	}
+
+	#[tokio::test]
+	async fn grpc_rejection() {
+		let request = Request::builder()
+			.method(Method::POST)
+			.uri("/lighting.Controller/SetState")
+			.header(header::CONTENT_TYPE, "application/grpc")
+			.body(String::new())
+			.unwrap();
+		let response = send(request).await;
+
+		let status = Status::from_header_map(response.headers()).unwrap();
+		assert_eq!(status.code(), tonic::Code::Unauthenticated);
+	}
+
+	#[tokio::test]
+	async fn preflight_passes() {
+		let request = Request::builder()
+			.method(Method::OPTIONS)
+			.uri("/lighting.Controller/SetState")
+			.header("access-control-request-method", "POST")
+			.body(String::new())
+			.unwrap();
+		let response = send(request).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(response.body(), "ok");
+
+		let request = Request::builder()
+			.method(Method::POST)
+			.uri("/lighting.Controller/SetState")
+			.body(String::new())
+			.unwrap();
+		assert_eq!(send(request).await.status(), StatusCode::UNAUTHORIZED);
+	}
+}
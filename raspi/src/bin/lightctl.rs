@@ -0,0 +1,643 @@
+//! Client for the grpc api of a running server, for scripts and cron jobs on other machines.
+
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use eyre::{bail, ContextCompat, Result, WrapErr};
+use futures::StreamExt;
+use robolab::{
+	cli::{parse_segment_id, status_error, DEFAULT_SERVER},
+	grpc::schema::{
+		controller_client::ControllerClient,
+		edit_segments_request::Edit,
+		target_render_stats::Target,
+		Config,
+		DeletePresetRequest,
+		DisplayStateEffect,
+		EditSegmentsRequest,
+		EffectModifiers,
+		InsertSegment,
+		LoadPresetRequest,
+		MergeSegments,
+		PowerState,
+		SavePresetRequest,
+		Segment,
+		SegmentId,
+		SetPowerRequest,
+		SetStateRequest,
+		SplitSegment,
+		ValidateLayoutRequest,
+	},
+};
+use serde::Serialize;
+use tonic::{
+	metadata::{Ascii, MetadataValue},
+	service::{interceptor::InterceptedService, Interceptor},
+	transport::Channel,
+	Request,
+};
+
+type Client = ControllerClient<InterceptedService<Channel, Credentials>>;
+
+#[derive(Parser)]
+#[command(version, about = "Controls the lights over the grpc api")]
+struct Args {
+	#[arg(long, env = "LIGHTCTL_SERVER", default_value = DEFAULT_SERVER, global = true)]
+	server: String,
+	/// Name recorded in the history of the server for changes
+	#[arg(
+		long,
+		env = "LIGHTCTL_ACTOR",
+		default_value = "lightctl",
+		global = true
+	)]
+	actor:  String,
+	/// Token from the auth file of the server
+	#[arg(long, env = "LIGHTCTL_TOKEN", global = true, hide_env_values = true)]
+	token:  Option<String>,
+
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Print the brightness, or set it if a value is given
+	Brightness { value: Option<f32> },
+	/// Print the power, or switch the lights without changing the brightness
+	Power {
+		state:   Option<PowerArg>,
+		/// Master fader from 0 to 1
+		#[arg(long)]
+		master:  Option<f32>,
+		/// Defaults to the fade time of the server
+		#[arg(long)]
+		fade_ms: Option<u32>,
+	},
+	/// List the effects, or print the schema and default config of one
+	Effects { id: Option<String> },
+	/// Manage presets
+	#[command(subcommand)]
+	Presets(PresetCommand),
+	/// List or edit the segments, groups, the state and presets keep pointing at the same LEDs
+	#[command(subcommand)]
+	Segments(SegmentCommand),
+	/// Run an effect on a group, replacing the effects that ran on it
+	SetEffect {
+		group:  String,
+		effect: String,
+		/// JSON object merged over the default config of the effect
+		#[arg(long)]
+		config: Option<String>,
+	},
+	/// Print the modifiers of the effect running on a group, or change the given ones
+	Modifiers {
+		group:   String,
+		#[command(flatten)]
+		changes: ModifierChanges,
+	},
+	/// Print the state as JSON lines whenever it changes
+	Watch,
+	/// Print how long the effects take to render
+	Stats,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum PowerArg {
+	On,
+	Standby,
+	/// Off right away without fading
+	Blackout,
+}
+
+impl From<PowerArg> for PowerState {
+	fn from(value: PowerArg) -> Self {
+		match value {
+			PowerArg::On => PowerState::On,
+			PowerArg::Standby => PowerState::Standby,
+			PowerArg::Blackout => PowerState::Blackout,
+		}
+	}
+}
+
+/// The modifiers to change, the others are kept.
+#[derive(ClapArgs)]
+struct ModifierChanges {
+	/// From 0 to 1
+	#[arg(long)]
+	opacity:    Option<f32>,
+	/// From 0 to 8, 0 freezes the effect
+	#[arg(long)]
+	speed:      Option<f32>,
+	/// Hue rotation in degrees
+	#[arg(long, allow_hyphen_values = true)]
+	hue:        Option<f32>,
+	/// From 0 to 2
+	#[arg(long)]
+	saturation: Option<f32>,
+	#[arg(long)]
+	mirror:     Option<bool>,
+	#[arg(long)]
+	reverse:    Option<bool>,
+	/// Tile the effect this many times over every segment
+	#[arg(long)]
+	repeat:     Option<u32>,
+	/// Rotate the effect by this many LEDs
+	#[arg(long, allow_hyphen_values = true)]
+	offset:     Option<i32>,
+	/// Turn off every n-th LED, 0 uses every LED
+	#[arg(long)]
+	skip:       Option<u32>,
+	/// First LED of every segment the effect runs on
+	#[arg(long)]
+	start:      Option<u32>,
+	/// Last LED (exclusive) of every segment the effect runs on, 0 for the end of the segment
+	#[arg(long)]
+	end:        Option<u32>,
+}
+
+impl ModifierChanges {
+	fn is_empty(&self) -> bool {
+		self.opacity.is_none()
+			&& self.speed.is_none()
+			&& self.hue.is_none()
+			&& self.saturation.is_none()
+			&& self.mirror.is_none()
+			&& self.reverse.is_none()
+			&& self.repeat.is_none()
+			&& self.offset.is_none()
+			&& self.skip.is_none()
+			&& self.start.is_none()
+			&& self.end.is_none()
+	}
+
+	fn apply(&self, modifiers: &mut EffectModifiers) {
+		modifiers.opacity = self.opacity.unwrap_or(modifiers.opacity);
+		modifiers.speed = self.speed.unwrap_or(modifiers.speed);
+		modifiers.hue = self.hue.unwrap_or(modifiers.hue);
+		modifiers.saturation = self.saturation.unwrap_or(modifiers.saturation);
+		modifiers.mirror = self.mirror.unwrap_or(modifiers.mirror);
+		modifiers.reverse = self.reverse.unwrap_or(modifiers.reverse);
+		modifiers.repeat = self.repeat.unwrap_or(modifiers.repeat);
+		modifiers.offset = self.offset.unwrap_or(modifiers.offset);
+		modifiers.skip = self.skip.unwrap_or(modifiers.skip);
+		modifiers.start = self.start.unwrap_or(modifiers.start);
+		if let Some(end) = self.end {
+			modifiers.end = (end != 0).then_some(end);
+		}
+	}
+}
+
+/// Segments are given as `<strip>:<segment>`, e.g. `0:1`.
+#[derive(Subcommand)]
+enum SegmentCommand {
+	List,
+	/// Check the segments, groups and what the state targets for problems
+	Check,
+	/// Insert a segment before another one, or after the last one of the strip
+	Insert {
+		#[arg(value_parser = parse_segment_id)]
+		before:   SegmentId,
+		name:     String,
+		length:   u32,
+		#[arg(long)]
+		reversed: bool,
+	},
+	/// Split a segment after `length` LEDs, the rest becomes a new segment called `name`
+	Split {
+		#[arg(value_parser = parse_segment_id)]
+		segment: SegmentId,
+		length:  u32,
+		name:    String,
+	},
+	/// Merge a segment with the one after it
+	Merge {
+		#[arg(value_parser = parse_segment_id)]
+		segment: SegmentId,
+	},
+}
+
+#[derive(Subcommand)]
+enum PresetCommand {
+	List,
+	/// Replace the current state with the preset
+	Load {
+		name: String,
+	},
+	/// Save the current state as preset
+	Save {
+		name: String,
+	},
+	Delete {
+		name: String,
+	},
+}
+
+/// Sets the token and the `x-actor` header, so the changes are attributed in the history.
+#[derive(Clone)]
+struct Credentials {
+	actor:         MetadataValue<Ascii>,
+	authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for Credentials {
+	fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, tonic::Status> {
+		let metadata = request.metadata_mut();
+		metadata.insert("x-actor", self.actor.clone());
+		if let Some(authorization) = &self.authorization {
+			metadata.insert("authorization", authorization.clone());
+		}
+		Ok(request)
+	}
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+	println!("{}", serde_json::to_string_pretty(value)?);
+	Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	color_eyre::install()?;
+
+	let args = Args::parse();
+
+	let credentials = Credentials {
+		actor:         args.actor.parse().wrap_err("invalid actor")?,
+		authorization: args
+			.token
+			.map(|token| format!("Bearer {}", token).parse())
+			.transpose()
+			.wrap_err("invalid token")?,
+	};
+	let channel = Channel::from_shared(args.server.clone())?
+		.connect()
+		.await
+		.wrap_err_with(|| format!("connecting to {}", args.server))?;
+	let mut client = ControllerClient::with_interceptor(channel, credentials);
+
+	match args.command {
+		Command::Brightness { value } => brightness(&mut client, value).await,
+		Command::Power {
+			state,
+			master,
+			fade_ms,
+		} => power(&mut client, state, master, fade_ms).await,
+		Command::Effects { id } => effects(&mut client, id).await,
+		Command::Presets(command) => presets(&mut client, command).await,
+		Command::Segments(command) => segments(&mut client, command).await,
+		Command::SetEffect {
+			group,
+			effect,
+			config,
+		} => set_effect(&mut client, group, effect, config).await,
+		Command::Modifiers { group, changes } => modifiers(&mut client, group, changes).await,
+		Command::Watch => watch(&mut client).await,
+		Command::Stats => stats(&mut client).await,
+	}
+}
+
+async fn brightness(client: &mut Client, value: Option<f32>) -> Result<()> {
+	let config = client
+		.get_config(())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	let Some(brightness) = value else {
+		println!("{}", config.brightness);
+		return Ok(());
+	};
+
+	client
+		.set_config(Config {
+			brightness,
+			..config
+		})
+		.await
+		.map_err(status_error)?;
+
+	Ok(())
+}
+
+async fn power(
+	client: &mut Client,
+	state: Option<PowerArg>,
+	master: Option<f32>,
+	fade_ms: Option<u32>,
+) -> Result<()> {
+	let power = if state.is_none() && master.is_none() {
+		client.get_power(()).await
+	} else {
+		client
+			.set_power(SetPowerRequest {
+				state: state.map(|state| PowerState::from(state).into()),
+				master,
+				fade_ms,
+			})
+			.await
+	}
+	.map_err(status_error)?
+	.into_inner();
+
+	let state = match power.state() {
+		PowerState::On => "on",
+		PowerState::Standby => "standby",
+		PowerState::Blackout => "blackout",
+	};
+	println!("{}, master at {:.2}", state, power.master);
+
+	Ok(())
+}
+
+async fn effects(client: &mut Client, id: Option<String>) -> Result<()> {
+	let effects = client
+		.list_effects(())
+		.await
+		.map_err(status_error)?
+		.into_inner()
+		.effects;
+
+	match id {
+		Some(id) => {
+			let effect = effects
+				.get(&id)
+				.wrap_err_with(|| format!("unknown effect {}", id))?;
+			print_json(effect)
+		}
+		None => {
+			let mut effects = effects.into_values().collect::<Vec<_>>();
+			effects.sort_by(|a, b| a.id.cmp(&b.id));
+			for effect in effects {
+				println!("{}\t{}", effect.id, effect.name);
+			}
+			Ok(())
+		}
+	}
+}
+
+async fn presets(client: &mut Client, command: PresetCommand) -> Result<()> {
+	match command {
+		PresetCommand::List => {
+			let presets = client
+				.list_presets(())
+				.await
+				.map_err(status_error)?
+				.into_inner()
+				.presets;
+
+			let mut names = presets.keys().collect::<Vec<_>>();
+			names.sort();
+			for name in names {
+				println!("{}", name);
+			}
+		}
+		PresetCommand::Load { name } => {
+			client
+				.load_preset(LoadPresetRequest { name })
+				.await
+				.map_err(status_error)?;
+		}
+		PresetCommand::Save { name } => {
+			client
+				.save_preset(SavePresetRequest { name })
+				.await
+				.map_err(status_error)?;
+		}
+		PresetCommand::Delete { name } => {
+			client
+				.delete_preset(DeletePresetRequest { name })
+				.await
+				.map_err(status_error)?;
+		}
+	}
+
+	Ok(())
+}
+
+async fn segments(client: &mut Client, command: SegmentCommand) -> Result<()> {
+	let edit = match command {
+		SegmentCommand::List => None,
+		SegmentCommand::Check => return check_layout(client).await,
+		SegmentCommand::Insert {
+			before,
+			name,
+			length,
+			reversed,
+		} => Some(Edit::Insert(InsertSegment {
+			segment_id: Some(before),
+			segment:    Some(Segment {
+				name,
+				length,
+				reversed,
+			}),
+		})),
+		SegmentCommand::Split {
+			segment,
+			length,
+			name,
+		} => Some(Edit::Split(SplitSegment {
+			segment_id: Some(segment),
+			length,
+			name,
+		})),
+		SegmentCommand::Merge { segment } => Some(Edit::Merge(MergeSegments {
+			segment_id: Some(segment),
+		})),
+	};
+
+	let strips = match edit {
+		None => client.list_segments(()).await,
+		Some(edit) => {
+			client
+				.edit_segments(EditSegmentsRequest { edit: Some(edit) })
+				.await
+		}
+	}
+	.map_err(status_error)?
+	.into_inner()
+	.strips;
+
+	for (strip_idx, strip) in strips.iter().enumerate() {
+		let mut led_idx = strip.offset;
+		for (segment_idx, segment) in strip.segments.iter().enumerate() {
+			println!(
+				"{}:{}\t{}\t{}-{}{}",
+				strip_idx,
+				segment_idx,
+				segment.name,
+				led_idx,
+				led_idx + segment.length,
+				if segment.reversed { "\treversed" } else { "" }
+			);
+			led_idx += segment.length;
+		}
+	}
+
+	Ok(())
+}
+
+async fn check_layout(client: &mut Client) -> Result<()> {
+	let report = client
+		.validate_layout(ValidateLayoutRequest::default())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	for problem in report.errors.iter() {
+		println!("error: {}: {}", problem.field, problem.description);
+	}
+	for problem in report.warnings.iter() {
+		println!("warning: {}: {}", problem.field, problem.description);
+	}
+
+	if !report.errors.is_empty() {
+		bail!("found {} errors", report.errors.len());
+	}
+	if report.warnings.is_empty() {
+		println!("no problems found");
+	}
+
+	Ok(())
+}
+
+async fn set_effect(
+	client: &mut Client,
+	group: String,
+	effect_id: String,
+	config: Option<String>,
+) -> Result<()> {
+	let effects = client
+		.list_effects(())
+		.await
+		.map_err(status_error)?
+		.into_inner()
+		.effects;
+	let effect = effects
+		.get(&effect_id)
+		.wrap_err_with(|| format!("unknown effect {}", effect_id))?;
+
+	let mut effect_config = serde_json::to_value(&effect.default_config)?;
+	if let Some(config) = config {
+		let serde_json::Value::Object(fields) =
+			serde_json::from_str(&config).wrap_err("parsing --config")?
+		else {
+			bail!("--config has to be a JSON object");
+		};
+		let serde_json::Value::Object(defaults) = &mut effect_config else {
+			bail!("default config of {} is not an object", effect_id);
+		};
+		defaults.extend(fields);
+	}
+
+	let mut state = client
+		.get_state(())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	// the group only runs the new effect, effects without any target left are dropped
+	for state_effect in state.effects.iter_mut() {
+		state_effect.group_ids.retain(|id| *id != group);
+	}
+	state
+		.effects
+		.retain(|effect| !effect.group_ids.is_empty() || !effect.segment_ids.is_empty());
+
+	state.effects.push(DisplayStateEffect {
+		effect_id,
+		config: Some(serde_json::from_value(effect_config)?),
+		segment_ids: vec![],
+		group_ids: vec![group],
+		modifiers: None,
+	});
+
+	client
+		.set_state(SetStateRequest { state: Some(state) })
+		.await
+		.map_err(status_error)?;
+
+	Ok(())
+}
+
+async fn modifiers(client: &mut Client, group: String, changes: ModifierChanges) -> Result<()> {
+	let mut state = client
+		.get_state(())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	let effect = state
+		.effects
+		.iter_mut()
+		.find(|effect| effect.group_ids.contains(&group))
+		.wrap_err_with(|| format!("no effect is running on {}", group))?;
+	// not set by older servers
+	let modifiers = effect.modifiers.get_or_insert(EffectModifiers {
+		opacity: 1.0,
+		speed: 1.0,
+		saturation: 1.0,
+		repeat: 1,
+		..Default::default()
+	});
+
+	if changes.is_empty() {
+		return print_json(modifiers);
+	}
+	changes.apply(modifiers);
+
+	client
+		.set_state(SetStateRequest { state: Some(state) })
+		.await
+		.map_err(status_error)?;
+
+	Ok(())
+}
+
+async fn watch(client: &mut Client) -> Result<()> {
+	let mut states = client
+		.stream_state(())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	while let Some(state) = states.next().await {
+		let state = state.map_err(status_error)?;
+		println!("{}", serde_json::to_string(&state)?);
+	}
+
+	Ok(())
+}
+
+async fn stats(client: &mut Client) -> Result<()> {
+	let stats = client
+		.get_render_stats(())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	print!(
+		"{:.2}ms per frame, {:.1} fps",
+		stats.frame_time_ms, stats.fps
+	);
+	match stats.budget_ms {
+		Some(budget) => println!(", budget {:.2}ms per effect", budget),
+		None => println!(),
+	}
+
+	for target in stats.targets {
+		let name = match target.target {
+			Some(Target::SegmentId(id)) => format!("{}:{}", id.strip, id.segment),
+			Some(Target::GroupId(id)) => id,
+			None => "?".to_string(),
+		};
+
+		println!(
+			"{}\t{}\tavg {:.2}ms\tmax {:.2}ms\tevery {} frames, {} skipped",
+			name, target.effect_id, target.avg_ms, target.max_ms, target.interval, target.skipped
+		);
+		if let Some(error) = target.error {
+			println!("\tfailed: {}", error);
+		}
+	}
+
+	Ok(())
+}
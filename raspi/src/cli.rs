@@ -0,0 +1,245 @@
+//! Subcommands of the server binary that run without starting the servers, `export` and `import`
+//! talk to a running server over grpc.
+
+use std::{fs, path::Path, sync::Arc};
+
+use eyre::{bail, eyre, ContextCompat, Result, WrapErr};
+use tonic::{
+	metadata::{Ascii, MetadataValue},
+	service::{interceptor::InterceptedService, Interceptor},
+	transport::Channel,
+	Status,
+};
+use tonic_types::StatusExt;
+
+use crate::{
+	all_internal_effects,
+	all_plugin_effects,
+	auth::{self, Role},
+	config::{
+		server::ServerConfig,
+		store,
+		Config,
+		ConfigStore,
+		DisplayState,
+		GlobalConfig,
+		Presets,
+		Scripts,
+	},
+	grpc::schema::{
+		controller_client::ControllerClient,
+		Bundle,
+		ImportBundleRequest,
+		ImportMode,
+		SegmentId,
+		SegmentRemap,
+		StripRemap,
+	},
+	runner::{check_installation, script_effects, EffectsMap},
+};
+
+pub const DEFAULT_SERVER: &str = "http://127.0.0.1:4445";
+
+/// Internal effects and the plugins in the config directory.
+pub fn load_effects(config: &ServerConfig) -> Result<EffectsMap> {
+	let mut effects = all_internal_effects()?;
+	effects.extend(all_plugin_effects(&config.config_dir.join("plugins"))?);
+	Ok(effects)
+}
+
+/// Opens the store without changing it and prints the JSON files sled would import when the
+/// server starts.
+fn open_store(config: &ServerConfig) -> Result<Arc<dyn ConfigStore>> {
+	let (store, imports) = store::open_read_only(config.store, &config.config_dir)?;
+	for name in imports {
+		// on stderr, so the output of the commands stays the same
+		eprintln!(
+			"{}.json would be imported into the config db when the server starts",
+			name
+		);
+	}
+
+	Ok(store)
+}
+
+/// Checks the stored configs the same way imports are checked, without changing them.
+pub fn validate_config(config: &ServerConfig) -> Result<()> {
+	let store = open_store(config)?;
+	let effects = load_effects(config)?;
+
+	let global = Config::<GlobalConfig>::peek(&*store)?;
+	let state = Config::<DisplayState>::peek(&*store)?;
+	let presets = Config::<Presets>::peek(&*store)?;
+	let scripts = Config::<Scripts>::peek(&*store)?;
+
+	let errors = check_installation(&effects, &global, &state, &presets.0, &scripts.0);
+	if errors.is_empty() {
+		println!("{} is valid", config.config_dir.display());
+		return Ok(());
+	}
+
+	for error in errors.iter() {
+		println!("{}: {}", error.field, error.description);
+	}
+	bail!("found {} problems", errors.len());
+}
+
+/// Prints the ids of all effects, including plugins and scripts.
+pub fn list_effects(config: &ServerConfig) -> Result<()> {
+	let store = open_store(config)?;
+	let scripts = Config::<Scripts>::peek(&*store)?;
+
+	let mut effects = load_effects(config)?;
+	effects.extend(script_effects(&scripts.0, &mut vec![]));
+
+	let mut ids = effects.keys().collect::<Vec<_>>();
+	ids.sort();
+	for id in ids {
+		println!("{}", id);
+	}
+
+	Ok(())
+}
+
+/// Prints an entry for the auth file with a random token.
+pub fn generate_token(name: &str, role: Role) {
+	println!("[[tokens]]");
+	println!("name  = {}", toml::Value::from(name));
+	println!("token = \"{}\"", auth::generate_token());
+	println!("role  = \"{}\"", role);
+}
+
+/// Parses `<strip>:<segment>`, e.g. `0:1`.
+pub fn parse_segment_id(value: &str) -> Result<SegmentId> {
+	let (strip, segment) = value
+		.split_once(':')
+		.wrap_err_with(|| format!("expected <strip>:<segment>, got {}", value))?;
+
+	Ok(SegmentId {
+		strip:   strip.parse()?,
+		segment: segment.parse()?,
+	})
+}
+
+fn parse_map(value: &str, request: &mut ImportBundleRequest) -> Result<()> {
+	let (from, to) = value
+		.split_once('=')
+		.wrap_err_with(|| format!("expected <from>=<to>, got {}", value))?;
+
+	if from.contains(':') {
+		request.segments.push(SegmentRemap {
+			from: Some(parse_segment_id(from)?),
+			to:   Some(parse_segment_id(to)?),
+		});
+	} else {
+		request.strips.push(StripRemap {
+			from: from.parse()?,
+			to:   to.parse()?,
+		});
+	}
+
+	Ok(())
+}
+
+/// Includes the field violations of validation errors in the message.
+pub fn status_error(status: Status) -> eyre::Report {
+	let mut message = status.message().to_string();
+
+	if let Some(bad_request) = status.get_details_bad_request() {
+		for violation in bad_request.field_violations {
+			message.push_str(&format!(
+				"\n  {}: {}",
+				violation.field, violation.description
+			));
+		}
+	}
+
+	eyre!(message)
+}
+
+/// Adds the token to every request.
+#[derive(Clone)]
+struct BearerToken(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for BearerToken {
+	fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+		if let Some(authorization) = &self.0 {
+			request
+				.metadata_mut()
+				.insert("authorization", authorization.clone());
+		}
+		Ok(request)
+	}
+}
+
+async fn connect(
+	server: String,
+	token: Option<String>,
+) -> Result<ControllerClient<InterceptedService<Channel, BearerToken>>> {
+	let authorization = token
+		.map(|token| format!("Bearer {}", token).parse())
+		.transpose()
+		.wrap_err("invalid token")?;
+	let channel = Channel::from_shared(server.clone())?
+		.connect()
+		.await
+		.wrap_err_with(|| format!("connecting to {}", server))?;
+
+	Ok(ControllerClient::with_interceptor(
+		channel,
+		BearerToken(authorization),
+	))
+}
+
+pub async fn export_bundle(server: String, token: Option<String>, path: &Path) -> Result<()> {
+	let mut client = connect(server, token).await?;
+
+	let bundle = client
+		.export_bundle(())
+		.await
+		.map_err(status_error)?
+		.into_inner();
+
+	fs::write(path, bundle.json).wrap_err_with(|| format!("writing {}", path.display()))?;
+	println!("exported to {}", path.display());
+
+	Ok(())
+}
+
+/// `maps` are strips (`0=2`) or segments (`0:1=2:0`) to remap.
+pub async fn import_bundle(
+	server: String,
+	token: Option<String>,
+	path: &Path,
+	replace: bool,
+	dry_run: bool,
+	maps: &[String],
+) -> Result<()> {
+	let json = fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+
+	let mut request = ImportBundleRequest {
+		bundle: Some(Bundle { json }),
+		mode: if replace {
+			ImportMode::Replace
+		} else {
+			ImportMode::Merge
+		}
+		.into(),
+		dry_run,
+		..Default::default()
+	};
+	for map in maps {
+		parse_map(map, &mut request)?;
+	}
+
+	let mut client = connect(server, token).await?;
+	client.import_bundle(request).await.map_err(status_error)?;
+
+	if dry_run {
+		println!("{} is valid", path.display());
+	} else {
+		println!("imported {}", path.display());
+	}
+
+	Ok(())
+}
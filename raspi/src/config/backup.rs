@@ -0,0 +1,159 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+use serde_json::Value;
+
+use crate::config::store::ConfigStore;
+
+/// Number of backups kept per config, older ones get deleted.
+const MAX_BACKUPS: usize = 10;
+
+/// Minimum time between two backups of the same config, so frequent saves (e.g. dragging a
+/// slider) don't push out all older backups.
+const BACKUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// A copy of a config from an earlier save, the id is `<config>-<unix ms>`.
+#[derive(Clone, Debug)]
+pub struct Backup {
+	/// Name of the config, e.g. `state`.
+	pub config:  String,
+	pub id:      String,
+	pub created: SystemTime,
+	/// Size of the serialized data in bytes.
+	pub size:    u64,
+}
+
+pub(crate) fn backup_id(name: &str, created: SystemTime) -> Result<String> {
+	let millis = created.duration_since(UNIX_EPOCH)?.as_millis();
+	Ok(format!("{}-{}", name, millis))
+}
+
+/// Returns the creation time if the id belongs to a backup of the config.
+pub(crate) fn parse_backup_id(name: &str, id: &str) -> Option<SystemTime> {
+	let millis = id
+		.strip_prefix(name)?
+		.strip_prefix('-')?
+		.parse::<u64>()
+		.ok()?;
+
+	Some(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+/// Stores the data as a new backup if the last one is old enough.
+pub fn create_if_due(store: &dyn ConfigStore, name: &str, data: &Value) -> Result<()> {
+	let due = store.backups(name)?.first().map_or(true, |latest| {
+		latest
+			.created
+			.elapsed()
+			.map_or(true, |elapsed| elapsed >= BACKUP_INTERVAL)
+	});
+	if !due {
+		return Ok(());
+	}
+
+	create(store, name, data)
+}
+
+/// Stores the data as a new backup and deletes the oldest backups.
+pub fn create(store: &dyn ConfigStore, name: &str, data: &Value) -> Result<()> {
+	store.create_backup(name, data)?;
+
+	for backup in store.backups(name)?.iter().skip(MAX_BACKUPS) {
+		store.delete_backup(name, &backup.id)?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{fs, path::Path};
+
+	use serde_json::json;
+
+	use super::*;
+	use crate::config::store::{test_dir, FileStore};
+
+	fn ids(store: &dyn ConfigStore) -> Vec<String> {
+		store
+			.backups("state")
+			.unwrap()
+			.into_iter()
+			.map(|backup| backup.id)
+			.collect()
+	}
+
+	/// Writes a backup with the given id, `create_backup` always uses the current time.
+	fn write_backup(dir: &Path, id: &str) {
+		fs::create_dir_all(dir.join("backups")).unwrap();
+		fs::write(dir.join("backups").join(format!("{}.json", id)), "{}").unwrap();
+	}
+
+	#[test]
+	fn ids_round_trip() {
+		let created = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+		let id = backup_id("state", created).unwrap();
+		assert_eq!(id, "state-1700000000123");
+		assert_eq!(parse_backup_id("state", &id), Some(created));
+
+		for (name, id) in [
+			("presets", "state-1700000000123"),
+			("state", "state"),
+			("state", "state-"),
+			("state", "state1700000000123"),
+			("state", "state-abc"),
+			("state", "state--1"),
+			("state", "state-1/../../config"),
+		] {
+			assert_eq!(parse_backup_id(name, id), None, "{} {}", name, id);
+		}
+	}
+
+	#[test]
+	fn prunes_oldest() {
+		let dir = test_dir("backup-prune");
+		let store = FileStore::new(&dir);
+
+		for i in 1..=MAX_BACKUPS {
+			write_backup(&dir, &format!("state-{}", i * 1000));
+		}
+		// backups of other configs aren't counted
+		write_backup(&dir, "presets-500");
+
+		create(&store, "state", &json!({})).unwrap();
+
+		let ids = ids(&store);
+		assert_eq!(ids.len(), MAX_BACKUPS);
+		assert!(!ids.contains(&"state-1000".to_string()));
+		assert!(ids.contains(&"state-2000".to_string()));
+		assert_eq!(store.backups("presets").unwrap().len(), 1);
+
+		fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn creates_if_due() {
+		let dir = test_dir("backup-due");
+		let store = FileStore::new(&dir);
+
+		// the first save is always backed up
+		create_if_due(&store, "state", &json!({ "a": 1 })).unwrap();
+		assert_eq!(ids(&store).len(), 1);
+
+		// the latest backup is too recent
+		create_if_due(&store, "state", &json!({ "a": 2 })).unwrap();
+		assert_eq!(ids(&store).len(), 1);
+
+		fs::remove_dir_all(dir.join("backups")).unwrap();
+		write_backup(&dir, "state-1000");
+		create_if_due(&store, "state", &json!({ "a": 3 })).unwrap();
+		let ids = ids(&store);
+		assert_eq!(ids.len(), 2);
+		assert_eq!(
+			store.read_backup("state", &ids[0]).unwrap(),
+			json!({ "a": 3 })
+		);
+
+		fs::remove_dir_all(dir).ok();
+	}
+}
@@ -0,0 +1,214 @@
+//! Everything that makes up an installation in a single versioned JSON document, to set up spaces
+//! with similar hardware.
+
+use std::collections::HashMap;
+
+use eyre::{bail, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DisplayState, GlobalConfig, Script, SegmentId};
+
+/// Version of the bundle format, older bundles are accepted, newer ones rejected.
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bundle {
+	pub version: u32,
+	pub config:  GlobalConfig,
+	pub state:   DisplayState,
+	#[serde(default)]
+	pub presets: HashMap<String, DisplayState>,
+	#[serde(default)]
+	pub scripts: HashMap<String, Script>,
+}
+
+impl Bundle {
+	pub fn from_json(data: &[u8]) -> Result<Self> {
+		#[derive(Deserialize)]
+		struct Header {
+			version: u32,
+		}
+
+		let Header { version } =
+			serde_json::from_slice(data).wrap_err("reading the bundle version")?;
+		if version > BUNDLE_VERSION {
+			bail!(
+				"bundle has version {}, but only {} is supported",
+				version,
+				BUNDLE_VERSION
+			);
+		}
+
+		serde_json::from_slice(data).wrap_err("deserializing the bundle")
+	}
+
+	pub fn to_json(&self) -> Result<Vec<u8>> {
+		Ok(serde_json::to_vec_pretty(self)?)
+	}
+
+	/// Replaces the segment ids referenced by groups, the state and presets, the strips themselves
+	/// are left as they are.
+	pub fn remap(&mut self, map: &SegmentMap) {
+		if map.is_empty() {
+			return;
+		}
+
+		for group in self.config.groups.iter_mut() {
+			group.segment_ids = group.segment_ids.drain().map(|id| map.map(id)).collect();
+		}
+
+		for state in std::iter::once(&mut self.state).chain(self.presets.values_mut()) {
+			for effect in state.effects.iter_mut() {
+				effect.segment_ids = effect.segment_ids.drain().map(|id| map.map(id)).collect();
+			}
+		}
+	}
+}
+
+/// Maps segment ids of the exported installation to the one it is imported into, segments take
+/// precedence over whole strips.
+#[derive(Clone, Debug, Default)]
+pub struct SegmentMap {
+	pub strips:   HashMap<usize, usize>,
+	pub segments: HashMap<SegmentId, SegmentId>,
+}
+
+impl SegmentMap {
+	pub fn is_empty(&self) -> bool {
+		self.strips.is_empty() && self.segments.is_empty()
+	}
+
+	pub fn map(&self, id: SegmentId) -> SegmentId {
+		if let Some(mapped) = self.segments.get(&id) {
+			return *mapped;
+		}
+
+		SegmentId {
+			strip_idx:   self
+				.strips
+				.get(&id.strip_idx)
+				.copied()
+				.unwrap_or(id.strip_idx),
+			segment_idx: id.segment_idx,
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImportMode {
+	/// Keeps the strips and global settings, groups, presets and scripts from the bundle are added
+	/// or replace the ones with the same id.
+	#[default]
+	Merge,
+	/// Replaces everything with the content of the bundle.
+	Replace,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ImportOptions {
+	pub mode:    ImportMode,
+	pub remap:   SegmentMap,
+	/// Only validate the bundle without applying it.
+	pub dry_run: bool,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use super::*;
+	use crate::config::{DisplayStateEffect, Group};
+
+	fn id(strip_idx: usize, segment_idx: usize) -> SegmentId {
+		SegmentId {
+			strip_idx,
+			segment_idx,
+		}
+	}
+
+	fn ids(ids: &[SegmentId]) -> HashSet<SegmentId> {
+		ids.iter().copied().collect()
+	}
+
+	fn state(segment_ids: &[SegmentId]) -> DisplayState {
+		DisplayState {
+			effects: vec![DisplayStateEffect {
+				segment_ids: ids(segment_ids),
+				..Default::default()
+			}],
+		}
+	}
+
+	fn bundle() -> Bundle {
+		Bundle {
+			version: BUNDLE_VERSION,
+			config:  GlobalConfig {
+				groups: vec![Group {
+					id: "group".to_string(),
+					segment_ids: ids(&[id(0, 0), id(0, 1), id(1, 0)]),
+					..Default::default()
+				}],
+				..Default::default()
+			},
+			state:   state(&[id(0, 1), id(2, 0)]),
+			presets: HashMap::from([("preset".to_string(), state(&[id(1, 0)]))]),
+			scripts: HashMap::new(),
+		}
+	}
+
+	/// Strip 0 moves to 1 and strip 1 to 0, but segment 0/1 goes to 3/0.
+	fn segment_map() -> SegmentMap {
+		SegmentMap {
+			strips:   HashMap::from([(0, 1), (1, 0)]),
+			segments: HashMap::from([(id(0, 1), id(3, 0))]),
+		}
+	}
+
+	#[test]
+	fn reads_versions() {
+		let data = bundle().to_json().unwrap();
+		assert_eq!(Bundle::from_json(&data).unwrap().version, BUNDLE_VERSION);
+
+		let mut value = serde_json::from_slice::<serde_json::Value>(&data).unwrap();
+		value["version"] = (BUNDLE_VERSION + 1).into();
+		let err = Bundle::from_json(&serde_json::to_vec(&value).unwrap()).unwrap_err();
+		assert!(err.to_string().contains("version"), "{:?}", err);
+
+		assert!(Bundle::from_json(br#"{ "config": {} }"#).is_err());
+	}
+
+	#[test]
+	fn segments_take_precedence() {
+		let map = segment_map();
+		assert_eq!(map.map(id(0, 0)), id(1, 0));
+		assert_eq!(map.map(id(0, 1)), id(3, 0));
+		assert_eq!(map.map(id(0, 2)), id(1, 2));
+		assert_eq!(map.map(id(1, 1)), id(0, 1));
+		// unmapped strips stay where they are
+		assert_eq!(map.map(id(2, 0)), id(2, 0));
+	}
+
+	#[test]
+	fn remaps_references() {
+		let mut bundle = bundle();
+		bundle.remap(&segment_map());
+
+		assert_eq!(
+			bundle.config.groups[0].segment_ids,
+			ids(&[id(1, 0), id(3, 0), id(0, 0)])
+		);
+		assert_eq!(
+			bundle.state.effects[0].segment_ids,
+			ids(&[id(3, 0), id(2, 0)])
+		);
+		assert_eq!(
+			bundle.presets["preset"].effects[0].segment_ids,
+			ids(&[id(0, 0)])
+		);
+
+		// an empty map changes nothing
+		let mut unchanged = self::bundle();
+		unchanged.remap(&SegmentMap::default());
+		assert_eq!(unchanged.state, self::bundle().state);
+	}
+}
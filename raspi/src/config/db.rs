@@ -7,7 +7,7 @@ use tracing::{debug, error};
 pub const CONFIG_KEY: &str = "config";
 
 pub fn save_json<T: Serialize + Debug, K: AsRef<[u8]>>(
-	db: &mut sled::Tree,
+	db: &sled::Tree,
 	key: K,
 	value: &T,
 ) -> Result<()> {
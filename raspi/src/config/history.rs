@@ -0,0 +1,221 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigFile, DisplayState, Group, Strip};
+
+/// Number of changes that can be undone.
+const MAX_ENTRIES: usize = 50;
+
+/// Everything a tracked change can modify.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+	pub state:  DisplayState,
+	pub strips: Vec<Strip>,
+	pub groups: Vec<Group>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+	pub id:          u64,
+	pub time:        SystemTime,
+	/// Who made the change, taken from the request.
+	pub actor:       String,
+	pub description: String,
+	pub before:      Snapshot,
+	pub after:       Snapshot,
+}
+
+impl HistoryEntry {
+	/// Whether the segments or groups were changed, not only the state.
+	pub fn changes_layout(&self) -> bool {
+		self.before.strips != self.after.strips || self.before.groups != self.after.groups
+	}
+}
+
+/// Changes to the state, segments and groups, oldest first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct History {
+	entries:  VecDeque<HistoryEntry>,
+	/// Number of entries that are applied, the ones after it were undone and can be redone.
+	position: usize,
+	next_id:  u64,
+}
+
+impl ConfigFile for History {
+	const NAME: &'static str = "history";
+	// big and saved on every change, backups of it would mostly be copies of the same snapshots
+	const BACKUP: bool = false;
+}
+
+impl History {
+	pub fn entries(&self) -> &VecDeque<HistoryEntry> {
+		&self.entries
+	}
+
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	/// Records a change, discarding the changes that were undone before it.
+	pub fn push(&mut self, actor: &str, description: &str, before: Snapshot, after: Snapshot) {
+		self.entries.truncate(self.position);
+
+		self.entries.push_back(HistoryEntry {
+			id: self.next_id,
+			time: SystemTime::now(),
+			actor: actor.to_string(),
+			description: description.to_string(),
+			before,
+			after,
+		});
+		self.next_id += 1;
+
+		if self.entries.len() > MAX_ENTRIES {
+			self.entries.pop_front();
+		}
+		self.position = self.entries.len();
+	}
+
+	/// The entry [`History::undo`] would revert.
+	pub fn next_undo(&self) -> Option<&HistoryEntry> {
+		self.entries.get(self.position.checked_sub(1)?)
+	}
+
+	/// The entry [`History::redo`] would reapply.
+	pub fn next_redo(&self) -> Option<&HistoryEntry> {
+		self.entries.get(self.position)
+	}
+
+	/// Steps back, returns the entry whose `before` should be applied.
+	pub fn undo(&mut self) -> Option<&HistoryEntry> {
+		self.position = self.position.checked_sub(1)?;
+		self.entries.get(self.position)
+	}
+
+	/// Steps forward, returns the entry whose `after` should be applied.
+	pub fn redo(&mut self) -> Option<&HistoryEntry> {
+		let entry = self.entries.get(self.position)?;
+		self.position += 1;
+		Some(entry)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A history with changes "0" to "n - 1", each one adding a group.
+	fn history(n: usize) -> History {
+		let mut history = History::default();
+		for i in 0..n {
+			push(&mut history, i);
+		}
+		history
+	}
+
+	fn push(history: &mut History, i: usize) {
+		history.push("test", &i.to_string(), snapshot(i), snapshot(i + 1));
+	}
+
+	fn snapshot(groups: usize) -> Snapshot {
+		Snapshot {
+			groups: (0..groups)
+				.map(|i| Group {
+					id: i.to_string(),
+					..Default::default()
+				})
+				.collect(),
+			..Default::default()
+		}
+	}
+
+	fn descriptions(history: &History) -> Vec<&str> {
+		history
+			.entries()
+			.iter()
+			.map(|entry| entry.description.as_str())
+			.collect()
+	}
+
+	#[test]
+	fn undo_redo() {
+		let mut history = history(3);
+		assert_eq!(history.position(), 3);
+
+		assert_eq!(history.next_undo().unwrap().description, "2");
+		assert_eq!(history.undo().unwrap().before, snapshot(2));
+		assert_eq!(history.undo().unwrap().before, snapshot(1));
+		assert_eq!(history.position(), 1);
+
+		assert_eq!(history.next_redo().unwrap().description, "1");
+		assert_eq!(history.redo().unwrap().after, snapshot(2));
+		assert_eq!(history.position(), 2);
+		assert_eq!(descriptions(&history), ["0", "1", "2"]);
+	}
+
+	#[test]
+	fn undo_redo_at_the_ends() {
+		let mut history = History::default();
+		assert!(history.next_undo().is_none());
+		assert!(history.undo().is_none());
+		assert!(history.next_redo().is_none());
+		assert!(history.redo().is_none());
+		assert_eq!(history.position(), 0);
+
+		let mut history = self::history(2);
+		assert!(history.next_redo().is_none());
+		assert!(history.redo().is_none());
+		assert_eq!(history.position(), 2);
+
+		history.undo();
+		history.undo();
+		assert!(history.next_undo().is_none());
+		assert!(history.undo().is_none());
+		assert_eq!(history.position(), 0);
+		assert_eq!(history.next_redo().unwrap().description, "0");
+	}
+
+	#[test]
+	fn push_discards_undone() {
+		let mut history = history(3);
+		history.undo();
+		history.undo();
+
+		push(&mut history, 10);
+		assert_eq!(descriptions(&history), ["0", "10"]);
+		assert_eq!(history.position(), 2);
+		assert!(history.next_redo().is_none());
+
+		// ids keep counting, the discarded ones aren't reused
+		let ids = history
+			.entries()
+			.iter()
+			.map(|entry| entry.id)
+			.collect::<Vec<_>>();
+		assert_eq!(ids, [0, 3]);
+	}
+
+	#[test]
+	fn overflow() {
+		let mut history = history(MAX_ENTRIES);
+		assert_eq!(history.entries().len(), MAX_ENTRIES);
+		assert_eq!(history.entries()[0].description, "0");
+
+		push(&mut history, MAX_ENTRIES);
+		assert_eq!(history.entries().len(), MAX_ENTRIES);
+		assert_eq!(history.position(), MAX_ENTRIES);
+		assert_eq!(history.entries()[0].description, "1");
+		assert_eq!(
+			history.next_undo().unwrap().description,
+			MAX_ENTRIES.to_string()
+		);
+
+		// everything that's left can be undone, the dropped change can't
+		for _ in 0..MAX_ENTRIES {
+			assert!(history.undo().is_some());
+		}
+		assert!(history.undo().is_none());
+		assert_eq!(history.next_redo().unwrap().before, snapshot(1));
+	}
+}
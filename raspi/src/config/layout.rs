@@ -0,0 +1,664 @@
+//! Checks of the strips and groups, and edits of the segments of a strip that move the segment
+//! ids referenced by groups, the state and presets along, so they keep pointing at the same LEDs.
+
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
+
+use eyre::Result;
+
+use crate::{
+	config::{DisplayState, GlobalConfig, Segment, SegmentId, Strip},
+	controller::{LEDS_PER_STRIP, STRIPS},
+	effects::validate::{FieldError, ValidationError},
+};
+
+/// Problems with the strips and groups of a config. Errors make the config get rejected, with
+/// warnings it works, but probably not as intended.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutReport {
+	pub errors:   Vec<FieldError>,
+	pub warnings: Vec<FieldError>,
+}
+
+impl LayoutReport {
+	/// The warnings, or the errors as a [`ValidationError`] if there are any.
+	pub fn into_result(self) -> Result<Vec<FieldError>> {
+		if !self.errors.is_empty() {
+			return Err(ValidationError(self.errors).into());
+		}
+
+		Ok(self.warnings)
+	}
+}
+
+/// Checks the strips and groups of `config`, and what the effects of `state` target with them.
+///
+/// Segments follow each other from the offset of their strip, so they can only overlap the end of
+/// the strip, not each other. Effects can still end up sharing LEDs, by targeting the same
+/// segment directly or through groups.
+pub fn check_layout(config: &GlobalConfig, state: &DisplayState) -> LayoutReport {
+	let mut report = LayoutReport::default();
+
+	check_strips(&config.strips, &mut report);
+	check_groups(config, &mut report);
+	check_targets(config, state, &mut report);
+
+	report
+}
+
+fn check_strips(strips: &[Strip], report: &mut LayoutReport) {
+	if strips.len() > STRIPS {
+		report.errors.push(FieldError::new(
+			"strips",
+			format!("at most {} strips are supported", STRIPS),
+		));
+	}
+
+	for (strip_idx, strip) in strips.iter().enumerate() {
+		if strip.segments.is_empty() {
+			report.warnings.push(FieldError::new(
+				format!("strips[{}].segments", strip_idx),
+				"is empty, none of the LEDs of the strip are used",
+			));
+			continue;
+		}
+
+		let mut led_idx = strip.offset;
+
+		for (idx, segment) in strip.segments.iter().enumerate() {
+			let path = format!("strips[{}].segments[{}]", strip_idx, idx);
+
+			if segment.length == 0 {
+				report.warnings.push(FieldError::new(
+					format!("{}.length", path),
+					"is 0, effects on the segment aren't shown",
+				));
+			}
+
+			let end = led_idx + segment.length;
+			if end > LEDS_PER_STRIP {
+				report.errors.push(FieldError::new(
+					path,
+					format!(
+						"goes over the max number of LEDs ({} > {}) starting from the offset {}",
+						end, LEDS_PER_STRIP, strip.offset
+					),
+				));
+				break;
+			}
+
+			led_idx = end;
+		}
+	}
+}
+
+fn check_groups(config: &GlobalConfig, report: &mut LayoutReport) {
+	let mut ids = HashMap::new();
+
+	for (i, group) in config.groups.iter().enumerate() {
+		match ids.entry(group.id.as_str()) {
+			Entry::Occupied(first) => report.errors.push(FieldError::new(
+				format!("groups[{}].id", i),
+				format!("{} is already used by groups[{}]", group.id, first.get()),
+			)),
+			Entry::Vacant(entry) => {
+				entry.insert(i);
+			}
+		}
+
+		if group.segment_ids.is_empty() {
+			report.warnings.push(FieldError::new(
+				format!("groups[{}].segment_ids", i),
+				"is empty, effects on the group aren't shown",
+			));
+		}
+
+		for segment_id in sorted(&group.segment_ids) {
+			if config.segment(segment_id).is_none() {
+				report.warnings.push(FieldError::new(
+					format!("groups[{}].segment_ids", i),
+					format!(
+						"segment {} doesn't exist and is ignored",
+						fmt_id(segment_id)
+					),
+				));
+			}
+		}
+	}
+}
+
+/// Reports targets the state drops, and segments that several effects draw on.
+fn check_targets(config: &GlobalConfig, state: &DisplayState, report: &mut LayoutReport) {
+	let mut targeted = Vec::with_capacity(state.effects.len());
+
+	for (i, effect) in state.effects.iter().enumerate() {
+		let mut segments = BTreeSet::new();
+
+		for segment_id in sorted(&effect.segment_ids) {
+			if config.segment(segment_id).is_none() {
+				report.warnings.push(FieldError::new(
+					format!("state.effects[{}].segment_ids", i),
+					format!(
+						"segment {} doesn't exist and gets removed",
+						fmt_id(segment_id)
+					),
+				));
+				continue;
+			}
+			segments.insert(segment_id);
+		}
+
+		let mut group_ids = effect.group_ids.iter().collect::<Vec<_>>();
+		group_ids.sort();
+		for group_id in group_ids {
+			let Some(group) = config.groups.iter().find(|group| group.id == *group_id) else {
+				report.warnings.push(FieldError::new(
+					format!("state.effects[{}].group_ids", i),
+					format!("group {} doesn't exist and gets removed", group_id),
+				));
+				continue;
+			};
+
+			segments.extend(
+				group
+					.segment_ids
+					.iter()
+					.filter(|segment_id| config.segment(**segment_id).is_some()),
+			);
+		}
+
+		targeted.push(segments);
+	}
+
+	for (j, segments) in targeted.iter().enumerate() {
+		for (i, earlier) in targeted[..j].iter().enumerate() {
+			let shared = earlier
+				.intersection(segments)
+				.map(|segment_id| fmt_id(*segment_id))
+				.collect::<Vec<_>>();
+
+			if !shared.is_empty() {
+				report.warnings.push(FieldError::new(
+					format!("state.effects[{}]", j),
+					format!(
+						"shares segments {} with effects[{}], they draw over each other",
+						shared.join(", "),
+						i
+					),
+				));
+			}
+		}
+	}
+}
+
+fn sorted(ids: &HashSet<SegmentId>) -> Vec<SegmentId> {
+	let mut ids = ids.iter().copied().collect::<Vec<_>>();
+	ids.sort();
+	ids
+}
+
+fn fmt_id(id: SegmentId) -> String {
+	format!("{}:{}", id.strip_idx, id.segment_idx)
+}
+
+#[derive(Clone, Debug)]
+pub enum SegmentEdit {
+	/// Inserts `segment` before the one at `id`, or at the end of the strip if `id` is one past
+	/// the last segment. The segments after it move further along the strip.
+	Insert {
+		id:      SegmentId,
+		segment: Segment,
+	},
+	/// Shortens the segment at `id` to `length` LEDs, the rest becomes a new segment called `name`
+	/// right after it. Everything targeting the segment targets both halves afterwards.
+	Split {
+		id:     SegmentId,
+		length: usize,
+		name:   String,
+	},
+	/// Merges the segment at `id` with the one after it, keeping the name of the first.
+	Merge { id: SegmentId },
+}
+
+fn invalid(field: &str, description: impl Into<String>) -> eyre::Report {
+	ValidationError(vec![FieldError::new(field, description)]).into()
+}
+
+impl SegmentEdit {
+	/// The segment the edit is about.
+	pub fn id(&self) -> SegmentId {
+		match self {
+			SegmentEdit::Insert { id, .. }
+			| SegmentEdit::Split { id, .. }
+			| SegmentEdit::Merge { id } => *id,
+		}
+	}
+
+	/// Applies the edit to `strips`, nothing is changed if it fails.
+	pub fn apply(&self, strips: &mut [Strip]) -> Result<()> {
+		let id = self.id();
+		let strip = strips.get_mut(id.strip_idx).ok_or_else(|| {
+			invalid(
+				"segment_id",
+				format!("strip {} doesn't exist", id.strip_idx),
+			)
+		})?;
+		let missing = || {
+			invalid(
+				"segment_id",
+				format!(
+					"segment {} of strip {} doesn't exist",
+					id.segment_idx, id.strip_idx
+				),
+			)
+		};
+
+		match self {
+			SegmentEdit::Insert { segment, .. } => {
+				if id.segment_idx > strip.segments.len() {
+					return Err(missing());
+				}
+
+				let end = strip.offset + strip.led_count() + segment.length;
+				if end > LEDS_PER_STRIP {
+					return Err(invalid(
+						"segment.length",
+						format!(
+							"the strip would go over the max number of LEDs ({} > {})",
+							end, LEDS_PER_STRIP
+						),
+					));
+				}
+
+				strip.segments.insert(id.segment_idx, segment.clone());
+			}
+			SegmentEdit::Split { length, name, .. } => {
+				let segment = strip.segments.get_mut(id.segment_idx).ok_or_else(missing)?;
+				if *length == 0 || *length >= segment.length {
+					return Err(invalid(
+						"length",
+						format!(
+							"has to be at least 1 and less than the {} LEDs of the segment",
+							segment.length
+						),
+					));
+				}
+
+				let rest = Segment {
+					name:     name.clone(),
+					length:   segment.length - length,
+					reversed: segment.reversed,
+				};
+				segment.length = *length;
+				strip.segments.insert(id.segment_idx + 1, rest);
+			}
+			SegmentEdit::Merge { .. } => {
+				if id.segment_idx >= strip.segments.len() {
+					return Err(missing());
+				}
+				let Some(next) = strip.segments.get(id.segment_idx + 1) else {
+					return Err(invalid("segment_id", "is the last segment of the strip"));
+				};
+				if next.reversed != strip.segments[id.segment_idx].reversed {
+					return Err(invalid(
+						"segment_id",
+						"can't merge segments running in different directions",
+					));
+				}
+
+				let next = strip.segments.remove(id.segment_idx + 1);
+				strip.segments[id.segment_idx].length += next.length;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The ids a segment has after the edit, split segments have two.
+	pub fn migrate(&self, segment_id: SegmentId) -> Vec<SegmentId> {
+		let id = self.id();
+		if segment_id.strip_idx != id.strip_idx {
+			return vec![segment_id];
+		}
+		let moved = |delta: isize| SegmentId {
+			strip_idx:   segment_id.strip_idx,
+			segment_idx: segment_id.segment_idx.saturating_add_signed(delta),
+		};
+
+		match self {
+			SegmentEdit::Insert { .. } if segment_id.segment_idx >= id.segment_idx => {
+				vec![moved(1)]
+			}
+			SegmentEdit::Split { .. } if segment_id.segment_idx == id.segment_idx => {
+				vec![segment_id, moved(1)]
+			}
+			SegmentEdit::Split { .. } if segment_id.segment_idx > id.segment_idx => vec![moved(1)],
+			SegmentEdit::Merge { .. } if segment_id.segment_idx > id.segment_idx => {
+				vec![moved(-1)]
+			}
+			_ => vec![segment_id],
+		}
+	}
+
+	/// Replaces the ids in `ids` with the ones after the edit.
+	pub fn migrate_ids(&self, ids: &mut HashSet<SegmentId>) {
+		*ids = ids.drain().flat_map(|id| self.migrate(id)).collect();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::{DisplayStateEffect, Group};
+
+	fn id(strip_idx: usize, segment_idx: usize) -> SegmentId {
+		SegmentId {
+			strip_idx,
+			segment_idx,
+		}
+	}
+
+	fn segment(name: &str, length: usize) -> Segment {
+		Segment {
+			name: name.to_string(),
+			length,
+			reversed: false,
+		}
+	}
+
+	fn strips() -> Vec<Strip> {
+		vec![
+			Strip {
+				offset:   0,
+				segments: vec![segment("a", 100), segment("b", 50), segment("c", 10)],
+			},
+			Strip {
+				offset:   10,
+				segments: vec![segment("d", 200)],
+			},
+		]
+	}
+
+	fn lengths(strip: &Strip) -> Vec<usize> {
+		strip
+			.segments
+			.iter()
+			.map(|segment| segment.length)
+			.collect()
+	}
+
+	fn fields(errors: &[FieldError]) -> Vec<&str> {
+		errors.iter().map(|error| error.field.as_str()).collect()
+	}
+
+	fn effect(segment_ids: &[SegmentId], group_ids: &[&str]) -> DisplayStateEffect {
+		DisplayStateEffect {
+			segment_ids: segment_ids.iter().copied().collect(),
+			group_ids: group_ids.iter().map(|id| id.to_string()).collect(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn insert() {
+		let mut strips = strips();
+		let edit = SegmentEdit::Insert {
+			id:      id(0, 1),
+			segment: segment("new", 20),
+		};
+
+		edit.apply(&mut strips).unwrap();
+		assert_eq!(lengths(&strips[0]), [100, 20, 50, 10]);
+		assert_eq!(strips[0].segments[1].name, "new");
+
+		assert_eq!(edit.migrate(id(0, 0)), [id(0, 0)]);
+		assert_eq!(edit.migrate(id(0, 1)), [id(0, 2)]);
+		assert_eq!(edit.migrate(id(0, 2)), [id(0, 3)]);
+		assert_eq!(edit.migrate(id(1, 1)), [id(1, 1)]);
+
+		// one past the last segment appends
+		let edit = SegmentEdit::Insert {
+			id:      id(1, 1),
+			segment: segment("end", 5),
+		};
+		edit.apply(&mut strips).unwrap();
+		assert_eq!(lengths(&strips[1]), [200, 5]);
+	}
+
+	#[test]
+	fn insert_fails() {
+		let mut strips = strips();
+
+		let edit = SegmentEdit::Insert {
+			id:      id(0, 4),
+			segment: segment("new", 1),
+		};
+		assert!(edit.apply(&mut strips).is_err());
+
+		let edit = SegmentEdit::Insert {
+			id:      id(2, 0),
+			segment: segment("new", 1),
+		};
+		assert!(edit.apply(&mut strips).is_err());
+
+		// over the end of the strip
+		let edit = SegmentEdit::Insert {
+			id:      id(1, 0),
+			segment: segment("new", LEDS_PER_STRIP - 210 + 1),
+		};
+		assert!(edit.apply(&mut strips).is_err());
+		assert_eq!(strips, self::strips());
+	}
+
+	#[test]
+	fn split() {
+		let mut strips = strips();
+		let edit = SegmentEdit::Split {
+			id:     id(0, 1),
+			length: 20,
+			name:   "rest".to_string(),
+		};
+
+		edit.apply(&mut strips).unwrap();
+		assert_eq!(lengths(&strips[0]), [100, 20, 30, 10]);
+		assert_eq!(strips[0].segments[2].name, "rest");
+
+		assert_eq!(edit.migrate(id(0, 0)), [id(0, 0)]);
+		assert_eq!(edit.migrate(id(0, 1)), [id(0, 1), id(0, 2)]);
+		assert_eq!(edit.migrate(id(0, 2)), [id(0, 3)]);
+		assert_eq!(edit.migrate(id(1, 0)), [id(1, 0)]);
+	}
+
+	#[test]
+	fn split_fails() {
+		let mut strips = strips();
+		strips[1].segments.push(segment("single", 1));
+		strips[1].segments.push(segment("empty", 0));
+		let before = strips.clone();
+
+		for (segment_id, length) in [
+			(id(0, 0), 0),
+			(id(0, 0), 100),
+			(id(0, 3), 1),
+			(id(1, 1), 1),
+			(id(1, 2), 1),
+		] {
+			let edit = SegmentEdit::Split {
+				id: segment_id,
+				length,
+				name: "rest".to_string(),
+			};
+			assert!(edit.apply(&mut strips).is_err(), "{:?}", edit);
+		}
+		assert_eq!(strips, before);
+	}
+
+	#[test]
+	fn merge() {
+		let mut strips = strips();
+		let edit = SegmentEdit::Merge { id: id(0, 0) };
+
+		edit.apply(&mut strips).unwrap();
+		assert_eq!(lengths(&strips[0]), [150, 10]);
+		assert_eq!(strips[0].segments[0].name, "a");
+
+		assert_eq!(edit.migrate(id(0, 0)), [id(0, 0)]);
+		assert_eq!(edit.migrate(id(0, 1)), [id(0, 0)]);
+		assert_eq!(edit.migrate(id(0, 2)), [id(0, 1)]);
+	}
+
+	#[test]
+	fn merge_fails() {
+		let mut strips = strips();
+		strips[0].segments[1].reversed = true;
+		let before = strips.clone();
+
+		for segment_id in [id(0, 0), id(0, 2), id(0, 3), id(1, 0)] {
+			let edit = SegmentEdit::Merge { id: segment_id };
+			assert!(edit.apply(&mut strips).is_err(), "{:?}", edit);
+		}
+		assert_eq!(strips, before);
+	}
+
+	#[test]
+	fn migrate_ids() {
+		let edit = SegmentEdit::Merge { id: id(0, 1) };
+		let mut ids = HashSet::from([id(0, 0), id(0, 1), id(0, 2), id(1, 0)]);
+
+		edit.migrate_ids(&mut ids);
+		assert_eq!(ids, HashSet::from([id(0, 0), id(0, 1), id(1, 0)]));
+	}
+
+	#[test]
+	fn valid_layout() {
+		let config = GlobalConfig {
+			strips: strips(),
+			groups: vec![Group {
+				id: "all".to_string(),
+				segment_ids: HashSet::from([id(0, 0), id(1, 0)]),
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let state = DisplayState {
+			effects: vec![effect(&[id(0, 1)], &["all"]), effect(&[id(0, 2)], &[])],
+		};
+
+		let report = check_layout(&config, &state);
+		assert!(report.errors.is_empty(), "{:?}", report.errors);
+		assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+	}
+
+	#[test]
+	fn layout_errors() {
+		let mut strips = strips();
+		strips[1].segments.push(segment("long", LEDS_PER_STRIP));
+		strips.resize_with(STRIPS + 1, Strip::default);
+		let group = Group {
+			id: "all".to_string(),
+			segment_ids: HashSet::from([id(0, 0)]),
+			..Default::default()
+		};
+		let config = GlobalConfig {
+			strips,
+			groups: vec![group.clone(), group],
+			..Default::default()
+		};
+
+		let report = check_layout(&config, &DisplayState::default());
+		assert_eq!(
+			fields(&report.errors),
+			["strips", "strips[1].segments[1]", "groups[1].id"]
+		);
+		assert!(report.into_result().is_err());
+	}
+
+	#[test]
+	fn layout_warnings() {
+		let mut strips = strips();
+		strips[0].segments[2].length = 0;
+		strips.push(Strip::default());
+		let config = GlobalConfig {
+			strips,
+			groups: vec![
+				Group {
+					id: "empty".to_string(),
+					..Default::default()
+				},
+				Group {
+					id: "missing".to_string(),
+					segment_ids: HashSet::from([id(5, 0)]),
+					..Default::default()
+				},
+			],
+			..Default::default()
+		};
+		let state = DisplayState {
+			effects: vec![
+				effect(&[id(0, 0), id(0, 9)], &["unknown"]),
+				effect(&[id(0, 0)], &[]),
+			],
+		};
+
+		let report = check_layout(&config, &state);
+		assert!(report.errors.is_empty(), "{:?}", report.errors);
+		assert_eq!(
+			fields(&report.warnings),
+			[
+				"strips[0].segments[2].length",
+				"strips[2].segments",
+				"groups[0].segment_ids",
+				"groups[1].segment_ids",
+				"state.effects[0].segment_ids",
+				"state.effects[0].group_ids",
+				"state.effects[1]",
+			]
+		);
+		assert_eq!(report.into_result().unwrap().len(), 7);
+	}
+	#[test]
+	fn shared_targets() {
+		let config = GlobalConfig {
+			strips: strips(),
+			groups: vec![Group {
+				id: "first".to_string(),
+				// the missing segment isn't reported as shared
+				segment_ids: HashSet::from([id(0, 0), id(0, 1), id(4, 0)]),
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let state = DisplayState {
+			effects: vec![
+				effect(&[], &["first"]),
+				effect(&[id(0, 1), id(1, 0)], &[]),
+				effect(&[id(0, 2)], &[]),
+				effect(&[id(1, 0), id(0, 0), id(4, 0)], &["first"]),
+			],
+		};
+
+		let report = check_layout(&config, &state);
+		let warnings = report
+			.warnings
+			.iter()
+			.filter(|warning| warning.description.starts_with("shares"))
+			.map(|warning| (warning.field.as_str(), warning.description.as_str()))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			warnings,
+			[
+				(
+					"state.effects[1]",
+					"shares segments 0:1 with effects[0], they draw over each other"
+				),
+				(
+					"state.effects[3]",
+					"shares segments 0:0, 0:1 with effects[0], they draw over each other"
+				),
+				(
+					"state.effects[3]",
+					"shares segments 0:1, 1:0 with effects[1], they draw over each other"
+				),
+			]
+		);
+	}
+}
@@ -1,17 +1,25 @@
 use std::{
 	collections::{HashMap, HashSet},
-	fs::File,
-	io::{BufReader, BufWriter},
 	mem,
 	ops::{Deref, DerefMut},
-	path::{Path, PathBuf},
+	sync::Arc,
 };
 
 use educe::Educe;
-use eyre::Result;
+use eyre::{bail, Result, WrapErr};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{error, info, warn};
 
+pub use crate::config::{backup::Backup, history::History, store::ConfigStore};
+use crate::{audio::AudioConfig, effects::config::color::Color};
+
+pub mod backup;
+pub mod bundle;
 pub mod db;
+pub mod history;
+pub mod layout;
+pub mod server;
+pub mod store;
 
 pub trait WithConfig {
 	type Config: Default + DeserializeOwned + Serialize;
@@ -20,12 +28,86 @@ pub trait WithConfig {
 }
 
 pub trait ConfigFile: Serialize + DeserializeOwned {
-	fn path(config_dir: &Path) -> PathBuf;
+	/// Name of the config in the store, the file store saves it as `<name>.json`.
+	const NAME: &'static str;
+
+	/// Version of the file format, increase it together with a new step in [`ConfigFile::migrate`].
+	const VERSION: u32 = 1;
+
+	/// Whether backups are kept of the config. If it can't be loaded and there's no backup, it
+	/// starts empty instead of failing.
+	const BACKUP: bool = true;
+
+	/// Migrates the data from `version` to `version + 1`.
+	///
+	/// Version 0 is a file from before versioning, which only needs to be wrapped.
+	fn migrate(version: u32, data: &mut serde_json::Value) -> Result<()> {
+		let _ = (version, data);
+		Ok(())
+	}
+}
+
+/// Names of all configs, used when moving them between stores.
+pub const CONFIG_NAMES: [&str; 5] = [
+	GlobalConfig::NAME,
+	DisplayState::NAME,
+	Presets::NAME,
+	Scripts::NAME,
+	History::NAME,
+];
+
+/// What's stored on disk, the data of the file with the version of its format.
+#[derive(Serialize)]
+struct VersionedRef<'a, T> {
+	version: u32,
+	data:    &'a T,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Versioned {
+	version: u32,
+	data:    serde_json::Value,
+}
+
+/// Splits stored data into its version and the data, data from before versioning is version 0.
+fn split_versioned(value: serde_json::Value) -> (u32, serde_json::Value) {
+	match serde_json::from_value::<Versioned>(value.clone()) {
+		Ok(Versioned { version, data }) => (version, data),
+		Err(_) => (0, value),
+	}
+}
+
+/// The serialized data of a config, see [`Config::staged`].
+pub struct StagedConfig {
+	name:   &'static str,
+	data:   serde_json::Value,
+	backup: bool,
+}
+
+/// Saves several configs at once, if it fails none of them are changed.
+pub fn save_all(store: &dyn ConfigStore, configs: &[StagedConfig]) -> Result<()> {
+	let data = configs
+		.iter()
+		.map(|config| (config.name, &config.data))
+		.collect::<Vec<_>>();
+	store.write_all(&data)?;
+
+	for config in configs.iter().filter(|config| config.backup) {
+		// the data itself was saved, a missing backup is not worth failing the request for
+		if let Err(err) = backup::create_if_due(store, config.name, &config.data) {
+			warn!("failed to back up {}: {:?}", config.name, err);
+		}
+	}
+
+	Ok(())
 }
 
 pub struct Config<T> {
-	inner: T,
-	path:  PathBuf,
+	inner:    T,
+	store:    Arc<dyn ConfigStore>,
+	/// Counts every mutable access, so copies of the config only need updating when it changed.
+	revision: u64,
 }
 
 impl<T> Deref for Config<T> {
@@ -38,51 +120,198 @@ impl<T> Deref for Config<T> {
 
 impl<T> DerefMut for Config<T> {
 	fn deref_mut(&mut self) -> &mut Self::Target {
+		self.revision += 1;
 		&mut self.inner
 	}
 }
 
 impl<T: ConfigFile + Serialize + Default> Config<T> {
-	pub fn load(config_dir: &Path) -> Result<Self> {
-		let path = T::path(config_dir);
+	pub fn load(store: Arc<dyn ConfigStore>) -> Result<Self> {
+		let (config, needs_save) = match store.read(T::NAME) {
+			Ok(None) => (Default::default(), true),
+			Ok(Some(value)) => {
+				let (version, data) = split_versioned(value);
+				// written by a newer server, a backup would throw away what it changed
+				Self::check_version(version, T::NAME)?;
+
+				match Self::migrate_data(version, data, T::NAME) {
+					Ok(config) => (config, version < T::VERSION),
+					Err(err) => (Self::recover(&*store, err)?, true),
+				}
+			}
+			Err(err) => (Self::recover(&*store, err)?, true),
+		};
+
+		let config = Self {
+			inner: config,
+			store,
+			revision: 0,
+		};
+
+		if needs_save {
+			config.save()?;
+		}
+
+		Ok(config)
+	}
+
+	/// Reads the stored config without saving migrations or falling back to a backup.
+	pub fn peek(store: &dyn ConfigStore) -> Result<T> {
+		match store.read(T::NAME)? {
+			Some(value) => Ok(Self::decode(value, T::NAME)?.0),
+			None => Ok(Default::default()),
+		}
+	}
+
+	/// Migrates the stored data, returns it together with the version it was stored with.
+	fn decode(value: serde_json::Value, origin: &str) -> Result<(T, u32)> {
+		let (version, data) = split_versioned(value);
+		Self::check_version(version, origin)?;
+
+		Ok((Self::migrate_data(version, data, origin)?, version))
+	}
+
+	fn check_version(version: u32, origin: &str) -> Result<()> {
+		if version > T::VERSION {
+			bail!(
+				"{} has version {}, but only {} is supported",
+				origin,
+				version,
+				T::VERSION
+			);
+		}
 
-		if !path.exists() {
-			let cfg = Self {
-				inner: Default::default(),
-				path,
+		Ok(())
+	}
+
+	/// Migrates data of the given version, which must not be newer than [`ConfigFile::VERSION`].
+	fn migrate_data(version: u32, mut data: serde_json::Value, origin: &str) -> Result<T> {
+		for version in version..T::VERSION {
+			T::migrate(version, &mut data)
+				.wrap_err_with(|| format!("migrating {} from version {}", origin, version))?;
+		}
+
+		let config =
+			serde_json::from_value(data).wrap_err_with(|| format!("deserializing {}", origin))?;
+
+		if version < T::VERSION {
+			info!(
+				"migrated {} from version {} to {}",
+				origin,
+				version,
+				T::VERSION
+			);
+		}
+
+		Ok(config)
+	}
+
+	/// Falls back to the newest backup that can be read if the stored data is broken, the broken
+	/// data is kept for inspection.
+	fn recover(store: &dyn ConfigStore, err: eyre::Report) -> Result<T> {
+		error!("failed to load {}: {:?}", T::NAME, err);
+
+		if !T::BACKUP {
+			let moved_to = store.quarantine(T::NAME)?;
+			warn!(
+				"starting with an empty {}, the broken data was moved to {}",
+				T::NAME,
+				moved_to
+			);
+			return Ok(Default::default());
+		}
+
+		for backup in store.backups(T::NAME)? {
+			let config = match store
+				.read_backup(T::NAME, &backup.id)
+				.and_then(|value| Self::decode(value, &backup.id))
+			{
+				Ok((config, _)) => config,
+				Err(err) => {
+					warn!("skipping broken backup {}: {:?}", backup.id, err);
+					continue;
+				}
 			};
-			cfg.save()?;
 
-			return Ok(cfg);
+			let moved_to = store.quarantine(T::NAME)?;
+
+			warn!(
+				"recovered {} from backup {}, the broken data was moved to {}",
+				T::NAME,
+				backup.id,
+				moved_to
+			);
+
+			return Ok(config);
 		}
 
-		let file = File::open(&path)?;
-		let reader = BufReader::new(file);
+		Err(err.wrap_err(format!("no usable backup of {}", T::NAME)))
+	}
+
+	fn serialize(&self) -> Result<serde_json::Value> {
+		Ok(serde_json::to_value(VersionedRef {
+			version: T::VERSION,
+			data:    &self.inner,
+		})?)
+	}
 
-		let config = serde_json::from_reader(reader)?;
+	pub fn save(&self) -> Result<()> {
+		save_all(&*self.store, &[self.staged()?])
+	}
 
-		Ok(Self {
-			inner: config,
-			path,
+	/// The data [`Config::save`] would write, to save it together with other configs with
+	/// [`save_all`].
+	pub fn staged(&self) -> Result<StagedConfig> {
+		Ok(StagedConfig {
+			name:   T::NAME,
+			data:   self.serialize()?,
+			backup: T::BACKUP,
 		})
 	}
 
-	pub fn save(&self) -> Result<()> {
-		let file = File::create(&self.path)?;
-		let writer = BufWriter::new(file);
+	/// Name of the config in the store, e.g. `state`.
+	pub fn name(&self) -> &'static str {
+		T::NAME
+	}
+
+	/// Backups of the config, newest first.
+	pub fn backups(&self) -> Result<Vec<Backup>> {
+		self.store.backups(T::NAME)
+	}
 
-		serde_json::to_writer_pretty(writer, &self.inner)?;
+	/// Replaces the data with the backup with the given id, the current data is backed up first so
+	/// the restore can be reverted.
+	pub fn restore(&mut self, id: &str) -> Result<()> {
+		let value = self
+			.store
+			.read_backup(T::NAME, id)
+			.wrap_err_with(|| format!("reading backup {} of {}", id, T::NAME))?;
+		let (config, _) = Self::decode(value, id)?;
+
+		backup::create(&*self.store, T::NAME, &self.serialize()?)?;
+
+		self.set(config);
+		self.save()?;
+
+		info!("restored {} from backup {}", T::NAME, id);
 
 		Ok(())
 	}
 
 	pub fn set(&mut self, val: T) {
+		self.revision += 1;
 		self.inner = val;
 	}
 
 	pub fn replace(&mut self, val: T) -> T {
+		self.revision += 1;
 		mem::replace(&mut self.inner, val)
 	}
+
+	/// Changes whenever the config might have been changed, the saved state doesn't matter.
+	pub fn revision(&self) -> u64 {
+		self.revision
+	}
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Educe)]
@@ -97,28 +326,95 @@ pub struct GlobalConfig {
 	pub strips: Vec<Strip>,
 	#[serde(default)]
 	pub groups: Vec<Group>,
+
+	#[serde(default)]
+	pub audio: AudioConfig,
+
+	/// Keep the undo history across restarts.
+	#[serde(default)]
+	pub persist_history: bool,
+
+	/// How long switching the power on or to standby fades, in milliseconds.
+	#[educe(Default = 1000)]
+	#[serde(default = "default_power_fade_ms")]
+	pub power_fade_ms:  u64,
+	/// Don't render the effects while the lights are off.
+	#[educe(Default = true)]
+	#[serde(default = "default_pause_when_off")]
+	pub pause_when_off: bool,
 }
 
-impl ConfigFile for GlobalConfig {
-	fn path(config_dir: &Path) -> PathBuf {
-		config_dir.join("config.json")
+impl GlobalConfig {
+	/// Every configured segment with its id and the index of its first LED on the strip.
+	pub fn segments(&self) -> impl Iterator<Item = (SegmentId, usize, &Segment)> + '_ {
+		self.strips
+			.iter()
+			.enumerate()
+			.flat_map(|(strip_idx, strip)| {
+				strip.segments.iter().enumerate().scan(
+					strip.offset,
+					move |led_idx, (segment_idx, segment)| {
+						let start = *led_idx;
+						*led_idx += segment.length;
+
+						let id = SegmentId {
+							strip_idx,
+							segment_idx,
+						};
+						Some((id, start, segment))
+					},
+				)
+			})
+	}
+
+	/// The segment with the `id` and the index of its first LED on the strip.
+	pub fn segment(&self, id: SegmentId) -> Option<(usize, &Segment)> {
+		let strip = self.strips.get(id.strip_idx)?;
+		let segment = strip.segments.get(id.segment_idx)?;
+
+		let start = strip.offset
+			+ strip.segments[..id.segment_idx]
+				.iter()
+				.map(|segment| segment.length)
+				.sum::<usize>();
+
+		Some((start, segment))
 	}
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+fn default_power_fade_ms() -> u64 {
+	GlobalConfig::default().power_fade_ms
+}
+
+fn default_pause_when_off() -> bool {
+	GlobalConfig::default().pause_when_off
+}
+
+impl ConfigFile for GlobalConfig {
+	const NAME: &'static str = "config";
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Strip {
 	pub offset:   usize,
 	pub segments: Vec<Segment>,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+impl Strip {
+	/// LEDs of all segments together, without the offset.
+	pub fn led_count(&self) -> usize {
+		self.segments.iter().map(|segment| segment.length).sum()
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Segment {
 	pub name:     String,
 	pub length:   usize,
 	pub reversed: bool,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Group {
 	pub id:          String,
 	pub name:        String,
@@ -139,27 +435,228 @@ pub struct SegmentId {
 pub struct Presets(pub HashMap<String, DisplayState>);
 
 impl ConfigFile for Presets {
-	fn path(config_dir: &Path) -> PathBuf {
-		config_dir.join("presets.json")
-	}
+	const NAME: &'static str = "presets";
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct DisplayState {
 	#[serde(default)]
 	pub effects: Vec<DisplayStateEffect>,
 }
 
 impl ConfigFile for DisplayState {
-	fn path(config_dir: &Path) -> PathBuf {
-		config_dir.join("state.json")
-	}
+	const NAME: &'static str = "state";
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct DisplayStateEffect {
-	pub effect_id:   String,
-	pub config:      serde_json::Value,
-	pub segment_ids: HashSet<SegmentId>,
-	pub group_ids:   HashSet<String>,
+	pub effect_id:      String,
+	pub config:         serde_json::Value,
+	/// Version of the config, see [`crate::effects::EffectFactory::config_version`].
+	#[serde(default)]
+	pub config_version: u32,
+	pub segment_ids:    HashSet<SegmentId>,
+	pub group_ids:      HashSet<String>,
+	#[serde(default)]
+	pub modifiers:      EffectModifiers,
+}
+
+/// Applied by the runner to whatever the effect renders, the same for every effect.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Educe)]
+#[educe(Default)]
+#[serde(default)]
+pub struct EffectModifiers {
+	/// Multiplied with the alpha of the pixels, from 0 to 1.
+	#[educe(Default = 1.0)]
+	pub opacity:    f32,
+	/// How fast the effect runs, 0 freezes it.
+	#[educe(Default = 1.0)]
+	pub speed:      f32,
+	/// Rotates the hue of the pixels, in degrees.
+	#[educe(Default = 0.0)]
+	pub hue:        f32,
+	/// Multiplied with the saturation of the pixels.
+	#[educe(Default = 1.0)]
+	pub saturation: f32,
+	/// Renders the effect on the first half of every tile and mirrors it onto the second half,
+	/// together with `reverse` it starts in the center.
+	#[educe(Default = false)]
+	pub mirror:     bool,
+	/// Renders the effect from the end of every window.
+	#[educe(Default = false)]
+	pub reverse:    bool,
+	/// Tiles the effect this many times over every segment.
+	#[educe(Default = 1)]
+	pub repeat:     usize,
+	/// Rotates the effect along the segment by this many LEDs, wrapping around.
+	#[educe(Default = 0)]
+	pub offset:     i32,
+	/// Turns off every n-th LED to space out the effect, 0 uses every LED.
+	#[educe(Default = 0)]
+	pub skip:       usize,
+	/// First LED of every segment the effect runs on, the LEDs before it are left alone.
+	#[educe(Default = 0)]
+	pub start:      usize,
+	/// The effect runs up to this LED of every segment, the LEDs after it are left alone.
+	pub end:        Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Scripts(pub HashMap<String, Script>);
+
+impl ConfigFile for Scripts {
+	const NAME: &'static str = "scripts";
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Script {
+	pub source: String,
+	#[serde(default)]
+	pub params: Vec<ScriptParam>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptParam {
+	pub name: String,
+	#[serde(flatten)]
+	pub kind: ScriptParamKind,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScriptParamKind {
+	Number {
+		default: f32,
+		minimum: Option<f32>,
+		maximum: Option<f32>,
+	},
+	Bool {
+		default: bool,
+	},
+	Color {
+		default: Color,
+	},
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{fs, path::PathBuf};
+
+	use serde_json::json;
+
+	use super::*;
+	use crate::config::store::{test_dir, FileStore};
+
+	/// Stored as `{ "value": n }` before versioning, version 1 only wrapped it and version 2
+	/// renamed `value` to `count`.
+	#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+	struct Counter {
+		count: u32,
+	}
+
+	impl ConfigFile for Counter {
+		const NAME: &'static str = "counter";
+		const VERSION: u32 = 2;
+
+		fn migrate(version: u32, data: &mut serde_json::Value) -> Result<()> {
+			if version == 1 {
+				let Some(value) = data.get("value").cloned() else {
+					bail!("missing value");
+				};
+				*data = json!({ "count": value });
+			}
+			Ok(())
+		}
+	}
+
+	fn store(name: &str, data: serde_json::Value) -> (PathBuf, Arc<FileStore>) {
+		let dir = test_dir(name);
+		let store = FileStore::new(&dir);
+		store.write_all(&[(Counter::NAME, &data)]).unwrap();
+		(dir, Arc::new(store))
+	}
+
+	#[test]
+	fn migrates() {
+		for (name, data) in [
+			("unversioned", json!({ "value": 3 })),
+			("version-1", json!({ "version": 1, "data": { "value": 3 } })),
+			("version-2", json!({ "version": 2, "data": { "count": 3 } })),
+		] {
+			let (dir, store) = store(name, data);
+
+			let config = Config::<Counter>::load(store.clone()).unwrap();
+			assert_eq!(*config, Counter { count: 3 }, "{}", name);
+
+			// saved with the current version, so it loads again without migrating
+			assert_eq!(
+				store.read(Counter::NAME).unwrap(),
+				Some(json!({ "version": 2, "data": { "count": 3 } })),
+				"{}",
+				name
+			);
+			assert_eq!(
+				Config::<Counter>::peek(&*store).unwrap(),
+				Counter { count: 3 }
+			);
+
+			fs::remove_dir_all(dir).ok();
+		}
+	}
+
+	#[test]
+	fn rejects_newer_version() {
+		let newer = json!({ "version": 3, "data": { "total": 3 } });
+		let (dir, store) = store("newer", newer.clone());
+		store
+			.create_backup(
+				Counter::NAME,
+				&json!({ "version": 2, "data": { "count": 1 } }),
+			)
+			.unwrap();
+
+		assert!(Config::<Counter>::load(store.clone()).is_err());
+		assert!(Config::<Counter>::peek(&*store).is_err());
+
+		// neither replaced by the backup nor moved away
+		assert_eq!(store.read(Counter::NAME).unwrap(), Some(newer));
+		assert_eq!(store.backups(Counter::NAME).unwrap().len(), 1);
+
+		fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn recovers_broken_data() {
+		let (dir, store) = store("broken", json!({ "version": 2, "data": { "count": "a" } }));
+		store
+			.create_backup(
+				Counter::NAME,
+				&json!({ "version": 2, "data": { "count": 1 } }),
+			)
+			.unwrap();
+
+		let config = Config::<Counter>::load(store.clone()).unwrap();
+		assert_eq!(*config, Counter { count: 1 });
+		assert_eq!(
+			store.read(Counter::NAME).unwrap(),
+			Some(json!({ "version": 2, "data": { "count": 1 } }))
+		);
+
+		// the broken data is kept next to it
+		let corrupt = fs::read_dir(&dir)
+			.unwrap()
+			.filter(|entry| {
+				entry
+					.as_ref()
+					.unwrap()
+					.file_name()
+					.to_string_lossy()
+					.starts_with("counter.json.corrupt-")
+			})
+			.count();
+		assert_eq!(corrupt, 1);
+
+		fs::remove_dir_all(dir).ok();
+	}
 }
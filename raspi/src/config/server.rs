@@ -0,0 +1,96 @@
+//! Settings of the server binary, read from an optional TOML file and overridden on the command
+//! line. Unlike the other configs these are only read at startup.
+
+use std::{
+	fs,
+	net::{IpAddr, SocketAddr},
+	path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+use crate::{config::store::StoreKind, controller::OutputBackend};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+	#[default]
+	Full,
+	Compact,
+	Pretty,
+	Json,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+	/// Directory with the configs, plugins and the config db.
+	pub config_dir: PathBuf,
+	pub store:      StoreKind,
+
+	pub http_addr:   SocketAddr,
+	pub grpc_addr:   SocketAddr,
+	/// Address for the pixel stream packets, see [`crate::stream`].
+	pub udp_addr:    SocketAddr,
+	/// Hosts that may send pixel stream packets when auth is enabled, packets can't carry a
+	/// token. The udp listener isn't started if auth is enabled and this is empty.
+	pub udp_senders: Vec<IpAddr>,
+	/// Served by the http server, usually the build of the frontend.
+	pub static_dir:  PathBuf,
+	/// Tokens for the apis, see [`crate::auth`], defaults to `auth.toml` in the config directory.
+	pub auth_file:   Option<PathBuf>,
+
+	pub output:          OutputBackend,
+	pub log_format:      LogFormat,
+	/// Limits how often the effects are rendered, as fast as possible if not set.
+	pub fps:             Option<f32>,
+	/// Time in milliseconds each effect may take per frame, slower effects are rendered less
+	/// often. Not enforced if not set.
+	pub frame_budget_ms: Option<f32>,
+
+	/// How long the lights fade out when the server is stopped.
+	pub shutdown_fade_ms: u64,
+	/// Preset faded in after fading out, instead of leaving the lights off. It's only shown until
+	/// the server is started again.
+	pub shutdown_preset:  Option<String>,
+}
+
+impl Default for ServerConfig {
+	fn default() -> Self {
+		ServerConfig {
+			config_dir: PathBuf::from(".config"),
+			store:      StoreKind::default(),
+
+			http_addr:   SocketAddr::from(([0, 0, 0, 0], 4444)),
+			grpc_addr:   SocketAddr::from(([0, 0, 0, 0], 4445)),
+			udp_addr:    SocketAddr::from(([0, 0, 0, 0], 4446)),
+			udp_senders: vec![],
+			static_dir:  PathBuf::from("public"),
+			auth_file:   None,
+
+			output:          OutputBackend::default(),
+			log_format:      LogFormat::default(),
+			fps:             None,
+			frame_budget_ms: None,
+
+			shutdown_fade_ms: 500,
+			shutdown_preset:  None,
+		}
+	}
+}
+
+impl ServerConfig {
+	pub fn load(path: &Path) -> Result<Self> {
+		let data =
+			fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+		toml::from_str(&data).wrap_err_with(|| format!("parsing {}", path.display()))
+	}
+
+	pub fn auth_file(&self) -> PathBuf {
+		self.auth_file
+			.clone()
+			.unwrap_or_else(|| self.config_dir.join("auth.toml"))
+	}
+}
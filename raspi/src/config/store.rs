@@ -0,0 +1,604 @@
+//! Where configs are persisted, either as JSON files in the config directory or in a sled
+//! database in it.
+
+use std::{
+	fs::{self, File},
+	io::Write,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use eyre::{bail, eyre, ContextCompat, Result, WrapErr};
+use serde::Deserialize;
+use serde_json::Value;
+use sled::Transactional;
+use tracing::{info, warn};
+
+use crate::config::{
+	backup::{backup_id, parse_backup_id, Backup},
+	db,
+	CONFIG_NAMES,
+};
+
+/// Storage for the data of [`crate::config::Config`]s, the data includes the version envelope.
+pub trait ConfigStore: Send + Sync {
+	/// Reads the data of a config, `None` if it was never saved.
+	fn read(&self, name: &str) -> Result<Option<Value>>;
+	/// Replaces the data of the configs, either completely or not at all. Changes that touch more
+	/// than one config write them together.
+	fn write_all(&self, configs: &[(&str, &Value)]) -> Result<()>;
+	/// Moves data that can't be loaded out of the way so it can be inspected later, returns where
+	/// it was moved to.
+	fn quarantine(&self, name: &str) -> Result<String>;
+
+	/// Backups of a config, newest first.
+	fn backups(&self, name: &str) -> Result<Vec<Backup>>;
+	fn read_backup(&self, name: &str, id: &str) -> Result<Value>;
+	fn create_backup(&self, name: &str, data: &Value) -> Result<()>;
+	fn delete_backup(&self, name: &str, id: &str) -> Result<()>;
+
+	/// Makes sure everything written so far is on disk, before exiting.
+	fn flush(&self) -> Result<()> {
+		Ok(())
+	}
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+	/// A JSON file per config.
+	#[default]
+	File,
+	/// A sled database in `db`, the JSON files are imported when it is first opened.
+	Sled,
+}
+
+/// Opens the store in the config directory, switching to sled imports the JSON files once.
+pub fn open(kind: StoreKind, config_dir: &Path) -> Result<Arc<dyn ConfigStore>> {
+	let files = FileStore::new(config_dir);
+
+	match kind {
+		StoreKind::File => Ok(Arc::new(files)),
+		StoreKind::Sled => {
+			let sled = SledStore::open(&config_dir.join("db"))?;
+			sled.import(&files)?;
+
+			Ok(Arc::new(sled))
+		}
+	}
+}
+
+/// Opens the store for reading without creating or importing anything, so the configs can be
+/// checked while the server isn't running. Configs sled would import on its next start are read
+/// from their JSON files, their names are returned with the store.
+pub fn open_read_only(
+	kind: StoreKind,
+	config_dir: &Path,
+) -> Result<(Arc<dyn ConfigStore>, Vec<&'static str>)> {
+	let files = FileStore::new(config_dir);
+
+	match kind {
+		StoreKind::File => Ok((
+			Arc::new(ReadOnlyStore {
+				sled: None,
+				files,
+				imports: vec![],
+			}),
+			vec![],
+		)),
+		StoreKind::Sled => {
+			let path = config_dir.join("db");
+			let sled = if path.exists() {
+				Some(SledStore::open(&path)?)
+			} else {
+				None
+			};
+
+			let mut imports = vec![];
+			for name in CONFIG_NAMES {
+				let imported = match &sled {
+					Some(sled) => sled.configs.contains_key(name)?,
+					None => false,
+				};
+				if !imported && files.path(name).exists() {
+					imports.push(name);
+				}
+			}
+
+			let store = ReadOnlyStore {
+				sled,
+				files,
+				imports: imports.clone(),
+			};
+			Ok((Arc::new(store), imports))
+		}
+	}
+}
+
+fn now_millis() -> Result<u128> {
+	Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis())
+}
+
+/// Writes the data to a temporary file next to `path`, returns the path of the temporary file.
+fn write_tmp(path: &Path, data: &[u8]) -> Result<PathBuf> {
+	let tmp_path = path.with_extension("json.tmp");
+
+	let mut file =
+		File::create(&tmp_path).wrap_err_with(|| format!("creating {}", tmp_path.display()))?;
+	file.write_all(data)?;
+	file.sync_all()?;
+
+	Ok(tmp_path)
+}
+
+fn rename_tmp(tmp_path: &Path, path: &Path) -> Result<()> {
+	fs::rename(tmp_path, path)
+		.wrap_err_with(|| format!("renaming {} to {}", tmp_path.display(), path.display()))
+}
+
+/// Makes renames in the directory durable, not supported on every platform.
+fn sync_dir(path: &Path) {
+	if let Some(parent) = path.parent() {
+		if let Ok(dir) = File::open(parent) {
+			dir.sync_all().ok();
+		}
+	}
+}
+
+/// Writes to a temporary file that is renamed over the original, so a crash leaves either the old
+/// or the new file and never a truncated one.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+	let tmp_path = write_tmp(path, data)?;
+	rename_tmp(&tmp_path, path)?;
+	sync_dir(path);
+
+	Ok(())
+}
+
+/// Stores every config as `<name>.json` and the backups as `backups/<id>.json`.
+pub struct FileStore {
+	dir: PathBuf,
+}
+
+impl FileStore {
+	pub fn new(dir: &Path) -> Self {
+		FileStore {
+			dir: dir.to_path_buf(),
+		}
+	}
+
+	fn path(&self, name: &str) -> PathBuf {
+		self.dir.join(format!("{}.json", name))
+	}
+
+	fn backup_path(&self, name: &str, id: &str) -> Result<PathBuf> {
+		// ids come from the api, only allow names that can't point outside of the directory
+		if parse_backup_id(name, id).is_none() {
+			bail!("invalid backup id {} for {}", id, name);
+		}
+
+		Ok(self.dir.join("backups").join(format!("{}.json", id)))
+	}
+
+	fn read_file(path: &Path) -> Result<Value> {
+		let data = fs::read(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+		serde_json::from_slice(&data).wrap_err_with(|| format!("parsing {}", path.display()))
+	}
+}
+
+impl ConfigStore for FileStore {
+	fn read(&self, name: &str) -> Result<Option<Value>> {
+		let path = self.path(name);
+		if !path.exists() {
+			return Ok(None);
+		}
+
+		Self::read_file(&path).map(Some)
+	}
+
+	/// Writes all temporary files before renaming any of them, so a failed write leaves all
+	/// previous files in place.
+	fn write_all(&self, configs: &[(&str, &Value)]) -> Result<()> {
+		let mut written = vec![];
+
+		for (name, data) in configs {
+			let path = self.path(name);
+			let tmp_path = serde_json::to_vec_pretty(data)
+				.map_err(eyre::Report::from)
+				.and_then(|data| write_tmp(&path, &data))
+				.wrap_err_with(|| format!("saving {}", path.display()));
+
+			match tmp_path {
+				Ok(tmp_path) => written.push((tmp_path, path)),
+				Err(err) => {
+					for (tmp_path, _) in written {
+						fs::remove_file(tmp_path).ok();
+					}
+					return Err(err);
+				}
+			}
+		}
+
+		for (tmp_path, path) in written.iter() {
+			rename_tmp(tmp_path, path)?;
+		}
+		if let Some((_, path)) = written.first() {
+			sync_dir(path);
+		}
+
+		Ok(())
+	}
+
+	fn quarantine(&self, name: &str) -> Result<String> {
+		let path = self.path(name);
+		let mut corrupt_path = path.as_os_str().to_owned();
+		corrupt_path.push(format!(".corrupt-{}", now_millis()?));
+
+		fs::rename(&path, &corrupt_path)?;
+
+		Ok(Path::new(&corrupt_path).display().to_string())
+	}
+
+	fn backups(&self, name: &str) -> Result<Vec<Backup>> {
+		let dir = self.dir.join("backups");
+		if !dir.exists() {
+			return Ok(vec![]);
+		}
+
+		let mut backups = vec![];
+		for entry in fs::read_dir(&dir)? {
+			let entry = entry?;
+			let file_name = entry.file_name();
+
+			let Some(id) = file_name
+				.to_str()
+				.and_then(|file_name| file_name.strip_suffix(".json"))
+			else {
+				continue;
+			};
+			let Some(created) = parse_backup_id(name, id) else {
+				continue;
+			};
+
+			backups.push(Backup {
+				config: name.to_string(),
+				id: id.to_string(),
+				created,
+				size: entry.metadata()?.len(),
+			});
+		}
+
+		backups.sort_by(|a, b| b.created.cmp(&a.created));
+
+		Ok(backups)
+	}
+
+	fn read_backup(&self, name: &str, id: &str) -> Result<Value> {
+		Self::read_file(&self.backup_path(name, id)?)
+	}
+
+	fn create_backup(&self, name: &str, data: &Value) -> Result<()> {
+		fs::create_dir_all(self.dir.join("backups"))?;
+
+		let id = backup_id(name, SystemTime::now())?;
+		write_atomic(
+			&self.backup_path(name, &id)?,
+			&serde_json::to_vec_pretty(data)?,
+		)
+	}
+
+	fn delete_backup(&self, name: &str, id: &str) -> Result<()> {
+		let path = self.backup_path(name, id)?;
+		fs::remove_file(&path).wrap_err_with(|| format!("removing {}", path.display()))
+	}
+}
+
+/// Stores the configs in a sled database, with a tree for the configs, one for the backups and
+/// one for data that couldn't be loaded.
+pub struct SledStore {
+	db:      sled::Db,
+	configs: sled::Tree,
+	backups: sled::Tree,
+	corrupt: sled::Tree,
+}
+
+impl SledStore {
+	pub fn open(path: &Path) -> Result<Self> {
+		let db = sled::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+
+		Ok(SledStore {
+			configs: db.open_tree("configs")?,
+			backups: db.open_tree("backups")?,
+			corrupt: db.open_tree("corrupt")?,
+			db,
+		})
+	}
+
+	/// Copies configs and their backups from JSON files that aren't in the database yet, the files
+	/// are renamed to `<name>.json.imported` afterwards so this only happens once.
+	pub fn import(&self, files: &FileStore) -> Result<()> {
+		for name in CONFIG_NAMES {
+			let path = files.path(name);
+			if !path.exists() {
+				continue;
+			}
+
+			if self.configs.contains_key(name)? {
+				warn!(
+					"not importing {}, the config db already contains {}",
+					path.display(),
+					name
+				);
+				continue;
+			}
+
+			let data = match files.read(name) {
+				Ok(data) => data.wrap_err("file should exist")?,
+				Err(err) => {
+					warn!("not importing {}: {:?}", path.display(), err);
+					continue;
+				}
+			};
+
+			for backup in files.backups(name)? {
+				let backup_data = files.read_backup(name, &backup.id)?;
+				self.backups
+					.insert(&backup.id, serde_json::to_vec(&backup_data)?)?;
+			}
+
+			db::save_json(&self.configs, name, &data)?;
+			self.flush()?;
+
+			let mut imported_path = path.as_os_str().to_owned();
+			imported_path.push(".imported");
+			fs::rename(&path, &imported_path)?;
+
+			info!("imported {} into the config db", path.display());
+		}
+
+		Ok(())
+	}
+}
+
+impl ConfigStore for SledStore {
+	fn read(&self, name: &str) -> Result<Option<Value>> {
+		db::load_json(&self.configs, name).wrap_err_with(|| format!("loading {} from the db", name))
+	}
+
+	fn write_all(&self, configs: &[(&str, &Value)]) -> Result<()> {
+		let mut batch = sled::Batch::default();
+		for (name, data) in configs {
+			batch.insert(*name, serde_json::to_vec(data)?);
+		}
+
+		self.configs
+			.apply_batch(batch)
+			.wrap_err("writing configs to the db")?;
+		self.flush()
+	}
+
+	fn quarantine(&self, name: &str) -> Result<String> {
+		let key = format!("{}-{}", name, now_millis()?);
+
+		(&self.configs, &self.corrupt)
+			.transaction(|(configs, corrupt)| {
+				if let Some(data) = configs.remove(name)? {
+					corrupt.insert(key.as_bytes(), data)?;
+				}
+				Ok(())
+			})
+			.map_err(|err: sled::transaction::TransactionError| {
+				eyre!("moving {} to the corrupt tree: {}", name, err)
+			})?;
+		self.flush()?;
+
+		Ok(format!("corrupt/{}", key))
+	}
+
+	fn backups(&self, name: &str) -> Result<Vec<Backup>> {
+		let mut backups = vec![];
+		for entry in self.backups.scan_prefix(format!("{}-", name)) {
+			let (key, data) = entry?;
+
+			let Ok(id) = std::str::from_utf8(&key) else {
+				continue;
+			};
+			let Some(created) = parse_backup_id(name, id) else {
+				continue;
+			};
+
+			backups.push(Backup {
+				config: name.to_string(),
+				id: id.to_string(),
+				created,
+				size: data.len() as u64,
+			});
+		}
+
+		backups.sort_by(|a, b| b.created.cmp(&a.created));
+
+		Ok(backups)
+	}
+
+	fn read_backup(&self, name: &str, id: &str) -> Result<Value> {
+		if parse_backup_id(name, id).is_none() {
+			bail!("invalid backup id {} for {}", id, name);
+		}
+
+		db::load_json(&self.backups, id)?.wrap_err_with(|| format!("backup {} not found", id))
+	}
+
+	fn create_backup(&self, name: &str, data: &Value) -> Result<()> {
+		let id = backup_id(name, SystemTime::now())?;
+		db::save_json(&self.backups, id, data)?;
+		self.flush()
+	}
+
+	fn delete_backup(&self, name: &str, id: &str) -> Result<()> {
+		if parse_backup_id(name, id).is_none() {
+			bail!("invalid backup id {} for {}", id, name);
+		}
+
+		self.backups.remove(id)?;
+		self.flush()
+	}
+
+	fn flush(&self) -> Result<()> {
+		self.db.flush().wrap_err("flushing the config db")?;
+		Ok(())
+	}
+}
+
+/// Reads from sled, or the JSON files for configs that aren't imported yet, and refuses to write.
+struct ReadOnlyStore {
+	sled:    Option<SledStore>,
+	files:   FileStore,
+	imports: Vec<&'static str>,
+}
+
+impl ReadOnlyStore {
+	fn source(&self, name: &str) -> &dyn ConfigStore {
+		match &self.sled {
+			Some(sled) if !self.imports.contains(&name) => sled,
+			_ => &self.files,
+		}
+	}
+}
+
+impl ConfigStore for ReadOnlyStore {
+	fn read(&self, name: &str) -> Result<Option<Value>> {
+		self.source(name).read(name)
+	}
+
+	fn write_all(&self, _configs: &[(&str, &Value)]) -> Result<()> {
+		bail!("the store is opened read-only")
+	}
+
+	fn quarantine(&self, _name: &str) -> Result<String> {
+		bail!("the store is opened read-only")
+	}
+
+	fn backups(&self, name: &str) -> Result<Vec<Backup>> {
+		self.source(name).backups(name)
+	}
+
+	fn read_backup(&self, name: &str, id: &str) -> Result<Value> {
+		self.source(name).read_backup(name, id)
+	}
+
+	fn create_backup(&self, _name: &str, _data: &Value) -> Result<()> {
+		bail!("the store is opened read-only")
+	}
+
+	fn delete_backup(&self, _name: &str, _id: &str) -> Result<()> {
+		bail!("the store is opened read-only")
+	}
+}
+
+/// An empty directory for a test, the tests remove it once they're done.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!(
+		"robolab-store-{}-{}-{}",
+		name,
+		std::process::id(),
+		now_millis().unwrap()
+	));
+	fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{thread, time::Duration};
+
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn file_store_write_all() {
+		let dir = test_dir("file");
+		let store = FileStore::new(&dir);
+
+		store
+			.write_all(&[
+				("config", &json!({ "a": 1 })),
+				("state", &json!({ "b": 2 })),
+			])
+			.unwrap();
+		assert_eq!(store.read("config").unwrap(), Some(json!({ "a": 1 })));
+		assert_eq!(store.read("state").unwrap(), Some(json!({ "b": 2 })));
+
+		// the second file can't be written, so the first one isn't replaced either
+		let err = store.write_all(&[
+			("config", &json!({ "a": 3 })),
+			("missing/state", &json!({ "b": 4 })),
+		]);
+		assert!(err.is_err());
+		assert_eq!(store.read("config").unwrap(), Some(json!({ "a": 1 })));
+		assert!(!dir.join("config.json.tmp").exists());
+
+		fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn sled_store_write_all() {
+		let dir = test_dir("sled");
+		let store = SledStore::open(&dir.join("db")).unwrap();
+
+		store
+			.write_all(&[
+				("config", &json!({ "a": 1 })),
+				("state", &json!({ "b": 2 })),
+			])
+			.unwrap();
+		assert_eq!(store.read("config").unwrap(), Some(json!({ "a": 1 })));
+		assert_eq!(store.read("state").unwrap(), Some(json!({ "b": 2 })));
+		assert_eq!(store.read("presets").unwrap(), None);
+
+		drop(store);
+		fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn read_only_sled_doesnt_import() {
+		let dir = test_dir("read-only");
+		FileStore::new(&dir)
+			.write_all(&[("config", &json!({ "a": 1 }))])
+			.unwrap();
+
+		let (store, imports) = open_read_only(StoreKind::Sled, &dir).unwrap();
+		assert_eq!(imports, ["config"]);
+		assert_eq!(store.read("config").unwrap(), Some(json!({ "a": 1 })));
+		assert_eq!(store.read("state").unwrap(), None);
+		assert!(store.write_all(&[("state", &json!({}))]).is_err());
+		assert!(!dir.join("db").exists());
+		assert!(dir.join("config.json").exists());
+		drop(store);
+
+		// once imported, sled is read instead of the files
+		let sled = SledStore::open(&dir.join("db")).unwrap();
+		sled.import(&FileStore::new(&dir)).unwrap();
+		sled.write_all(&[("config", &json!({ "a": 2 }))]).unwrap();
+		drop(sled);
+
+		// sled's flusher thread holds the lock for a moment after the db is dropped
+		let mut opened = open_read_only(StoreKind::Sled, &dir);
+		for _ in 0..20 {
+			if opened.is_ok() {
+				break;
+			}
+			thread::sleep(Duration::from_millis(100));
+			opened = open_read_only(StoreKind::Sled, &dir);
+		}
+		let (store, imports) = opened.unwrap();
+		assert!(imports.is_empty());
+		assert_eq!(store.read("config").unwrap(), Some(json!({ "a": 2 })));
+
+		drop(store);
+		fs::remove_dir_all(dir).ok();
+	}
+}
@@ -1,47 +1,69 @@
 use std::{
 	fmt::Debug,
 	io::ErrorKind,
+	mem,
 	ops::{Bound, Index, IndexMut, RangeBounds},
-	slice::{Iter, IterMut},
+	time::Instant,
 };
 
+use clap::ValueEnum;
 use eyre::{eyre, Result};
 use palette::{encoding, Mix, WithAlpha};
+use serde::Deserialize;
 use serial_ws2812::{Config as SerialConfig, Error, SerialWs2812};
 use tracing::{error, instrument, trace};
 
 use crate::{
 	color::{Rgb, Rgba},
-	config::GlobalConfig,
+	config::{GlobalConfig, SegmentId},
+	metrics::metrics,
 };
 
 pub const LEDS_PER_STRIP: usize = 480;
 pub const STRIPS: usize = 8;
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputBackend {
+	/// The WS2812 controller connected over serial.
+	#[default]
+	Serial,
+	/// Renders without sending the frames anywhere, for running without the hardware.
+	Disabled,
+}
+
 pub struct Controller {
-	serial: SerialWs2812,
+	serial: Option<SerialWs2812>,
 
 	state:  [[Rgba; LEDS_PER_STRIP]; STRIPS],
 	buffer: [u8; 3 * LEDS_PER_STRIP * STRIPS],
 }
 
 pub trait LedController {
-	fn write_state(&mut self, config: &GlobalConfig);
+	fn write_state(&mut self, config: &GlobalConfig, level: f32);
 	fn state_mut(&mut self) -> &mut [[Rgba; LEDS_PER_STRIP]; STRIPS];
 	fn state_mut_flat(&mut self) -> &mut [Rgba; LEDS_PER_STRIP * STRIPS];
-	fn views_mut(&mut self) -> Views;
+	/// The sections of the configured segments.
+	fn views_mut(&mut self, config: &GlobalConfig) -> Views;
 	fn section(&mut self, strip: usize, start: usize, len: usize, reversed: bool) -> Section;
 }
 
 impl Controller {
-	pub fn new() -> Result<Self> {
-		let mut serial = SerialWs2812::find(SerialConfig {
-			leds:   LEDS_PER_STRIP,
-			strips: STRIPS,
-		})?
-		.ok_or(eyre!("device not found"))?;
-
-		serial.configure()?;
+	pub fn new(output: OutputBackend) -> Result<Self> {
+		let serial = match output {
+			OutputBackend::Serial => {
+				let mut serial = SerialWs2812::find(SerialConfig {
+					leds:   LEDS_PER_STRIP,
+					strips: STRIPS,
+				})?
+				.ok_or(eyre!("device not found"))?;
+
+				serial.configure()?;
+
+				Some(serial)
+			}
+			OutputBackend::Disabled => None,
+		};
 
 		Ok(Controller {
 			serial,
@@ -52,12 +74,12 @@ impl Controller {
 	}
 
 	#[instrument(skip(self))]
-	fn encode_state(&mut self, config: &GlobalConfig) {
+	fn encode_state(&mut self, config: &GlobalConfig, level: f32) {
 		#[allow(clippy::identity_op)]
 		for (i, c) in self.state.iter().flatten().enumerate() {
 			let (c, a) = c.split();
 			// from black to the colour
-			let c = Rgb::default().mix(c.into_linear(), a * config.brightness);
+			let c = Rgb::default().mix(c.into_linear(), a * config.brightness * level);
 
 			let (r, g, b) = if config.as_srgb {
 				c.into_encoding::<u8, encoding::Srgb>()
@@ -75,13 +97,24 @@ impl Controller {
 }
 
 impl LedController for Controller {
-	/// Writes the inner state to the strips
+	/// Writes the inner state to the strips, `level` is multiplied with the brightness
 	#[instrument(skip(self))]
-	fn write_state(&mut self, config: &GlobalConfig) {
+	fn write_state(&mut self, config: &GlobalConfig, level: f32) {
 		trace!("sending ws2812 buffer over serial");
-		self.encode_state(config);
+		self.encode_state(config, level);
 
-		if let Err(e) = self.serial.send_leds(&self.buffer) {
+		let Some(serial) = &mut self.serial else {
+			return;
+		};
+
+		let start = Instant::now();
+		let result = serial.send_leds(&self.buffer);
+		metrics()
+			.serial_write_time
+			.observe(start.elapsed().as_secs_f64());
+
+		if let Err(e) = result {
+			metrics().error("serial");
 			if let Error::IO(ref e) = e {
 				if e.kind() == ErrorKind::BrokenPipe {
 					panic!("broken pipe: {:#}", e);
@@ -102,9 +135,9 @@ impl LedController for Controller {
 		unsafe { std::mem::transmute(&mut self.state) }
 	}
 
-	#[instrument(skip(self))]
-	fn views_mut(&mut self) -> Views {
-		Views::new(&mut self.state)
+	#[instrument(skip(self, config))]
+	fn views_mut(&mut self, config: &GlobalConfig) -> Views {
+		Views::new(&mut self.state, config)
 	}
 
 	#[instrument(skip(self))]
@@ -116,54 +149,34 @@ impl LedController for Controller {
 	}
 }
 
-///
-/// strip 1: 0-148  149-308  309-391  392-474
-/// strip 2: 0-148  149-308  309-350  351-436
-/// strip 3: 0-106  107-153  154-208  209-308  309-350  351-442  443-474
-///
+/// The sections of the configured segments, see [`GlobalConfig::segments`].
 pub struct Views<'a> {
-	pub sections: [Section<'a>; 15],
+	pub sections: Vec<(SegmentId, Section<'a>)>,
 }
 
 impl<'a> Views<'a> {
-	pub fn new(leds: &'a mut [[Rgba; LEDS_PER_STRIP]; STRIPS]) -> Self {
-		let [first, second, third, ..] = leds;
-
-		let (section1, rest) = first.split_at_mut(149);
-		let (section2, rest) = rest.split_at_mut(309 - 149);
-		let (section3, rest) = rest.split_at_mut(392 - 309);
-		let (section4, _) = rest.split_at_mut(475 - 392);
-
-		let (section5, rest) = second.split_at_mut(149);
-		let (section6, rest) = rest.split_at_mut(309 - 149);
-		let (section7, rest) = rest.split_at_mut(351 - 309);
-		let (section8, _) = rest.split_at_mut(437 - 351);
-
-		let (section9, rest) = third.split_at_mut(107);
-		let (section10, rest) = rest.split_at_mut(153 - 107);
-		let (section11, rest) = rest.split_at_mut(208 - 153);
-		let (section12, rest) = rest.split_at_mut(308 - 208);
-		let (section13, rest) = rest.split_at_mut(350 - 308);
-		let (section14, rest) = rest.split_at_mut(442 - 350);
-		let (section15, _) = rest.split_at_mut(475 - 442);
-
-		let sections = [
-			Section::new(section1, true),
-			Section::new(section2, true),
-			Section::new(section3, true),
-			Section::new(section4, true),
-			Section::new(section5, true),
-			Section::new(section6, true),
-			Section::new(section7, true),
-			Section::new(section8, true),
-			Section::new(section9, true),
-			Section::new(section10, false),
-			Section::new(section11, false),
-			Section::new(section12, false),
-			Section::new(section13, false),
-			Section::new(section14, false),
-			Section::new(section15, false),
-		];
+	/// Segments that don't fit on the strip are left out, `validate_config` shortens them.
+	pub fn new(leds: &'a mut [[Rgba; LEDS_PER_STRIP]; STRIPS], config: &GlobalConfig) -> Self {
+		let mut sections = vec![];
+
+		for (strip_idx, (leds, strip)) in leds.iter_mut().zip(config.strips.iter()).enumerate() {
+			let mut rest = &mut leds[strip.offset.min(LEDS_PER_STRIP)..];
+
+			for (segment_idx, segment) in strip.segments.iter().enumerate() {
+				if segment.length > rest.len() {
+					break;
+				}
+
+				let (section, tail) = mem::take(&mut rest).split_at_mut(segment.length);
+				rest = tail;
+
+				let id = SegmentId {
+					strip_idx,
+					segment_idx,
+				};
+				sections.push((id, Section::new(section, segment.reversed)));
+			}
+		}
 
 		Views { sections }
 	}
@@ -176,12 +189,26 @@ impl<'a> Views<'a> {
 		self.sections.is_empty()
 	}
 
-	pub fn iter_mut(&mut self) -> IterMut<'_, Section<'a>> {
-		self.sections.iter_mut()
+	pub fn get(&self, id: SegmentId) -> Option<&Section<'a>> {
+		self.sections
+			.iter()
+			.find(|(section_id, _)| *section_id == id)
+			.map(|(_, section)| section)
+	}
+
+	pub fn get_mut(&mut self, id: SegmentId) -> Option<&mut Section<'a>> {
+		self.sections
+			.iter_mut()
+			.find(|(section_id, _)| *section_id == id)
+			.map(|(_, section)| section)
+	}
+
+	pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Section<'a>> {
+		self.sections.iter_mut().map(|(_, section)| section)
 	}
 
-	pub fn iter(&mut self) -> Iter<'_, Section<'a>> {
-		self.sections.iter()
+	pub fn iter(&self) -> impl Iterator<Item = &Section<'a>> {
+		self.sections.iter().map(|(_, section)| section)
 	}
 }
 
@@ -189,13 +216,13 @@ impl<'a> Index<usize> for Views<'a> {
 	type Output = Section<'a>;
 
 	fn index(&self, index: usize) -> &Self::Output {
-		&self.sections[index]
+		&self.sections[index].1
 	}
 }
 
 impl<'a> IndexMut<usize> for Views<'a> {
 	fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-		&mut self.sections[index]
+		&mut self.sections[index].1
 	}
 }
 
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+
+use educe::Educe;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::effects::{
+	config::color::ColorGradient,
+	explosions::{draw_explosions, Explosion},
+	EffectWindow,
+	FrameContext,
+};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+pub struct BeatExplosionsConfig {
+	// #[schema(inline)]
+	colors: ColorGradient,
+
+	#[schema(minimum = 1, maximum = 10)]
+	#[educe(Default = 1)]
+	explosions_per_beat: usize,
+
+	/// Start speed of an explosion at full bass level.
+	#[schema(minimum = 0.0, maximum = 10.0)]
+	#[educe(Default = 1.0)]
+	start_speed: f32,
+
+	#[schema(minimum = 0.00001, maximum = 0.99999)]
+	#[educe(Default = 0.95)]
+	speed_falloff: f32,
+
+	#[schema(minimum = 0.00001, maximum = 0.99999)]
+	#[educe(Default = 0.08)]
+	darken_factor: f32,
+
+	/// React to every onset instead of only beats in the bass.
+	#[educe(Default = false)]
+	onsets: bool,
+}
+
+#[derive(Default)]
+pub struct BeatExplosionsState {
+	/// Count seen on the last frame, `None` until the first frame.
+	count:      Option<u64>,
+	explosions: VecDeque<Explosion>,
+}
+
+pub fn beat_explosions(
+	config: &BeatExplosionsConfig,
+	state: &mut BeatExplosionsState,
+	mut strip: EffectWindow,
+	ctx: &FrameContext,
+) {
+	let count = if config.onsets {
+		ctx.audio.onsets
+	} else {
+		ctx.audio.beats
+	};
+
+	let last = state.count.replace(count);
+	if last.is_some_and(|last| count > last) && !strip.is_empty() {
+		let mut rand = thread_rng();
+		// louder bass makes bigger explosions
+		let speed = config.start_speed * (0.25 + ctx.audio.bass().min(1.0) * 0.75);
+
+		for _ in 0..config.explosions_per_beat {
+			state.explosions.push_back(Explosion {
+				pos: rand.gen_range(0..strip.len() as i32),
+				speed,
+				width: 0.0,
+				col: config.colors.random(),
+			});
+		}
+	}
+
+	draw_explosions(
+		&mut state.explosions,
+		&mut strip,
+		config.speed_falloff,
+		config.darken_factor,
+	);
+}
@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use educe::Educe;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow, FrameContext};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+pub struct BeatFlashConfig {
+	// #[schema(inline)]
+	colors: ColorGradient,
+
+	/// Time it takes for a flash to fade out.
+	#[schema(minimum = 0.001, maximum = 5.0)]
+	#[educe(Default = 0.3)]
+	decay_s: f32,
+
+	/// Brightness between flashes.
+	#[schema(minimum = 0.0, maximum = 1.0)]
+	#[educe(Default = 0.0)]
+	base_brightness: f32,
+
+	/// React to every onset instead of only beats in the bass.
+	#[educe(Default = false)]
+	onsets: bool,
+
+	/// Grow the flash from the center instead of lighting the whole window.
+	#[educe(Default = false)]
+	pulse: bool,
+}
+
+#[derive(Default)]
+pub struct BeatFlashState {
+	/// Count seen on the last frame, `None` until the first frame so a new effect doesn't flash
+	/// for the beats before it was added.
+	count: Option<u64>,
+	color: Hsv,
+	start: Option<Instant>,
+}
+
+pub fn beat_flash(
+	config: &BeatFlashConfig,
+	state: &mut BeatFlashState,
+	mut window: EffectWindow,
+	ctx: &FrameContext,
+) {
+	let count = if config.onsets {
+		ctx.audio.onsets
+	} else {
+		ctx.audio.beats
+	};
+
+	let last = state.count.replace(count);
+	if last.is_some_and(|last| count > last) {
+		state.color = config.colors.random();
+		state.start = Some(ctx.now);
+	}
+
+	let progress = state.start.map_or(1.0, |start| {
+		(ctx.now.saturating_duration_since(start).as_secs_f32() / config.decay_s).min(1.0)
+	});
+	let brightness = config.base_brightness.max(1.0 - progress);
+
+	let color: Rgba = state.color.darken(1.0 - brightness).into();
+
+	if !config.pulse {
+		set_all_raw(&mut window, color);
+		return;
+	}
+
+	let len = window.len();
+	let center = len as f32 / 2.0;
+	let radius = progress * center;
+	for i in 0..len {
+		let distance = (i as f32 + 0.5 - center).abs();
+
+		window[i] = if distance <= radius {
+			color
+		} else {
+			state.color.darken(1.0 - config.base_brightness).into()
+		};
+	}
+}
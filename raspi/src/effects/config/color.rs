@@ -21,6 +21,12 @@ impl Color {
 	}
 }
 
+impl From<Hsv> for Color {
+	fn from(value: Hsv) -> Self {
+		Color { value }
+	}
+}
+
 impl Default for Color {
 	fn default() -> Self {
 		Color {
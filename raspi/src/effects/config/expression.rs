@@ -0,0 +1,660 @@
+use std::{f32::consts::PI, fmt, iter::Peekable, str::CharIndices};
+
+use eyre::{bail, eyre, Result};
+use serde::{Deserialize, Serialize};
+use utoipa::{
+	openapi::{ObjectBuilder, RefOr, SchemaType},
+	ToSchema,
+};
+
+use crate::{
+	color::{Hsv, Rgba},
+	noise,
+};
+
+/// Variables that are set for every pixel, in the order of their slots.
+///
+/// - `i`: index of the led in the window
+/// - `x`: position in the window from 0 to 1
+/// - `n`: number of leds in the window
+/// - `t`: seconds since the effect started
+/// - `seg`: index of the window in the group
+pub const INPUTS: [&str; 5] = ["i", "x", "n", "t", "seg"];
+
+/// Longer programs are rejected, this also bounds how deep chains like `a + a + ...` get.
+const MAX_SOURCE_LEN: usize = 4096;
+/// Nesting of parentheses, signs, powers and calls, deeper programs would overflow the stack
+/// while parsing or evaluating them.
+const MAX_DEPTH: usize = 64;
+
+const DEFAULT_PROGRAM: &str = "h = x * 360 + t * 30; v = (sin(x * 10 + t) + 1) / 2";
+
+/// A list of `name = expression` assignments that is evaluated for every pixel.
+///
+/// Assigning to `h`, `s` and `v` (degrees and `0..1`) produces a HSV color, assigning to `r`, `g`
+/// and `b` (`0..1`) a RGB one, other names can be used as intermediate variables.
+#[derive(Clone)]
+pub struct PixelProgram {
+	source:     String,
+	statements: Vec<(usize, Expr)>,
+	slots:      usize,
+	output:     Output,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Output {
+	Rgb([Option<usize>; 3]),
+	Hsv([Option<usize>; 3]),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+	Const(f32),
+	Var(usize),
+	Neg(Box<Expr>),
+	Binary(BinaryOp, Box<Expr>, Box<Expr>),
+	Call(Func, Vec<Expr>),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum BinaryOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Rem,
+	Pow,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Func {
+	Sin,
+	Cos,
+	Tan,
+	Abs,
+	Floor,
+	Ceil,
+	Fract,
+	Sqrt,
+	Exp,
+	Ln,
+	Pow,
+	Min,
+	Max,
+	Clamp,
+	Mix,
+	Step,
+	Smoothstep,
+	Noise,
+}
+
+impl Func {
+	fn parse(name: &str) -> Option<Self> {
+		let func = match name {
+			"sin" => Func::Sin,
+			"cos" => Func::Cos,
+			"tan" => Func::Tan,
+			"abs" => Func::Abs,
+			"floor" => Func::Floor,
+			"ceil" => Func::Ceil,
+			"fract" => Func::Fract,
+			"sqrt" => Func::Sqrt,
+			"exp" => Func::Exp,
+			"ln" => Func::Ln,
+			"pow" => Func::Pow,
+			"min" => Func::Min,
+			"max" => Func::Max,
+			"clamp" => Func::Clamp,
+			"mix" => Func::Mix,
+			"step" => Func::Step,
+			"smoothstep" => Func::Smoothstep,
+			"noise" => Func::Noise,
+			_ => return None,
+		};
+
+		Some(func)
+	}
+
+	/// Accepted number of arguments.
+	fn arity(&self) -> (usize, usize) {
+		match self {
+			Func::Pow | Func::Min | Func::Max | Func::Step => (2, 2),
+			Func::Clamp | Func::Mix | Func::Smoothstep => (3, 3),
+			Func::Noise => (1, 3),
+			_ => (1, 1),
+		}
+	}
+
+	fn call(&self, args: &[f32]) -> f32 {
+		match self {
+			Func::Sin => args[0].sin(),
+			Func::Cos => args[0].cos(),
+			Func::Tan => args[0].tan(),
+			Func::Abs => args[0].abs(),
+			Func::Floor => args[0].floor(),
+			Func::Ceil => args[0].ceil(),
+			Func::Fract => args[0].rem_euclid(1.0),
+			Func::Sqrt => args[0].sqrt(),
+			Func::Exp => args[0].exp(),
+			Func::Ln => args[0].ln(),
+			Func::Pow => args[0].powf(args[1]),
+			Func::Min => args[0].min(args[1]),
+			Func::Max => args[0].max(args[1]),
+			Func::Clamp => args[0].max(args[1]).min(args[2]),
+			Func::Mix => args[0] + (args[1] - args[0]) * args[2],
+			Func::Step => {
+				if args[1] < args[0] {
+					0.0
+				} else {
+					1.0
+				}
+			}
+			Func::Smoothstep => {
+				let t = ((args[2] - args[0]) / (args[1] - args[0])).clamp(0.0, 1.0);
+				t * t * (3.0 - 2.0 * t)
+			}
+			Func::Noise => noise::simplex3d(
+				args[0],
+				args.get(1).copied().unwrap_or(0.0),
+				args.get(2).copied().unwrap_or(0.0),
+			),
+		}
+	}
+}
+
+impl Expr {
+	fn eval(&self, slots: &[f32]) -> f32 {
+		match self {
+			Expr::Const(value) => *value,
+			Expr::Var(slot) => slots[*slot],
+			Expr::Neg(expr) => -expr.eval(slots),
+			Expr::Binary(op, lhs, rhs) => {
+				let (lhs, rhs) = (lhs.eval(slots), rhs.eval(slots));
+				match op {
+					BinaryOp::Add => lhs + rhs,
+					BinaryOp::Sub => lhs - rhs,
+					BinaryOp::Mul => lhs * rhs,
+					BinaryOp::Div => lhs / rhs,
+					BinaryOp::Rem => lhs.rem_euclid(rhs),
+					BinaryOp::Pow => lhs.powf(rhs),
+				}
+			}
+			Expr::Call(func, args) => {
+				let mut values = [0.0; 3];
+				for (value, arg) in values.iter_mut().zip(args) {
+					*value = arg.eval(slots);
+				}
+				func.call(&values[..args.len()])
+			}
+		}
+	}
+}
+
+impl PixelProgram {
+	pub fn parse(source: &str) -> Result<Self> {
+		if source.len() > MAX_SOURCE_LEN {
+			bail!("longer than {} characters", MAX_SOURCE_LEN);
+		}
+
+		Parser::new(source).program()
+	}
+
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	/// Number of slots needed for [`PixelProgram::eval`].
+	pub fn slots(&self) -> usize {
+		self.slots
+	}
+
+	/// Evaluates the program with the [`INPUTS`] in the first slots.
+	///
+	/// `slots` has to have at least [`PixelProgram::slots`] elements.
+	pub fn eval(&self, slots: &mut [f32]) -> Rgba {
+		for (slot, expr) in self.statements.iter() {
+			slots[*slot] = expr.eval(slots);
+		}
+
+		let get = |slot: Option<usize>, default: f32| {
+			let value = slot.map_or(default, |slot| slots[slot]);
+			if value.is_finite() {
+				value
+			} else {
+				0.0
+			}
+		};
+
+		match self.output {
+			Output::Rgb([r, g, b]) => Rgba::new(
+				get(r, 0.0).clamp(0.0, 1.0),
+				get(g, 0.0).clamp(0.0, 1.0),
+				get(b, 0.0).clamp(0.0, 1.0),
+				1.0,
+			),
+			Output::Hsv([h, s, v]) => Hsv::new(
+				get(h, 0.0).rem_euclid(360.0),
+				get(s, 1.0).clamp(0.0, 1.0),
+				get(v, 1.0).clamp(0.0, 1.0),
+			)
+			.into(),
+		}
+	}
+}
+
+impl Default for PixelProgram {
+	fn default() -> Self {
+		PixelProgram::parse(DEFAULT_PROGRAM).expect("default program should be valid")
+	}
+}
+
+impl fmt::Debug for PixelProgram {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("PixelProgram").field(&self.source).finish()
+	}
+}
+
+impl Serialize for PixelProgram {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.source)
+	}
+}
+
+impl<'de> Deserialize<'de> for PixelProgram {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let source = String::deserialize(deserializer)?;
+		PixelProgram::parse(&source).map_err(serde::de::Error::custom)
+	}
+}
+
+impl<'a> ToSchema<'a> for PixelProgram {
+	fn schema() -> (&'a str, RefOr<utoipa::openapi::Schema>) {
+		(
+			"PixelProgram",
+			ObjectBuilder::new()
+				.schema_type(SchemaType::String)
+				.description(Some(
+					"assignments to h, s, v or r, g, b separated by `;`, with the variables i, x, \
+					 n, t and seg",
+				))
+				.into(),
+		)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+	Number(f32),
+	Ident(String),
+	Op(char),
+	End,
+}
+
+struct Parser<'a> {
+	source: &'a str,
+	chars:  Peekable<CharIndices<'a>>,
+	token:  Token,
+	pos:    usize,
+	depth:  usize,
+
+	/// Names of all slots, starting with the [`INPUTS`].
+	names: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+	fn new(source: &'a str) -> Self {
+		Parser {
+			source,
+			chars: source.char_indices().peekable(),
+			token: Token::End,
+			pos: 0,
+			depth: 0,
+
+			names: INPUTS.iter().map(|name| name.to_string()).collect(),
+		}
+	}
+
+	fn error<T>(&self, message: impl fmt::Display) -> Result<T> {
+		Err(eyre!("{} at column {}", message, self.pos + 1))
+	}
+
+	fn next(&mut self) -> Result<()> {
+		while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+		let Some((pos, c)) = self.chars.next() else {
+			self.pos = self.source.len();
+			self.token = Token::End;
+			return Ok(());
+		};
+		self.pos = pos;
+
+		self.token = if c.is_ascii_digit() || c == '.' {
+			let mut end = pos + c.len_utf8();
+			while let Some((i, c)) = self.chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.') {
+				end = i + c.len_utf8();
+			}
+
+			match self.source[pos..end].parse() {
+				Ok(number) => Token::Number(number),
+				Err(_) => {
+					return self.error(format!("invalid number `{}`", &self.source[pos..end]))
+				}
+			}
+		} else if c.is_ascii_alphabetic() || c == '_' {
+			let mut end = pos + c.len_utf8();
+			while let Some((i, c)) = self
+				.chars
+				.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+			{
+				end = i + c.len_utf8();
+			}
+
+			Token::Ident(self.source[pos..end].to_string())
+		} else if "+-*/%^(),;=".contains(c) {
+			Token::Op(c)
+		} else {
+			return self.error(format!("unexpected character `{}`", c));
+		};
+
+		Ok(())
+	}
+
+	fn expect(&mut self, op: char) -> Result<()> {
+		if self.token != Token::Op(op) {
+			return self.error(format!("expected `{}`", op));
+		}
+		self.next()
+	}
+
+	fn program(mut self) -> Result<PixelProgram> {
+		let mut statements = vec![];
+		let mut rgb = [None; 3];
+		let mut hsv = [None; 3];
+
+		self.next()?;
+		while self.token != Token::End {
+			if self.token == Token::Op(';') {
+				self.next()?;
+				continue;
+			}
+
+			let Token::Ident(name) = self.token.clone() else {
+				return self.error("expected a variable name");
+			};
+			if INPUTS.contains(&name.as_str()) {
+				return self.error(format!("`{}` can't be assigned", name));
+			}
+			if name == "pi" || Func::parse(&name).is_some() {
+				return self.error(format!("`{}` is reserved", name));
+			}
+
+			self.next()?;
+			self.expect('=')?;
+			let expr = self.expr()?;
+
+			let slot = match self.names.iter().position(|n| *n == name) {
+				Some(slot) => slot,
+				None => {
+					self.names.push(name.clone());
+					self.names.len() - 1
+				}
+			};
+
+			match name.as_str() {
+				"r" => rgb[0] = Some(slot),
+				"g" => rgb[1] = Some(slot),
+				"b" => rgb[2] = Some(slot),
+				"h" => hsv[0] = Some(slot),
+				"s" => hsv[1] = Some(slot),
+				"v" => hsv[2] = Some(slot),
+				_ => {}
+			}
+
+			statements.push((slot, expr));
+
+			match self.token {
+				Token::End => {}
+				Token::Op(';') => self.next()?,
+				_ => return self.error("expected `;`"),
+			}
+		}
+
+		let is_rgb = rgb.iter().any(Option::is_some);
+		let is_hsv = hsv.iter().any(Option::is_some);
+		let output = match (is_rgb, is_hsv) {
+			(true, true) => bail!("can't assign both r, g, b and h, s, v"),
+			(true, false) => Output::Rgb(rgb),
+			(false, true) => Output::Hsv(hsv),
+			(false, false) => bail!("nothing assigned, assign to h, s, v or r, g, b"),
+		};
+
+		Ok(PixelProgram {
+			source: self.source.to_string(),
+			statements,
+			slots: self.names.len(),
+			output,
+		})
+	}
+
+	/// `+` and `-`
+	fn expr(&mut self) -> Result<Expr> {
+		let mut lhs = self.term()?;
+
+		loop {
+			let op = match self.token {
+				Token::Op('+') => BinaryOp::Add,
+				Token::Op('-') => BinaryOp::Sub,
+				_ => return Ok(lhs),
+			};
+			self.next()?;
+
+			lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+		}
+	}
+
+	/// `*`, `/` and `%`
+	fn term(&mut self) -> Result<Expr> {
+		let mut lhs = self.unary()?;
+
+		loop {
+			let op = match self.token {
+				Token::Op('*') => BinaryOp::Mul,
+				Token::Op('/') => BinaryOp::Div,
+				Token::Op('%') => BinaryOp::Rem,
+				_ => return Ok(lhs),
+			};
+			self.next()?;
+
+			lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+		}
+	}
+
+	/// Every nested expression goes through here, so this is where the depth is limited.
+	fn unary(&mut self) -> Result<Expr> {
+		if self.depth >= MAX_DEPTH {
+			return self.error(format!("nested deeper than {} levels", MAX_DEPTH));
+		}
+
+		self.depth += 1;
+		let expr = self.unary_inner();
+		self.depth -= 1;
+
+		expr
+	}
+
+	fn unary_inner(&mut self) -> Result<Expr> {
+		match self.token {
+			Token::Op('-') => {
+				self.next()?;
+				Ok(Expr::Neg(Box::new(self.unary()?)))
+			}
+			Token::Op('+') => {
+				self.next()?;
+				self.unary()
+			}
+			_ => self.power(),
+		}
+	}
+
+	/// `^`, right associative
+	fn power(&mut self) -> Result<Expr> {
+		let base = self.atom()?;
+
+		if self.token == Token::Op('^') {
+			self.next()?;
+			return Ok(Expr::Binary(
+				BinaryOp::Pow,
+				Box::new(base),
+				Box::new(self.unary()?),
+			));
+		}
+
+		Ok(base)
+	}
+
+	fn atom(&mut self) -> Result<Expr> {
+		match self.token.clone() {
+			Token::Number(number) => {
+				self.next()?;
+				Ok(Expr::Const(number))
+			}
+			Token::Op('(') => {
+				self.next()?;
+				let expr = self.expr()?;
+				self.expect(')')?;
+				Ok(expr)
+			}
+			Token::Ident(name) => {
+				let pos = self.pos;
+				self.next()?;
+
+				if self.token == Token::Op('(') {
+					return self.call(&name, pos);
+				}
+
+				if name == "pi" {
+					return Ok(Expr::Const(PI));
+				}
+
+				match self.names.iter().position(|n| *n == name) {
+					Some(slot) => Ok(Expr::Var(slot)),
+					None => {
+						self.pos = pos;
+						self.error(format!("unknown variable `{}`", name))
+					}
+				}
+			}
+			Token::End => self.error("unexpected end"),
+			Token::Op(op) => self.error(format!("unexpected `{}`", op)),
+		}
+	}
+
+	fn call(&mut self, name: &str, pos: usize) -> Result<Expr> {
+		let Some(func) = Func::parse(name) else {
+			self.pos = pos;
+			return self.error(format!("unknown function `{}`", name));
+		};
+
+		self.expect('(')?;
+		let mut args = vec![];
+		if self.token != Token::Op(')') {
+			args.push(self.expr()?);
+			while self.token == Token::Op(',') {
+				self.next()?;
+				args.push(self.expr()?);
+			}
+		}
+		self.expect(')')?;
+
+		let (min, max) = func.arity();
+		if args.len() < min || args.len() > max {
+			self.pos = pos;
+			return self.error(format!(
+				"`{}` takes {} argument{}, got {}",
+				name,
+				if min == max {
+					min.to_string()
+				} else {
+					format!("{} to {}", min, max)
+				},
+				if max == 1 { "" } else { "s" },
+				args.len()
+			));
+		}
+
+		Ok(Expr::Call(func, args))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Values of the assigned variables, in the order they were first assigned.
+	fn eval(source: &str, inputs: [f32; 5]) -> Vec<f32> {
+		let program = PixelProgram::parse(source).unwrap();
+		let mut slots = vec![0.0; program.slots()];
+		slots[..INPUTS.len()].copy_from_slice(&inputs);
+		program.eval(&mut slots);
+		slots[INPUTS.len()..].to_vec()
+	}
+
+	#[test]
+	fn precedence() {
+		assert_eq!(eval("v = 1 + 2 * 3 - 4 / 2", [0.0; 5]), [5.0]);
+		assert_eq!(eval("v = (1 + 2) * 3", [0.0; 5]), [9.0]);
+		assert_eq!(eval("v = -2 ^ 2", [0.0; 5]), [-4.0]);
+		assert_eq!(eval("v = 2 ^ 3 ^ 2", [0.0; 5]), [512.0]);
+		assert_eq!(eval("v = -7 % 3", [0.0; 5]), [2.0]);
+	}
+
+	#[test]
+	fn variables_and_functions() {
+		let values = eval(
+			"a = i * 2; v = max(a, n) + clamp(x, 0, 1); h = pi",
+			[3.0, 0.5, 4.0, 0.0, 0.0],
+		);
+		assert_eq!(values, [6.0, 6.5, PI]);
+	}
+
+	#[test]
+	fn output() {
+		let program = PixelProgram::parse("r = 1; g = 0.5").unwrap();
+		assert_eq!(program.output, Output::Rgb([Some(5), Some(6), None]));
+
+		let program = PixelProgram::parse("t2 = t; h = t2").unwrap();
+		assert_eq!(program.output, Output::Hsv([Some(6), None, None]));
+
+		assert!(PixelProgram::parse("r = 1; h = 1").is_err());
+		assert!(PixelProgram::parse("a = 1").is_err());
+	}
+
+	#[test]
+	fn errors() {
+		let error = |source: &str| PixelProgram::parse(source).unwrap_err().to_string();
+
+		assert_eq!(error("v = y"), "unknown variable `y` at column 5");
+		assert_eq!(error("v = foo(1)"), "unknown function `foo` at column 5");
+		assert_eq!(
+			error("v = min(1)"),
+			"`min` takes 2 arguments, got 1 at column 5"
+		);
+		assert_eq!(error("v = (1"), "expected `)` at column 7");
+		assert_eq!(error("x = 1"), "`x` can't be assigned at column 1");
+		assert_eq!(error("sin = 1"), "`sin` is reserved at column 1");
+		assert_eq!(error("v = 1 $"), "unexpected character `$` at column 7");
+	}
+
+	#[test]
+	fn depth_limit() {
+		let nested = |depth: usize| format!("v = {}1{}", "(".repeat(depth), ")".repeat(depth));
+		assert!(PixelProgram::parse(&nested(MAX_DEPTH - 2)).is_ok());
+		assert!(PixelProgram::parse(&nested(MAX_DEPTH)).is_err());
+
+		let signs = format!("v = {}1", "-".repeat(MAX_DEPTH));
+		assert!(PixelProgram::parse(&signs).is_err());
+
+		let long = format!("v = {}1", "(".repeat(200_000));
+		assert!(PixelProgram::parse(&long).is_err());
+	}
+}
@@ -1 +1,2 @@
 pub mod color;
+pub mod expression;
@@ -9,7 +9,7 @@ use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow};
+use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow, FrameContext};
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
 #[educe(Default)]
@@ -34,12 +34,12 @@ pub struct ExplosionsConfig {
 	darken_factor: f32,
 }
 
-struct Explosion {
+pub(crate) struct Explosion {
 	// strip: usize,
-	pos:   i32,
-	speed: f32,
-	width: f32,
-	col:   Hsv,
+	pub(crate) pos:   i32,
+	pub(crate) speed: f32,
+	pub(crate) width: f32,
+	pub(crate) col:   Hsv,
 }
 
 #[derive(Default)]
@@ -48,13 +48,18 @@ pub struct ExplosionsState {
 	explosions: VecDeque<Explosion>,
 }
 
-pub fn explosions(config: &ExplosionsConfig, state: &mut ExplosionsState, mut strip: EffectWindow) {
+pub fn explosions(
+	config: &ExplosionsConfig,
+	state: &mut ExplosionsState,
+	mut strip: EffectWindow,
+	ctx: &FrameContext,
+) {
 	let duration = Duration::from_secs_f32(config.explosion_interval);
 
 	let mut rand = thread_rng();
 
-	let now = Instant::now();
-	let last = state.last.unwrap_or(Instant::now());
+	let now = ctx.now;
+	let last = state.last.unwrap_or(now);
 
 	if now - last > duration {
 		let pos = rand.gen_range(0..strip.len() as i32);
@@ -69,17 +74,32 @@ pub fn explosions(config: &ExplosionsConfig, state: &mut ExplosionsState, mut st
 		state.last = Some(now);
 	}
 
+	draw_explosions(
+		&mut state.explosions,
+		&mut strip,
+		config.speed_falloff,
+		config.darken_factor,
+	);
+}
+
+/// Darkens the window and advances and draws all explosions, removing the ones that stopped.
+pub(crate) fn draw_explosions(
+	explosions: &mut VecDeque<Explosion>,
+	strip: &mut EffectWindow,
+	speed_falloff: f32,
+	darken_factor: f32,
+) {
 	for led in strip.iter_mut() {
-		*led = led.darken(config.darken_factor).into();
+		*led = led.darken(darken_factor).into();
 	}
 
 	let mut pop_count = 0;
-	for explosion in state.explosions.iter_mut() {
+	for explosion in explosions.iter_mut() {
 		// let start_width = explosion.width;
 		explosion.width += explosion.speed;
 		let end_width = explosion.width;
 
-		explosion.speed *= config.speed_falloff;
+		explosion.speed *= speed_falloff;
 		if explosion.speed <= 0.05 {
 			pop_count += 1;
 		}
@@ -115,6 +135,6 @@ pub fn explosions(config: &ExplosionsConfig, state: &mut ExplosionsState, mut st
 	}
 
 	for _ in 0..pop_count {
-		state.explosions.pop_front();
+		explosions.pop_front();
 	}
 }
@@ -0,0 +1,54 @@
+use std::time::Instant;
+
+use educe::Educe;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::effects::{
+	config::expression::{PixelProgram, INPUTS},
+	EffectWindow,
+	FrameContext,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+pub struct ExpressionConfig {
+	/// Evaluated for every led, e.g. `h = x * 360 + t * 30; v = sin(x * 10 + t)`.
+	program: PixelProgram,
+
+	/// Multiplier for `t`.
+	#[schema(minimum = 0.0, maximum = 10.0)]
+	#[educe(Default = 1.0)]
+	speed: f32,
+}
+
+#[derive(Default)]
+pub struct ExpressionState {
+	start: Option<Instant>,
+	slots: Vec<f32>,
+}
+
+pub fn expression(
+	config: &ExpressionConfig,
+	state: &mut ExpressionState,
+	mut window: EffectWindow,
+	ctx: &FrameContext,
+) {
+	let start = *state.start.get_or_insert(ctx.now);
+	let t = ctx.now.saturating_duration_since(start).as_secs_f32() * config.speed;
+
+	state.slots.resize(config.program.slots(), 0.0);
+
+	let len = window.len();
+	for (i, led) in window.iter_mut().enumerate() {
+		state.slots[..INPUTS.len()].copy_from_slice(&[
+			i as f32,
+			i as f32 / (len.max(2) - 1) as f32,
+			len as f32,
+			t,
+			ctx.segment as f32,
+		]);
+
+		*led = config.program.eval(&mut state.slots);
+	}
+}
@@ -4,7 +4,7 @@ use educe::Educe;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow};
+use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow, FrameContext};
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
 #[educe(Default)]
@@ -30,9 +30,10 @@ pub fn flash_rainbow(
 	config: &FlashRainbowConfig,
 	state: &mut FlashRainbowState,
 	mut window: EffectWindow,
+	ctx: &FrameContext,
 ) {
 	let period = Duration::from_secs_f32(config.period);
-	let t = state.timer.tick(period);
+	let t = state.timer.tick(period, ctx.now);
 
 	if t.triggered {
 		let color = config.colors.random();
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 use crate::{
-	effects::{config::color::ColorGradient, prelude::*, EffectWindow},
+	effects::{config::color::ColorGradient, prelude::*, EffectWindow, FrameContext},
 	noise,
 };
 
@@ -46,9 +46,10 @@ pub fn flash_rainbow_noise(
 	config: &FlashRainbowNoiseConfig,
 	state: &mut FlashRainbowNoiseState,
 	mut window: EffectWindow,
+	ctx: &FrameContext,
 ) {
 	let period = Duration::from_secs_f32(config.period);
-	let t = state.timer.tick(period);
+	let t = state.timer.tick(period, ctx.now);
 
 	state.counter += config.speed;
 
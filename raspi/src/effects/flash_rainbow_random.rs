@@ -5,7 +5,7 @@ use rand::random;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow};
+use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow, FrameContext};
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
 #[educe(Default)]
@@ -44,9 +44,10 @@ pub fn flash_rainbow_random(
 	config: &FlashRainbowRandomConfig,
 	state: &mut FlashRainbowRandomState,
 	mut window: EffectWindow,
+	ctx: &FrameContext,
 ) {
 	let period = Duration::from_secs_f32(config.period);
-	let t = state.timer.tick(period);
+	let t = state.timer.tick(period, ctx.now);
 
 	if !t.triggered {
 		if t.percentage > config.on_percentage {
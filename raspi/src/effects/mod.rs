@@ -1,34 +1,53 @@
-use std::{fmt::Debug, marker::PhantomData};
+use std::{fmt::Debug, marker::PhantomData, time::Instant};
 
-use eyre::Result;
+use eyre::{bail, Result};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use utoipa::{
 	openapi::{RefOr, Schema},
 	ToSchema,
 };
 
-use crate::{config::WithConfig, controller::Section};
+use crate::{
+	audio::AudioFrame,
+	color::Rgba,
+	config::WithConfig,
+	controller::Section,
+	runner::EffectTarget,
+	stream::PixelStreams,
+};
 
 pub mod balls;
+pub mod beat_explosions;
+pub mod beat_flash;
 pub mod config;
 pub mod explosions;
+pub mod expression;
 pub mod flash_rainbow;
 pub mod flash_rainbow_noise;
 pub mod flash_rainbow_random;
 pub mod meteors;
 pub mod moving_lights;
+pub mod plugin;
 // pub mod police;
 pub mod prelude;
 pub mod rainbow;
 pub mod random;
 // pub mod schema;
+pub mod script;
 pub mod snake;
 pub mod solid;
+pub mod spectrum;
 pub mod static_rainbow;
+pub mod stream;
+pub mod validate;
+pub mod vu_meter;
 
 pub use crate::effects::{
 	balls::balls,
+	beat_explosions::beat_explosions,
+	beat_flash::beat_flash,
 	explosions::explosions,
+	expression::expression,
 	flash_rainbow::flash_rainbow,
 	flash_rainbow_noise::flash_rainbow_noise,
 	flash_rainbow_random::flash_rainbow_random,
@@ -38,35 +57,79 @@ pub use crate::effects::{
 	random::random,
 	snake::snake,
 	solid::solid,
+	spectrum::spectrum,
 	static_rainbow::static_rainbow,
+	stream::stream,
+	vu_meter::vu_meter,
 };
 
 pub trait EffectFactory: Send {
 	fn schema(&self) -> Schema;
 	fn default_config(&self) -> Result<serde_json::Value>;
 	fn build(&self, config: serde_json::Value) -> Result<Box<dyn Effect>>;
+
+	/// Checks what the schema can't, e.g. that an expression parses, without building an effect.
+	fn check_config(&self, config: &serde_json::Value) -> Result<()> {
+		let _ = config;
+		Ok(())
+	}
+
+	/// Version of the config, stored configs with an older version get migrated with
+	/// [`EffectFactory::migrate_config`] before they're used.
+	fn config_version(&self) -> u32 {
+		0
+	}
+
+	/// Migrates a config from `version` to `version + 1`.
+	fn migrate_config(&self, version: u32, config: &mut serde_json::Value) -> Result<()> {
+		let _ = (version, config);
+		Ok(())
+	}
 }
 
-pub struct FnEffectFactory<C, S, F> {
-	func: F,
+/// Migrates a stored config to the next version, e.g. by renaming a field.
+pub type ConfigMigration = fn(&mut serde_json::Value) -> Result<()>;
+
+pub struct FnEffectFactory<C, S, A, F> {
+	func:       F,
+	migrations: &'static [ConfigMigration],
+	stepped:    bool,
 
 	_marker: PhantomData<fn() -> (C, S)>,
+	_args:   PhantomData<fn() -> A>,
 }
 
-impl<C, S, F> FnEffectFactory<C, S, F> {
+impl<C, S, A, F> FnEffectFactory<C, S, A, F> {
 	pub fn new(func: F) -> Self {
 		Self {
 			func,
+			migrations: &[],
+			stepped: false,
 			_marker: PhantomData,
+			_args: PhantomData,
 		}
 	}
+
+	/// Adds migrations for changes to the config, the first one migrates from version 0 (configs
+	/// stored before there were any migrations) to 1 and so on.
+	pub fn with_migrations(mut self, migrations: &'static [ConfigMigration]) -> Self {
+		self.migrations = migrations;
+		self
+	}
+
+	/// For effects that move by a fixed step every time they run, see [`Effect::stepped`].
+	pub fn stepped(mut self) -> Self {
+		self.stepped = true;
+		self
+	}
 }
 
-impl<C, S, F> EffectFactory for FnEffectFactory<C, S, F>
+impl<C, S, A, F> EffectFactory for FnEffectFactory<C, S, A, F>
 where
 	C: Default + Serialize + DeserializeOwned + for<'a> ToSchema<'a> + Send + Sync + 'static,
 	S: Default + Send + Sync + 'static,
-	F: EffectFn<C, S> + Send + Sync + Clone + 'static,
+	A: 'static,
+	F: EffectFn<C, S, A> + Send + Sync + Clone + 'static,
 {
 	fn schema(&self) -> Schema {
 		match C::schema().1 {
@@ -85,7 +148,27 @@ where
 	}
 
 	fn build(&self, config: serde_json::Value) -> Result<Box<dyn Effect>> {
-		Ok(Box::new(EffectState::new(self.func.clone(), config)?))
+		Ok(Box::new(EffectState::new(
+			self.func.clone(),
+			config,
+			self.stepped,
+		)?))
+	}
+
+	fn check_config(&self, config: &serde_json::Value) -> Result<()> {
+		C::deserialize(config)?;
+		Ok(())
+	}
+
+	fn config_version(&self) -> u32 {
+		self.migrations.len() as u32
+	}
+
+	fn migrate_config(&self, version: u32, config: &mut serde_json::Value) -> Result<()> {
+		match self.migrations.get(version as usize) {
+			Some(migration) => migration(config),
+			None => bail!("no migration from version {}", version),
+		}
 	}
 }
 
@@ -94,11 +177,50 @@ type EffectWindow<'a> = Section<'a>;
 // 	Single(Section<'a>),
 // }
 
+/// Everything besides the config and state an effect might need to render a frame.
+pub struct FrameContext<'a> {
+	pub audio:   &'a AudioFrame,
+	pub streams: &'a PixelStreams,
+	/// The segment or group the effect is running on.
+	pub target:  &'a EffectTarget,
+	/// Index of the window in the group the effect is running on, `0` for single segments.
+	pub segment: usize,
+	/// Time of the frame, use it instead of [`Instant::now`] so the speed modifier of
+	/// the target applies.
+	pub now:     Instant,
+}
+
 pub trait Effect: WithConfig<Config = serde_json::Value> + Send + Sync {
-	fn run(&mut self, window: EffectWindow);
+	fn run(&mut self, window: EffectWindow, ctx: &FrameContext);
+
+	/// Whether the effect moves by a fixed step every time it runs instead of following
+	/// [`FrameContext::now`]. The speed modifier runs those more or less often per frame, all
+	/// others run once per frame with the time sped up or slowed down.
+	fn stepped(&self) -> bool {
+		false
+	}
+}
+
+/// Replaces an instance that panicked, keeps its window black until the state is set again.
+pub struct FailedEffect;
+
+impl WithConfig for FailedEffect {
+	type Config = serde_json::Value;
+
+	fn set_config(&mut self, _config: Self::Config) -> Result<()> {
+		Ok(())
+	}
+}
+
+impl Effect for FailedEffect {
+	fn run(&mut self, mut window: EffectWindow, _ctx: &FrameContext) {
+		for led in window.iter_mut() {
+			*led = Rgba::default();
+		}
+	}
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct EffectData {
 	pub id:             String,
 	pub name:           String,
@@ -106,8 +228,12 @@ pub struct EffectData {
 	pub default_config: serde_json::Value,
 }
 
-pub trait EffectFn<C, S> {
-	fn call(&self, config: &C, state: &mut S, window: EffectWindow);
+/// Marker for effect functions that take a [`FrameContext`] as the last argument.
+pub struct WithContext;
+
+/// `A` is only there to allow effect functions with and without the [`FrameContext`] argument.
+pub trait EffectFn<C, S, A = ()> {
+	fn call(&self, config: &C, state: &mut S, window: EffectWindow, ctx: &FrameContext);
 }
 
 impl<C, S, F> EffectFn<C, S> for F
@@ -116,11 +242,22 @@ where
 	S: Default,
 	F: Fn(&C, &mut S, EffectWindow) + Send + Sync,
 {
-	fn call(&self, config: &C, state: &mut S, window: EffectWindow) {
+	fn call(&self, config: &C, state: &mut S, window: EffectWindow, _: &FrameContext) {
 		(self)(config, state, window)
 	}
 }
 
+impl<C, S, F> EffectFn<C, S, WithContext> for F
+where
+	C: Default,
+	S: Default,
+	F: Fn(&C, &mut S, EffectWindow, &FrameContext) + Send + Sync,
+{
+	fn call(&self, config: &C, state: &mut S, window: EffectWindow, ctx: &FrameContext) {
+		(self)(config, state, window, ctx)
+	}
+}
+
 // impl<C, S, F> EffectFn<C, S> for F
 // where
 // 	C: Default,
@@ -161,55 +298,66 @@ where
 // 	}
 // }
 
-struct EffectState<C, S, F>
+struct EffectState<C, S, A, F>
 where
-	F: EffectFn<C, S>,
+	F: EffectFn<C, S, A>,
 {
-	config: C,
-	state:  S,
-	func:   F,
+	config:  C,
+	state:   S,
+	func:    F,
+	stepped: bool,
+
+	_args: PhantomData<fn() -> A>,
 }
 
-impl<C, S, F> EffectState<C, S, F>
+impl<C, S, A, F> EffectState<C, S, A, F>
 where
 	C: Default + DeserializeOwned,
 	S: Default,
-	F: EffectFn<C, S>,
+	F: EffectFn<C, S, A>,
 {
-	fn new(func: F, config: serde_json::Value) -> Result<Self> {
+	fn new(func: F, config: serde_json::Value, stepped: bool) -> Result<Self> {
 		let config = serde_json::from_value(config)?;
 
 		Ok(Self {
 			config,
 			state: Default::default(),
 			func,
+			stepped,
+
+			_args: PhantomData,
 		})
 	}
 }
 
-impl<C, S, F> WithConfig for EffectState<C, S, F>
+impl<C, S, A, F> WithConfig for EffectState<C, S, A, F>
 where
 	C: Default + Serialize + DeserializeOwned + for<'a> ToSchema<'a> + Send + Sync,
 	S: Default + Send + Sync,
-	F: EffectFn<C, S> + Send + Sync,
+	F: EffectFn<C, S, A> + Send + Sync,
 {
 	type Config = serde_json::Value;
 
 	fn set_config(&mut self, value: Self::Config) -> Result<()> {
-		// TODO: validation
+		// validated against the schema by the runner for api writes, configs loaded from disk are
+		// only deserialized
 		self.config = serde_json::from_value(value)?;
 		Ok(())
 	}
 }
 
-impl<C, S, F> Effect for EffectState<C, S, F>
+impl<C, S, A, F> Effect for EffectState<C, S, A, F>
 where
 	C: Default + Serialize + DeserializeOwned + for<'a> ToSchema<'a> + Send + Sync,
 	S: Default + Send + Sync,
-	F: EffectFn<C, S> + Send + Sync,
+	F: EffectFn<C, S, A> + Send + Sync,
 {
-	fn run(&mut self, window: EffectWindow) {
-		self.func.call(&self.config, &mut self.state, window)
+	fn run(&mut self, window: EffectWindow, ctx: &FrameContext) {
+		self.func.call(&self.config, &mut self.state, window, ctx)
+	}
+
+	fn stepped(&self) -> bool {
+		self.stepped
 	}
 }
 
@@ -7,7 +7,7 @@ use educe::Educe;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::effects::{prelude::*, EffectWindow};
+use crate::effects::{prelude::*, EffectWindow, FrameContext};
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
 #[educe(Default)]
@@ -27,7 +27,7 @@ pub struct MovingLightsConfig {
 
 pub struct MovingLightsState {
 	anim:            MovingLightStripsAnimation,
-	next_light_time: Instant,
+	next_light_time: Option<Instant>,
 	timer:           TimerState,
 }
 
@@ -35,7 +35,7 @@ impl Default for MovingLightsState {
 	fn default() -> Self {
 		MovingLightsState {
 			anim:            MovingLightStripsAnimation::new(NUM_LEDS, 15),
-			next_light_time: Instant::now(),
+			next_light_time: None,
 			timer:           TimerState::default(),
 		}
 	}
@@ -45,16 +45,17 @@ pub fn moving_lights(
 	config: &MovingLightsConfig,
 	state: &mut MovingLightsState,
 	mut window: EffectWindow,
+	ctx: &FrameContext,
 ) {
 	let frequency_s = Duration::from_secs_f32(1.0 / config.frequency);
 
-	let now = Instant::now();
-	if now >= state.next_light_time {
+	let now = ctx.now;
+	if state.next_light_time.map_or(true, |time| now >= time) {
 		state.anim.add_next_light_impulse();
-		state.next_light_time = now.add(Duration::from_secs_f32(config.pulse_delay_s))
+		state.next_light_time = Some(now.add(Duration::from_secs_f32(config.pulse_delay_s)))
 	}
 
-	if state.timer.tick(frequency_s).triggered {
+	if state.timer.tick(frequency_s, now).triggered {
 		state.anim.shift_all_pixels();
 	}
 
@@ -0,0 +1,374 @@
+//! Effects loaded from WebAssembly modules at runtime.
+//!
+//! There is no WASI, a plugin only talks to the host through these exports:
+//!
+//! - `memory`: the linear memory used to exchange data
+//! - `alloc(len: i32) -> i32`: allocates `len` bytes and returns the pointer
+//! - `schema() -> i64`: JSON schema of the config as a packed `(ptr << 32) | len` string
+//! - `default_config() -> i64`: the default config as a packed JSON string
+//! - `init(ptr: i32, len: i32) -> i32`: (re)configures the plugin with a JSON config, returns 0 on
+//!   success
+//! - `render(ptr: i32, len: i32, time: f32)`: renders `len` LEDs as RGB bytes to `ptr`, `time` is
+//!   the number of seconds since the effect was created
+//!
+//! Optionally it can import `env.log(ptr: i32, len: i32)` and `env.noise(x: f32, y: f32, z: f32) ->
+//! f32`.
+//!
+//! Every call gets a fixed amount of fuel and the memory of a plugin can't grow beyond
+//! [`MAX_MEMORY`]. A plugin that runs out of fuel (or traps otherwise), or that keeps rendering
+//! slower than [`SLOW_RENDER`], is disabled until it gets a new config.
+
+use std::{
+	fs,
+	path::Path,
+	time::{Duration, Instant},
+};
+
+use eyre::{bail, eyre, ContextCompat, Result, WrapErr};
+use tracing::{error, info, warn};
+use utoipa::openapi::Schema;
+use wasmi::{
+	Caller,
+	Config,
+	Engine,
+	Extern,
+	Instance,
+	Linker,
+	Memory,
+	Module,
+	Store,
+	StoreLimits,
+	StoreLimitsBuilder,
+	TypedFunc,
+};
+
+use crate::{
+	color::Rgba,
+	config::WithConfig,
+	effects::{prelude::clear_all_raw, Effect, EffectFactory, EffectWindow, FrameContext},
+	noise,
+};
+
+/// Prefix of the effect id of plugin effects, followed by the file name without extension.
+pub const PLUGIN_EFFECT_PREFIX: &str = "plugin:";
+
+/// Fuel for a single call into a plugin, roughly the number of executed instructions.
+const FUEL_PER_CALL: u64 = 5_000_000;
+
+/// Upper limit for the linear memory of a plugin, growing beyond it fails.
+const MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+/// Upper limit for the elements of the function table of a plugin.
+const MAX_TABLE_ELEMENTS: u32 = 10_000;
+
+/// Renders taking longer than this get logged.
+const SLOW_RENDER: Duration = Duration::from_millis(10);
+
+/// A plugin is disabled after this many renders in a row took longer than [`SLOW_RENDER`].
+const MAX_SLOW_RENDERS: u32 = 50;
+
+pub fn plugin_effect_id(name: &str) -> String {
+	format!("{}{}", PLUGIN_EFFECT_PREFIX, name)
+}
+
+struct PluginHost {
+	name:   String,
+	limits: StoreLimits,
+}
+
+pub struct PluginEffectFactory {
+	name:   String,
+	engine: Engine,
+	module: Module,
+
+	schema:         Schema,
+	default_config: serde_json::Value,
+}
+
+impl PluginEffectFactory {
+	pub fn load(path: &Path) -> Result<Self> {
+		let name = path
+			.file_stem()
+			.and_then(|name| name.to_str())
+			.wrap_err("plugin file name is not valid utf-8")?
+			.to_string();
+
+		let bytes = fs::read(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+
+		let mut config = Config::default();
+		config.consume_fuel(true);
+		let engine = Engine::new(&config);
+		let module = Module::new(&engine, &bytes[..])?;
+
+		let mut instance = PluginInstance::new(&name, &engine, &module)?;
+
+		let schema = serde_json::from_str(&instance.call_string("schema")?)
+			.wrap_err("parsing the schema of the plugin")?;
+		let default_config = serde_json::from_str(&instance.call_string("default_config")?)
+			.wrap_err("parsing the default config of the plugin")?;
+
+		info!("loaded plugin {} from {}", name, path.display());
+
+		Ok(PluginEffectFactory {
+			name,
+			engine,
+			module,
+
+			schema,
+			default_config,
+		})
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+}
+
+impl EffectFactory for PluginEffectFactory {
+	fn schema(&self) -> Schema {
+		self.schema.clone()
+	}
+
+	fn default_config(&self) -> Result<serde_json::Value> {
+		Ok(self.default_config.clone())
+	}
+
+	fn build(&self, config: serde_json::Value) -> Result<Box<dyn Effect>> {
+		let mut effect = PluginEffect {
+			instance: PluginInstance::new(&self.name, &self.engine, &self.module)?,
+			start:    None,
+			failed:   false,
+
+			slow_renders: 0,
+		};
+		effect.set_config(config)?;
+
+		Ok(Box::new(effect))
+	}
+}
+
+struct PluginInstance {
+	store:  Store<PluginHost>,
+	memory: Memory,
+
+	instance: Instance,
+	alloc:    TypedFunc<i32, i32>,
+	init:     TypedFunc<(i32, i32), i32>,
+	render:   TypedFunc<(i32, i32, f32), ()>,
+
+	/// pointer and capacity (in LEDs) of the pixel buffer in the plugin memory.
+	pixels: Option<(i32, usize)>,
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+	(
+		(packed as u64 >> 32) as usize,
+		(packed as u64 & 0xffff_ffff) as usize,
+	)
+}
+
+fn read_memory(memory: &[u8], ptr: usize, len: usize) -> Result<&[u8]> {
+	memory
+		.get(ptr..ptr.saturating_add(len))
+		.wrap_err_with(|| format!("plugin returned out of bounds memory {}+{}", ptr, len))
+}
+
+impl PluginInstance {
+	fn new(name: &str, engine: &Engine, module: &Module) -> Result<Self> {
+		let mut store = Store::new(
+			engine,
+			PluginHost {
+				name:   name.to_string(),
+				limits: StoreLimitsBuilder::new()
+					.memory_size(MAX_MEMORY)
+					.memories(1)
+					.tables(1)
+					.table_elements(MAX_TABLE_ELEMENTS)
+					.instances(1)
+					.build(),
+			},
+		);
+		store.limiter(|host| &mut host.limits);
+		store.set_fuel(FUEL_PER_CALL)?;
+
+		let mut linker = <Linker<PluginHost>>::new(engine);
+		linker.func_wrap(
+			"env",
+			"log",
+			|caller: Caller<'_, PluginHost>, ptr: i32, len: i32| {
+				let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+					return;
+				};
+
+				if let Ok(message) = read_memory(memory.data(&caller), ptr as usize, len as usize) {
+					info!(
+						"plugin {}: {}",
+						caller.data().name,
+						String::from_utf8_lossy(message)
+					);
+				}
+			},
+		)?;
+		linker.func_wrap("env", "noise", |x: f32, y: f32, z: f32| {
+			noise::simplex3d(x, y, z)
+		})?;
+
+		let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+
+		let memory = instance
+			.get_memory(&store, "memory")
+			.wrap_err("plugin does not export `memory`")?;
+		let alloc = instance.get_typed_func(&store, "alloc")?;
+		let init = instance.get_typed_func(&store, "init")?;
+		let render = instance.get_typed_func(&store, "render")?;
+
+		Ok(PluginInstance {
+			store,
+			memory,
+
+			instance,
+			alloc,
+			init,
+			render,
+
+			pixels: None,
+		})
+	}
+
+	fn refuel(&mut self) -> Result<()> {
+		self.store.set_fuel(FUEL_PER_CALL)?;
+		Ok(())
+	}
+
+	fn call_string(&mut self, export: &str) -> Result<String> {
+		self.refuel()?;
+
+		let func = self
+			.instance
+			.get_typed_func::<(), i64>(&self.store, export)
+			.wrap_err_with(|| format!("plugin does not export `{}`", export))?;
+		let (ptr, len) = unpack(func.call(&mut self.store, ())?);
+
+		let bytes = read_memory(self.memory.data(&self.store), ptr, len)?;
+
+		Ok(String::from_utf8(bytes.to_vec())?)
+	}
+
+	fn configure(&mut self, config: &serde_json::Value) -> Result<()> {
+		self.refuel()?;
+
+		let bytes = serde_json::to_vec(config)?;
+		let ptr = self.alloc.call(&mut self.store, bytes.len() as i32)?;
+		self.memory.write(&mut self.store, ptr as usize, &bytes)?;
+
+		let result = self.init.call(&mut self.store, (ptr, bytes.len() as i32))?;
+		if result != 0 {
+			bail!("plugin rejected the config (error code {})", result);
+		}
+
+		Ok(())
+	}
+
+	fn render(&mut self, len: usize, time: f32) -> Result<&[u8]> {
+		self.refuel()?;
+
+		let ptr = match self.pixels {
+			Some((ptr, capacity)) if capacity >= len => ptr,
+			_ => {
+				let ptr = self.alloc.call(&mut self.store, (len * 3) as i32)?;
+				self.pixels = Some((ptr, len));
+				ptr
+			}
+		};
+
+		self.render.call(&mut self.store, (ptr, len as i32, time))?;
+
+		read_memory(self.memory.data(&self.store), ptr as usize, len * 3)
+	}
+}
+
+struct PluginEffect {
+	instance: PluginInstance,
+	/// Set on the first frame, from the clock of the [`FrameContext`].
+	start:    Option<Instant>,
+	failed:   bool,
+
+	/// Number of renders in a row that were slower than [`SLOW_RENDER`].
+	slow_renders: u32,
+}
+
+impl WithConfig for PluginEffect {
+	type Config = serde_json::Value;
+
+	fn set_config(&mut self, config: Self::Config) -> Result<()> {
+		self.instance
+			.configure(&config)
+			.map_err(|err| eyre!("{:#}", err))?;
+		self.failed = false;
+		self.slow_renders = 0;
+
+		Ok(())
+	}
+}
+
+impl Effect for PluginEffect {
+	fn run(&mut self, mut window: EffectWindow, ctx: &FrameContext) {
+		if self.failed {
+			clear_all_raw(&mut window);
+			return;
+		}
+
+		let start = Instant::now();
+		let time = ctx
+			.now
+			.saturating_duration_since(*self.start.get_or_insert(ctx.now))
+			.as_secs_f32();
+
+		match self.instance.render(window.len(), time) {
+			Ok(pixels) => {
+				for (led, rgb) in window.iter_mut().zip(pixels.chunks_exact(3)) {
+					*led = Rgba::new(
+						rgb[0] as f32 / 255.0,
+						rgb[1] as f32 / 255.0,
+						rgb[2] as f32 / 255.0,
+						1.0,
+					);
+				}
+			}
+			Err(err) => {
+				error!(
+					"plugin {} failed, disabling it until it gets a new config: {:#}",
+					self.instance.store.data().name,
+					err
+				);
+				self.failed = true;
+				clear_all_raw(&mut window);
+			}
+		}
+
+		let elapsed = start.elapsed();
+		if elapsed <= SLOW_RENDER {
+			self.slow_renders = 0;
+			return;
+		}
+
+		self.slow_renders += 1;
+		if self.slow_renders < MAX_SLOW_RENDERS {
+			warn!(
+				"plugin {} took {:?} to render",
+				self.instance.store.data().name,
+				elapsed
+			);
+			return;
+		}
+
+		error!(
+			"plugin {} was slower than {:?} for {} renders in a row, disabling it until it gets a new config",
+			self.instance.store.data().name,
+			SLOW_RENDER,
+			self.slow_renders
+		);
+		self.failed = true;
+		clear_all_raw(&mut window);
+	}
+}
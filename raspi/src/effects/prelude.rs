@@ -86,6 +86,7 @@ impl Default for Timer {
 	}
 }
 
+#[derive(Copy, Clone, Debug, Default)]
 pub struct Stats {
 	pub dt:  f32,
 	pub avg: f32,
@@ -152,20 +153,21 @@ impl Default for TimerState {
 }
 
 impl TimerState {
-	pub(crate) fn tick(&mut self, period: Duration) -> TimerStateResult {
+	/// `now` is the time of the frame from the [`crate::effects::FrameContext`].
+	pub(crate) fn tick(&mut self, period: Duration, now: Instant) -> TimerStateResult {
 		let mut triggered = false;
 
 		if !self.init {
-			self.last = Instant::now();
+			self.last = now;
 			self.init = true;
 			triggered = true;
 		}
 
-		let mut elapsed = self.last.elapsed();
+		let mut elapsed = now.saturating_duration_since(self.last);
 
 		while elapsed > period {
 			self.last += period;
-			elapsed = self.last.elapsed();
+			elapsed = now.saturating_duration_since(self.last);
 
 			triggered = true;
 		}
@@ -0,0 +1,431 @@
+use std::{sync::Arc, time::Instant};
+
+use eyre::{bail, eyre, Result, WrapErr};
+use palette::{Darken, Mix};
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+use tracing::error;
+use utoipa::{
+	openapi::{KnownFormat, ObjectBuilder, RefOr, Schema, SchemaFormat, SchemaType},
+	ToSchema,
+};
+
+use crate::{
+	color::{Hsv, Rgba},
+	config::{Script, ScriptParam, ScriptParamKind, WithConfig},
+	controller::{LEDS_PER_STRIP, STRIPS},
+	effects::{config::color::Color, Effect, EffectFactory, EffectWindow, FrameContext},
+	noise,
+};
+
+/// Prefix of the effect id of script effects, followed by the name of the script.
+pub const SCRIPT_EFFECT_PREFIX: &str = "script:";
+
+/// Upper limit for the operations of a single run, so a script can't stall the render loop.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Limits for the values a script can build up, so it can't run the Pi out of memory within its
+/// operation budget. Arrays are big enough to hold a value per LED.
+const MAX_STRING_SIZE: usize = 4 * 1024;
+const MAX_ARRAY_SIZE: usize = LEDS_PER_STRIP * STRIPS;
+const MAX_MAP_SIZE: usize = 1024;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+
+/// Upper limit for the `state` map kept between frames, counted as array items, map entries and
+/// string bytes. Rhai doesn't check the size of a map when new keys are assigned, so without it
+/// the state could grow a little every frame.
+const MAX_STATE_SIZE: usize = 64 * 1024;
+
+/// Variables that are always available in a script and can't be used as param names.
+const BUILTINS: [&str; 6] = ["leds", "len", "time", "dt", "state", "audio"];
+
+pub fn script_effect_id(name: &str) -> String {
+	format!("{}{}", SCRIPT_EFFECT_PREFIX, name)
+}
+
+#[derive(Clone)]
+struct Pixels(Vec<Rgba>);
+
+fn pixel_index(pixels: &Pixels, index: INT) -> Result<usize, Box<EvalAltResult>> {
+	usize::try_from(index)
+		.ok()
+		.filter(|index| *index < pixels.0.len())
+		.ok_or_else(|| format!("led index {} out of bounds (len {})", index, pixels.0.len()).into())
+}
+
+/// Rough size of a map, see [`MAX_STATE_SIZE`].
+fn map_size(map: &Map) -> usize {
+	map.len() + map.values().map(data_size).sum::<usize>()
+}
+
+fn data_size(value: &Dynamic) -> usize {
+	if let Some(array) = value.read_lock::<rhai::Array>() {
+		return array.len() + array.iter().map(data_size).sum::<usize>();
+	}
+	if let Some(map) = value.read_lock::<Map>() {
+		return map_size(&map);
+	}
+	if let Some(string) = value.read_lock::<rhai::ImmutableString>() {
+		return string.len();
+	}
+
+	0
+}
+
+fn engine() -> Engine {
+	let mut engine = Engine::new();
+	engine
+		.set_max_operations(MAX_OPERATIONS)
+		.set_max_string_size(MAX_STRING_SIZE)
+		.set_max_array_size(MAX_ARRAY_SIZE)
+		.set_max_map_size(MAX_MAP_SIZE)
+		.set_max_call_levels(MAX_CALL_LEVELS)
+		.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH);
+
+	engine
+		.register_type_with_name::<Rgba>("Color")
+		.register_fn("rgb", |r: FLOAT, g: FLOAT, b: FLOAT| {
+			Rgba::new(r, g, b, 1.0)
+		})
+		.register_fn("hsv", |h: FLOAT, s: FLOAT, v: FLOAT| {
+			Rgba::from(Hsv::new(h.rem_euclid(360.0), s, v))
+		})
+		.register_fn("mix", |a: &mut Rgba, b: Rgba, factor: FLOAT| {
+			Rgba::from((**a).mix(*b, factor.clamp(0.0, 1.0)))
+		})
+		.register_fn("darken", |c: &mut Rgba, factor: FLOAT| {
+			Rgba::from((**c).darken(factor.clamp(0.0, 1.0)))
+		})
+		.register_get("r", |c: &mut Rgba| c.red)
+		.register_get("g", |c: &mut Rgba| c.green)
+		.register_get("b", |c: &mut Rgba| c.blue);
+
+	engine
+		.register_type_with_name::<Pixels>("Leds")
+		.register_get("len", |p: &mut Pixels| p.0.len() as INT)
+		.register_indexer_get(|p: &mut Pixels, index: INT| {
+			let index = pixel_index(p, index)?;
+			Ok::<_, Box<EvalAltResult>>(p.0[index])
+		})
+		.register_indexer_set(|p: &mut Pixels, index: INT, color: Rgba| {
+			let index = pixel_index(p, index)?;
+			p.0[index] = color;
+			Ok::<_, Box<EvalAltResult>>(())
+		})
+		.register_fn("fill", |p: &mut Pixels, color: Rgba| p.0.fill(color));
+
+	engine
+		.register_fn("noise", |x: FLOAT, y: FLOAT, z: FLOAT| {
+			noise::simplex3d(x, y, z)
+		})
+		.register_fn("noise", |x: FLOAT, y: FLOAT| noise::simplex3d(x, y, 0.0));
+
+	engine
+}
+
+/// Builds effects from a script that is run once per frame.
+///
+/// The script gets `leds` (indexable and assignable with colors), `len`, `time` (seconds since the
+/// effect was created), `dt`, `audio` (a map with the current analysis) and a `state` map that is
+/// kept between frames. Declared params are available as constants with their name.
+///
+/// ```rhai
+/// for i in 0..len {
+///     leds[i] = hsv(i * 3.0 + time * speed, 1.0, noise(i / 10.0, time));
+/// }
+/// ```
+pub struct ScriptEffectFactory {
+	engine: Arc<Engine>,
+	ast:    Arc<AST>,
+	params: Arc<Vec<ScriptParam>>,
+}
+
+impl ScriptEffectFactory {
+	pub fn new(script: &Script) -> Result<Self> {
+		for param in script.params.iter() {
+			let valid =
+				param.name.chars().enumerate().all(|(i, c)| {
+					c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+				});
+
+			if !valid || param.name.is_empty() {
+				bail!("param name `{}` is not a valid identifier", param.name);
+			}
+			if BUILTINS.contains(&param.name.as_str()) {
+				bail!("param name `{}` is reserved", param.name);
+			}
+		}
+
+		let engine = engine();
+		let ast = engine
+			.compile(&script.source)
+			.map_err(|err| eyre!("{}", err))
+			.wrap_err("compiling script")?;
+
+		Ok(ScriptEffectFactory {
+			engine: Arc::new(engine),
+			ast:    Arc::new(ast),
+			params: Arc::new(script.params.clone()),
+		})
+	}
+}
+
+impl EffectFactory for ScriptEffectFactory {
+	fn schema(&self) -> Schema {
+		let mut object = ObjectBuilder::new();
+
+		for param in self.params.iter() {
+			let schema: RefOr<Schema> = match &param.kind {
+				ScriptParamKind::Number {
+					minimum, maximum, ..
+				} => ObjectBuilder::new()
+					.schema_type(SchemaType::Number)
+					.format(Some(SchemaFormat::KnownFormat(KnownFormat::Float)))
+					.minimum(minimum.map(f64::from))
+					.maximum(maximum.map(f64::from))
+					.into(),
+				ScriptParamKind::Bool { .. } => {
+					ObjectBuilder::new().schema_type(SchemaType::Boolean).into()
+				}
+				ScriptParamKind::Color { .. } => Color::schema().1,
+			};
+
+			object = object.property(&param.name, schema).required(&param.name);
+		}
+
+		object.into()
+	}
+
+	fn default_config(&self) -> Result<serde_json::Value> {
+		let mut config = serde_json::Map::new();
+
+		for param in self.params.iter() {
+			let value = match &param.kind {
+				ScriptParamKind::Number { default, .. } => serde_json::to_value(default)?,
+				ScriptParamKind::Bool { default } => serde_json::to_value(default)?,
+				ScriptParamKind::Color { default } => serde_json::to_value(default)?,
+			};
+
+			config.insert(param.name.clone(), value);
+		}
+
+		Ok(serde_json::Value::Object(config))
+	}
+
+	fn build(&self, config: serde_json::Value) -> Result<Box<dyn Effect>> {
+		let mut effect = ScriptEffect {
+			engine: self.engine.clone(),
+			ast:    self.ast.clone(),
+			params: self.params.clone(),
+
+			values:     vec![],
+			state:      Map::new(),
+			start:      None,
+			last:       None,
+			last_error: None,
+		};
+		effect.set_config(config)?;
+
+		Ok(Box::new(effect))
+	}
+}
+
+struct ScriptEffect {
+	engine: Arc<Engine>,
+	ast:    Arc<AST>,
+	params: Arc<Vec<ScriptParam>>,
+
+	values:     Vec<(String, Dynamic)>,
+	state:      Map,
+	/// Both set on the first frame, from the clock of the [`FrameContext`].
+	start:      Option<Instant>,
+	last:       Option<Instant>,
+	last_error: Option<String>,
+}
+
+impl WithConfig for ScriptEffect {
+	type Config = serde_json::Value;
+
+	fn set_config(&mut self, config: Self::Config) -> Result<()> {
+		let serde_json::Value::Object(mut config) = config else {
+			bail!("script config has to be an object");
+		};
+
+		let mut values = Vec::with_capacity(self.params.len());
+		for param in self.params.iter() {
+			let value = config.remove(&param.name);
+
+			let value = match (&param.kind, value) {
+				(ScriptParamKind::Number { default, .. }, None) => Dynamic::from_float(*default),
+				(ScriptParamKind::Number { .. }, Some(value)) => Dynamic::from_float(
+					serde_json::from_value(value)
+						.wrap_err_with(|| format!("param `{}` has to be a number", param.name))?,
+				),
+				(ScriptParamKind::Bool { default }, None) => Dynamic::from_bool(*default),
+				(ScriptParamKind::Bool { .. }, Some(value)) => Dynamic::from_bool(
+					serde_json::from_value(value)
+						.wrap_err_with(|| format!("param `{}` has to be a boolean", param.name))?,
+				),
+				(ScriptParamKind::Color { default }, None) => {
+					Dynamic::from(Rgba::from(default.value()))
+				}
+				(ScriptParamKind::Color { .. }, Some(value)) => {
+					let color: Color = serde_json::from_value(value)
+						.wrap_err_with(|| format!("param `{}` has to be a color", param.name))?;
+					Dynamic::from(Rgba::from(color.value()))
+				}
+			};
+
+			values.push((param.name.clone(), value));
+		}
+
+		self.values = values;
+		self.last_error = None;
+
+		Ok(())
+	}
+}
+
+impl Effect for ScriptEffect {
+	fn run(&mut self, mut window: EffectWindow, ctx: &FrameContext) {
+		let now = ctx.now;
+		let start = *self.start.get_or_insert(now);
+		let dt = self.last.replace(now).map_or(0.0, |last| {
+			now.saturating_duration_since(last).as_secs_f32()
+		});
+
+		let mut audio = Map::new();
+		audio.insert("rms".into(), Dynamic::from_float(ctx.audio.rms));
+		audio.insert("peak".into(), Dynamic::from_float(ctx.audio.peak));
+		audio.insert("bass".into(), Dynamic::from_float(ctx.audio.bass()));
+		audio.insert("beats".into(), Dynamic::from_int(ctx.audio.beats as INT));
+		audio.insert("onsets".into(), Dynamic::from_int(ctx.audio.onsets as INT));
+		audio.insert(
+			"bands".into(),
+			ctx.audio
+				.bands
+				.iter()
+				.map(|band| Dynamic::from_float(*band))
+				.collect::<Vec<_>>()
+				.into(),
+		);
+
+		let mut scope = Scope::new();
+		scope
+			.push("leds", Pixels(window.iter().copied().collect()))
+			.push_constant("len", window.len() as INT)
+			.push_constant("time", now.saturating_duration_since(start).as_secs_f32())
+			.push_constant("dt", dt)
+			.push_constant("audio", audio)
+			.push("state", std::mem::take(&mut self.state));
+
+		for (name, value) in self.values.iter() {
+			scope.push_constant_dynamic(name.clone(), value.clone());
+		}
+
+		let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+
+		if let Some(state) = scope.get_value::<Map>("state") {
+			self.state = state;
+		}
+
+		let result = result.and_then(|()| {
+			let size = map_size(&self.state);
+			if size > MAX_STATE_SIZE {
+				self.state.clear();
+				return Err(format!(
+					"state grew too large ({} > {}), resetting it",
+					size, MAX_STATE_SIZE
+				)
+				.into());
+			}
+			Ok(())
+		});
+
+		match result {
+			Ok(()) => {
+				if let Some(pixels) = scope.get_value::<Pixels>("leds") {
+					for (led, pixel) in window.iter_mut().zip(pixels.0) {
+						*led = pixel;
+					}
+				}
+			}
+			Err(err) => {
+				let err = err.to_string();
+				// only log once instead of every frame
+				if self.last_error.as_ref() != Some(&err) {
+					error!("script error: {}", err);
+					self.last_error = Some(err);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn run(source: &str) -> Result<(), Box<EvalAltResult>> {
+		engine().run(source)
+	}
+
+	#[test]
+	fn data_limits() {
+		let err = run(r#"let s = "a"; loop { s += s; }"#).unwrap_err();
+		assert!(
+			matches!(*err, EvalAltResult::ErrorDataTooLarge(..)),
+			"{}",
+			err
+		);
+
+		let err = run("let a = []; loop { a.push(1); }").unwrap_err();
+		assert!(
+			matches!(*err, EvalAltResult::ErrorDataTooLarge(..)),
+			"{}",
+			err
+		);
+
+		let err = run("let m = #{}; m.a = []; loop { m.a.push(1); }").unwrap_err();
+		assert!(
+			matches!(*err, EvalAltResult::ErrorDataTooLarge(..)),
+			"{}",
+			err
+		);
+
+		// one value per LED is fine
+		run(&format!(
+			"let a = []; a.pad({}, 0);",
+			LEDS_PER_STRIP * STRIPS
+		))
+		.unwrap();
+	}
+
+	#[test]
+	fn state_size() {
+		let mut state = Map::new();
+		state.insert("small".into(), vec![Dynamic::from_int(1); 10].into());
+		assert_eq!(map_size(&state), 1 + 10);
+
+		state.insert("name".into(), "x".repeat(100).into());
+		assert_eq!(map_size(&state), 2 + 10 + 100);
+
+		let mut nested = Map::new();
+		nested.insert("inner".into(), Dynamic::from_map(state));
+		assert_eq!(map_size(&nested), 1 + 2 + 10 + 100);
+	}
+
+	#[test]
+	fn nesting_limits() {
+		let err = run("fn f(x) { f(x + 1) } f(0);").unwrap_err();
+		assert!(
+			matches!(*err, EvalAltResult::ErrorStackOverflow(..)),
+			"{}",
+			err
+		);
+
+		let deep = format!("{}1{}", "(".repeat(200), ")".repeat(200));
+		assert!(engine().compile(deep).is_err());
+	}
+}
@@ -0,0 +1,61 @@
+use educe::Educe;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::effects::{config::color::ColorGradient, prelude::*, EffectWindow, FrameContext};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+pub struct SpectrumConfig {
+	// #[schema(inline)]
+	colors: ColorGradient,
+
+	#[schema(minimum = 20.0, maximum = 1000.0)]
+	#[educe(Default = 40.0)]
+	min_frequency: f32,
+
+	#[schema(minimum = 1000.0, maximum = 20000.0)]
+	#[educe(Default = 12000.0)]
+	max_frequency: f32,
+
+	#[schema(minimum = 0.0, maximum = 20.0)]
+	#[educe(Default = 2.0)]
+	gain: f32,
+
+	#[schema(minimum = 0.0, maximum = 0.99999)]
+	#[educe(Default = 0.85)]
+	falloff: f32,
+}
+
+#[derive(Default)]
+pub struct SpectrumState {
+	levels: Vec<f32>,
+}
+
+pub fn spectrum(
+	config: &SpectrumConfig,
+	state: &mut SpectrumState,
+	mut window: EffectWindow,
+	ctx: &FrameContext,
+) {
+	let len = window.len();
+	state.levels.resize(len, 0.0);
+
+	// logarithmic frequency scale, low frequencies at the start of the window
+	let ratio = config.max_frequency / config.min_frequency.max(1.0);
+
+	for (i, led) in window.iter_mut().enumerate() {
+		let from = config.min_frequency * ratio.powf(i as f32 / len as f32);
+		let to = config.min_frequency * ratio.powf((i + 1) as f32 / len as f32);
+
+		let energy = (ctx.audio.energy(from, to) * config.gain).min(1.0);
+		let level = &mut state.levels[i];
+		*level = energy.max(*level * config.falloff);
+
+		*led = config
+			.colors
+			.lerp(i as f32 / len as f32)
+			.darken(1.0 - *level)
+			.into();
+	}
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use educe::Educe;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::effects::{config::color::Color, prelude::*, EffectWindow, FrameContext};
+
+/// What to show when the client stopped sending frames.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+#[serde(tag = "type")]
+pub enum StreamFallback {
+	#[educe(Default)]
+	Off,
+	/// Keep showing the last frame.
+	Hold,
+	Color {
+		color: Color,
+	},
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+pub struct StreamConfig {
+	/// Time without a new frame after which the fallback is shown.
+	#[schema(minimum = 0.01, maximum = 60.0)]
+	#[educe(Default = 1.0)]
+	timeout_s: f32,
+
+	fallback: StreamFallback,
+}
+
+#[derive(Default)]
+pub struct StreamState {
+	/// Position of the current window in the frame of a group.
+	offset: usize,
+}
+
+/// Shows the frames pushed by clients for the segment or group the effect is running on.
+pub fn stream(
+	config: &StreamConfig,
+	state: &mut StreamState,
+	mut window: EffectWindow,
+	ctx: &FrameContext,
+) {
+	if ctx.segment == 0 {
+		state.offset = 0;
+	}
+	let offset = state.offset;
+	state.offset += window.len();
+
+	let timeout = Duration::from_secs_f32(config.timeout_s);
+
+	let shown = ctx.streams.with_frame(ctx.target, |frame| {
+		if frame.received.elapsed() > timeout && !matches!(config.fallback, StreamFallback::Hold) {
+			return false;
+		}
+
+		let pixels = frame.pixels.get(offset * 3..).unwrap_or_default();
+		let mut pixels = pixels.chunks_exact(3);
+		for led in window.iter_mut() {
+			*led = match pixels.next() {
+				Some(rgb) => Rgba::new(
+					rgb[0] as f32 / 255.0,
+					rgb[1] as f32 / 255.0,
+					rgb[2] as f32 / 255.0,
+					1.0,
+				),
+				None => Rgba::default(),
+			};
+		}
+
+		true
+	});
+
+	if shown == Some(true) {
+		return;
+	}
+
+	match config.fallback {
+		StreamFallback::Off | StreamFallback::Hold => clear_all_raw(&mut window),
+		StreamFallback::Color { color } => set_all_raw(&mut window, color.value().into()),
+	}
+}
@@ -0,0 +1,537 @@
+//! Validation of effect configs against the schema of the effect, so the ranges in the schema are
+//! more than a hint for the UI.
+
+use std::fmt;
+
+use serde_json::Value;
+use utoipa::{
+	openapi::{RefOr, Schema, SchemaType},
+	ToSchema,
+};
+
+use crate::effects::{
+	config::{
+		color::{Color, ColorConfig, ColorGradient, MultiColor},
+		expression::PixelProgram,
+	},
+	stream::StreamFallback,
+};
+
+const COMPONENT_PREFIX: &str = "#/components/schemas/";
+
+const ENUM_MISMATCH: &str = "has to be one of";
+
+#[derive(Clone, Debug)]
+pub struct FieldError {
+	/// Path to the field, e.g. `effects[0].config.speed`.
+	pub field:       String,
+	pub description: String,
+}
+
+impl FieldError {
+	pub fn new(field: impl Into<String>, description: impl Into<String>) -> Self {
+		FieldError {
+			field:       field.into(),
+			description: description.into(),
+		}
+	}
+}
+
+/// Returned by the API when the input doesn't match the schema, gets turned into a
+/// `BadRequest` with one violation per field by the grpc server.
+#[derive(Debug)]
+pub struct ValidationError(pub Vec<FieldError>);
+
+impl fmt::Display for ValidationError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "invalid input: ")?;
+
+		for (i, error) in self.0.iter().enumerate() {
+			if i > 0 {
+				write!(f, ", ")?;
+			}
+			write!(f, "{}: {}", error.field, error.description)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Schemas that effect configs reference instead of inlining them.
+///
+/// Needs an entry for every type used in a config that has its own [`ToSchema`] impl, references
+/// that can't be resolved are not validated. The `component_refs_resolve` test fails for configs
+/// that reference a type missing here.
+fn component(name: &str) -> Option<RefOr<Schema>> {
+	let schema = match name {
+		"Color" => Color::schema(),
+		"ColorConfig" => ColorConfig::schema(),
+		"ColorGradient" => ColorGradient::schema(),
+		"MultiColor" => MultiColor::schema(),
+		"PixelProgram" => PixelProgram::schema(),
+		"StreamFallback" => StreamFallback::schema(),
+		_ => return None,
+	};
+
+	Some(schema.1)
+}
+
+/// Validates the value against the schema, `path` is the prefix for the fields in the errors.
+pub fn validate(schema: &Schema, value: &Value, path: &str) -> Vec<FieldError> {
+	let mut errors = vec![];
+	validate_schema(schema, value, path, &mut errors);
+	errors
+}
+
+fn validate_ref_or(
+	schema: &RefOr<Schema>,
+	value: &Value,
+	path: &str,
+	errors: &mut Vec<FieldError>,
+) {
+	match schema {
+		RefOr::T(schema) => validate_schema(schema, value, path, errors),
+		RefOr::Ref(reference) => {
+			let resolved = reference
+				.ref_location
+				.strip_prefix(COMPONENT_PREFIX)
+				.and_then(component);
+
+			if let Some(schema) = resolved {
+				validate_ref_or(&schema, value, path, errors);
+			}
+		}
+	}
+}
+
+fn type_name(value: &Value) -> &'static str {
+	match value {
+		Value::Null => "null",
+		Value::Bool(_) => "a boolean",
+		Value::Number(_) => "a number",
+		Value::String(_) => "a string",
+		Value::Array(_) => "an array",
+		Value::Object(_) => "an object",
+	}
+}
+
+fn validate_schema(schema: &Schema, value: &Value, path: &str, errors: &mut Vec<FieldError>) {
+	match schema {
+		Schema::Object(object) => {
+			if value.is_null() && object.nullable {
+				return;
+			}
+
+			let expected = match object.schema_type {
+				SchemaType::Value => None,
+				SchemaType::Object if !value.is_object() => Some("an object"),
+				SchemaType::String if !value.is_string() => Some("a string"),
+				SchemaType::Integer if !(value.is_i64() || value.is_u64()) => Some("an integer"),
+				SchemaType::Number if !value.is_number() => Some("a number"),
+				SchemaType::Boolean if !value.is_boolean() => Some("a boolean"),
+				SchemaType::Array if !value.is_array() => Some("an array"),
+				_ => None,
+			};
+			if let Some(expected) = expected {
+				errors.push(FieldError::new(
+					path,
+					format!("expected {}, got {}", expected, type_name(value)),
+				));
+				return;
+			}
+
+			if let Some(values) = &object.enum_values {
+				if !values.contains(value) {
+					let values = values
+						.iter()
+						.map(|value| value.to_string())
+						.collect::<Vec<_>>()
+						.join(", ");
+					errors.push(FieldError::new(
+						path,
+						format!("{} {}", ENUM_MISMATCH, values),
+					));
+				}
+			}
+
+			if let Some(number) = value.as_f64() {
+				if let Some(minimum) = object.minimum.filter(|minimum| number < *minimum) {
+					errors.push(FieldError::new(path, format!("has to be >= {}", minimum)));
+				}
+				if let Some(maximum) = object.maximum.filter(|maximum| number > *maximum) {
+					errors.push(FieldError::new(path, format!("has to be <= {}", maximum)));
+				}
+				if let Some(minimum) = object
+					.exclusive_minimum
+					.filter(|minimum| number <= *minimum)
+				{
+					errors.push(FieldError::new(path, format!("has to be > {}", minimum)));
+				}
+				if let Some(maximum) = object
+					.exclusive_maximum
+					.filter(|maximum| number >= *maximum)
+				{
+					errors.push(FieldError::new(path, format!("has to be < {}", maximum)));
+				}
+			}
+
+			if let Some(string) = value.as_str() {
+				let len = string.chars().count();
+				if let Some(min_length) = object.min_length.filter(|min| len < *min) {
+					errors.push(FieldError::new(
+						path,
+						format!("has to be at least {} characters", min_length),
+					));
+				}
+				if let Some(max_length) = object.max_length.filter(|max| len > *max) {
+					errors.push(FieldError::new(
+						path,
+						format!("has to be at most {} characters", max_length),
+					));
+				}
+			}
+
+			if let Some(map) = value.as_object() {
+				for required in object.required.iter() {
+					if !map.contains_key(required) {
+						errors.push(FieldError::new(
+							format!("{}.{}", path, required),
+							"is required",
+						));
+					}
+				}
+
+				for (name, property) in object.properties.iter() {
+					if let Some(value) = map.get(name) {
+						validate_ref_or(property, value, &format!("{}.{}", path, name), errors);
+					}
+				}
+			}
+		}
+		Schema::Array(array) => {
+			if value.is_null() && array.nullable {
+				return;
+			}
+
+			let Some(items) = value.as_array() else {
+				errors.push(FieldError::new(
+					path,
+					format!("expected an array, got {}", type_name(value)),
+				));
+				return;
+			};
+
+			if let Some(min_items) = array.min_items.filter(|min| items.len() < *min) {
+				errors.push(FieldError::new(
+					path,
+					format!("needs at least {} items", min_items),
+				));
+			}
+			if let Some(max_items) = array.max_items.filter(|max| items.len() > *max) {
+				errors.push(FieldError::new(
+					path,
+					format!("can have at most {} items", max_items),
+				));
+			}
+
+			for (i, item) in items.iter().enumerate() {
+				validate_ref_or(&array.items, item, &format!("{}[{}]", path, i), errors);
+			}
+		}
+		Schema::AllOf(all_of) => {
+			for schema in all_of.items.iter() {
+				validate_ref_or(schema, value, path, errors);
+			}
+		}
+		Schema::OneOf(one_of) => validate_variants(&one_of.items, value, path, errors),
+		Schema::AnyOf(any_of) => validate_variants(&any_of.items, value, path, errors),
+		_ => {}
+	}
+}
+
+/// Passes if any of the variants matches, otherwise reports the errors of the closest one,
+/// preferring variants where the tag matches.
+fn validate_variants(
+	variants: &[RefOr<Schema>],
+	value: &Value,
+	path: &str,
+	errors: &mut Vec<FieldError>,
+) {
+	let mut closest: Option<((bool, usize), Vec<FieldError>)> = None;
+	// allowed values of enum fields (the tag) over all variants
+	let mut allowed: Vec<(String, String)> = vec![];
+
+	for variant in variants {
+		let mut variant_errors = vec![];
+		validate_ref_or(variant, value, path, &mut variant_errors);
+
+		if variant_errors.is_empty() {
+			return;
+		}
+
+		let mut tag_mismatch = false;
+		for error in variant_errors.iter() {
+			if let Some(values) = error.description.strip_prefix(ENUM_MISMATCH) {
+				tag_mismatch = true;
+				allowed.push((error.field.clone(), values.trim().to_string()));
+			}
+		}
+
+		let rank = (tag_mismatch, variant_errors.len());
+		if closest
+			.as_ref()
+			.map_or(true, |(closest, _)| rank < *closest)
+		{
+			closest = Some((rank, variant_errors));
+		}
+	}
+
+	let Some(((tag_mismatch, _), mut closest)) = closest else {
+		return;
+	};
+
+	// none of the tags matched, list the values of all variants instead of only the closest one
+	if tag_mismatch {
+		for error in closest.iter_mut() {
+			if error.description.starts_with(ENUM_MISMATCH) {
+				let values = allowed
+					.iter()
+					.filter(|(field, _)| *field == error.field)
+					.map(|(_, values)| values.as_str())
+					.collect::<Vec<_>>()
+					.join(", ");
+				error.description = format!("{} {}", ENUM_MISMATCH, values);
+			}
+		}
+	}
+
+	errors.extend(closest);
+}
+
+fn resolve(schema: &RefOr<Schema>) -> Option<RefOr<Schema>> {
+	match schema {
+		RefOr::T(_) => Some(schema.clone()),
+		RefOr::Ref(reference) => reference
+			.ref_location
+			.strip_prefix(COMPONENT_PREFIX)
+			.and_then(component),
+	}
+}
+
+/// Makes a stored config match the current schema: fields that are missing or don't validate are
+/// taken from the default, unknown fields are dropped.
+///
+/// Returns a description of every change.
+pub fn merge_with_default(schema: &Schema, config: &mut Value, default: &Value) -> Vec<String> {
+	let mut changes = vec![];
+	merge_schema(schema, config, default, "config", &mut changes);
+	changes
+}
+
+fn merge_schema(
+	schema: &Schema,
+	value: &mut Value,
+	default: &Value,
+	path: &str,
+	changes: &mut Vec<String>,
+) {
+	let Schema::Object(object) = schema else {
+		// arrays and enums are either valid or replaced as a whole
+		if !validate(schema, value, path).is_empty() {
+			*value = default.clone();
+			changes.push(format!("reset {}", path));
+		}
+		return;
+	};
+
+	let (Some(map), Some(default_map)) = (value.as_object_mut(), default.as_object()) else {
+		if !validate(schema, value, path).is_empty() {
+			*value = default.clone();
+			changes.push(format!("reset {}", path));
+		}
+		return;
+	};
+
+	if object.properties.is_empty() {
+		return;
+	}
+
+	let unknown = map
+		.keys()
+		.filter(|key| !object.properties.contains_key(*key))
+		.cloned()
+		.collect::<Vec<_>>();
+	for key in unknown {
+		map.remove(&key);
+		changes.push(format!("dropped {}.{}", path, key));
+	}
+
+	for (name, property) in object.properties.iter() {
+		let path = format!("{}.{}", path, name);
+
+		match (map.get_mut(name), default_map.get(name)) {
+			(Some(value), Some(default)) => {
+				if let Some(RefOr::T(property)) = resolve(property) {
+					merge_schema(&property, value, default, &path, changes);
+				}
+			}
+			(None, Some(default)) => {
+				map.insert(name.clone(), default.clone());
+				changes.push(format!("added {}", path));
+			}
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+	use crate::all_internal_effects;
+
+	/// Collects the names of all components referenced by the schema.
+	fn refs(schema: &RefOr<Schema>, names: &mut Vec<String>) {
+		let schema = match schema {
+			RefOr::T(schema) => schema,
+			RefOr::Ref(reference) => {
+				names.push(reference.ref_location.clone());
+				return;
+			}
+		};
+
+		match schema {
+			Schema::Object(object) => {
+				for property in object.properties.values() {
+					refs(property, names);
+				}
+			}
+			Schema::Array(array) => refs(&array.items, names),
+			Schema::AllOf(all_of) => all_of.items.iter().for_each(|item| refs(item, names)),
+			Schema::OneOf(one_of) => one_of.items.iter().for_each(|item| refs(item, names)),
+			Schema::AnyOf(any_of) => any_of.items.iter().for_each(|item| refs(item, names)),
+			_ => {}
+		}
+	}
+
+	#[test]
+	fn component_refs_resolve() {
+		for (effect_id, factory) in all_internal_effects().unwrap() {
+			let mut pending = vec![];
+			refs(&RefOr::T(factory.schema()), &mut pending);
+
+			let mut seen = vec![];
+			while let Some(location) = pending.pop() {
+				if seen.contains(&location) {
+					continue;
+				}
+
+				let resolved = location
+					.strip_prefix(COMPONENT_PREFIX)
+					.and_then(component)
+					.unwrap_or_else(|| {
+						panic!(
+							"config of effect {} references {}, which has no entry in `component`",
+							effect_id, location
+						)
+					});
+				refs(&resolved, &mut pending);
+				seen.push(location);
+			}
+		}
+	}
+
+	fn stream_errors(config: Value) -> Vec<(String, String)> {
+		let effects = all_internal_effects().unwrap();
+		validate(&effects["stream"].schema(), &config, "config")
+			.into_iter()
+			.map(|error| (error.field, error.description))
+			.collect()
+	}
+
+	#[test]
+	fn valid_config() {
+		let default = all_internal_effects().unwrap()["stream"]
+			.default_config()
+			.unwrap();
+		assert!(stream_errors(default).is_empty());
+	}
+
+	#[test]
+	fn minimum_and_maximum() {
+		assert_eq!(
+			stream_errors(json!({ "timeout_s": 100.0, "fallback": { "type": "Off" } })),
+			[("config.timeout_s".into(), "has to be <= 60".into())]
+		);
+		assert_eq!(
+			stream_errors(json!({ "timeout_s": 0.0, "fallback": { "type": "Off" } })),
+			[("config.timeout_s".into(), "has to be >= 0.01".into())]
+		);
+	}
+
+	#[test]
+	fn types_and_required() {
+		assert_eq!(
+			stream_errors(json!({ "timeout_s": "1", "fallback": { "type": "Off" } })),
+			[(
+				"config.timeout_s".into(),
+				"expected a number, got a string".into()
+			)]
+		);
+		assert_eq!(
+			stream_errors(json!({ "fallback": { "type": "Off" } })),
+			[("config.timeout_s".into(), "is required".into())]
+		);
+	}
+
+	#[test]
+	fn enums() {
+		let errors = stream_errors(json!({ "timeout_s": 1.0, "fallback": { "type": "Blink" } }));
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].0, "config.fallback.type");
+		for variant in ["Off", "Hold", "Color"] {
+			assert!(errors[0].1.contains(variant), "{}", errors[0].1);
+		}
+	}
+
+	#[test]
+	fn nested_refs() {
+		let color = json!({ "hue": 400.0, "saturation": 1.0, "value": 1.0 });
+		assert_eq!(
+			stream_errors(json!({
+				"timeout_s": 1.0,
+				"fallback": { "type": "Color", "color": color },
+			})),
+			[(
+				"config.fallback.color.hue".into(),
+				"has to be <= 360".into()
+			)]
+		);
+	}
+
+	#[test]
+	fn merge_resets_invalid_fields() {
+		let effects = all_internal_effects().unwrap();
+		let factory = &effects["stream"];
+
+		let mut config = json!({ "timeout_s": 100.0, "unknown": true });
+		let changes = merge_with_default(
+			&factory.schema(),
+			&mut config,
+			&factory.default_config().unwrap(),
+		);
+
+		assert_eq!(config, factory.default_config().unwrap());
+		assert_eq!(
+			changes,
+			[
+				"dropped config.unknown",
+				"added config.fallback",
+				"reset config.timeout_s"
+			]
+		);
+	}
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+use educe::Educe;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::effects::{
+	config::color::{Color, ColorGradient},
+	prelude::*,
+	EffectWindow,
+	FrameContext,
+};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Educe, ToSchema)]
+#[educe(Default)]
+pub struct VuMeterConfig {
+	// #[schema(inline)]
+	colors: ColorGradient,
+
+	// #[schema(inline)]
+	peak_color: Color,
+
+	#[schema(minimum = 0.0, maximum = 20.0)]
+	#[educe(Default = 3.0)]
+	gain: f32,
+
+	#[schema(minimum = 0.0, maximum = 0.99999)]
+	#[educe(Default = 0.8)]
+	falloff: f32,
+
+	#[schema(minimum = 0.0, maximum = 10.0)]
+	#[educe(Default = 1.0)]
+	peak_hold_s: f32,
+
+	#[schema(minimum = 0.00001, maximum = 10.0)]
+	#[educe(Default = 0.5)]
+	peak_falloff_s: f32,
+}
+
+#[derive(Default)]
+pub struct VuMeterState {
+	level:     f32,
+	peak:      f32,
+	peak_time: Option<Instant>,
+	last:      Option<Instant>,
+}
+
+pub fn vu_meter(
+	config: &VuMeterConfig,
+	state: &mut VuMeterState,
+	mut window: EffectWindow,
+	ctx: &FrameContext,
+) {
+	let now = ctx.now;
+	let dt = state
+		.last
+		.map_or(Duration::ZERO, |last| now - last)
+		.as_secs_f32();
+	state.last = Some(now);
+
+	let level = (ctx.audio.rms * config.gain).min(1.0);
+	state.level = level.max(state.level * config.falloff);
+
+	let holding = state
+		.peak_time
+		.is_some_and(|time| now - time < Duration::from_secs_f32(config.peak_hold_s));
+	if state.level >= state.peak {
+		state.peak = state.level;
+		state.peak_time = Some(now);
+	} else if !holding {
+		state.peak = (state.peak - dt / config.peak_falloff_s).max(state.level);
+	}
+
+	clear_all_raw(&mut window);
+
+	let len = window.len();
+	if len == 0 {
+		return;
+	}
+
+	// mirrored around the center of the window
+	let center = (len - 1) as f32 / 2.0;
+	for i in 0..len {
+		let distance = (i as f32 - center).abs() / center.max(1.0);
+
+		if distance <= state.level {
+			window[i] = config.colors.lerp(distance).into();
+		}
+	}
+
+	let peak: Rgba = config.peak_color.value().into();
+	window.set_aa(center - state.peak * center, &peak);
+	window.set_aa(center + state.peak * center, &peak);
+}
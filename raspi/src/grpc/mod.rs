@@ -1,44 +1,89 @@
 pub mod schema;
 
-use std::{
-	fmt::Display,
-	net::SocketAddr,
-	pin::Pin,
-	sync::{Arc, Mutex},
-};
+use std::{fmt::Display, future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
 
 use eyre::Result;
 use futures::{Stream, StreamExt};
 use serde::{de::DeserializeOwned, Serialize};
 use tokio_stream::wrappers::BroadcastStream;
-use tonic::{transport::Server, Request, Response, Status};
+use tonic::{transport::Server, Code, Request, Response, Status, Streaming};
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
 use tracing::error;
 
 use crate::{
+	auth::{Auth, AuthLayer, Identity, Role},
+	config,
+	effects::validate::ValidationError,
 	grpc::schema::{
 		controller_server::{Controller, ControllerServer},
+		pixel_frame,
+		Backups,
+		Bundle,
 		Config,
 		DeletePresetRequest,
+		DeleteScriptRequest,
 		DisplayState,
+		EditSegmentsRequest,
 		Effects,
 		GroupsResponse,
+		History,
+		ImportBundleRequest,
 		LoadPresetRequest,
+		PixelFrame,
+		Power,
+		PowerState,
 		Presets,
+		PushFramesResponse,
+		RenderStats,
+		RestoreBackupRequest,
 		SavePresetRequest,
+		Script,
+		Scripts,
 		SegmentsResponse,
 		SetGroupsRequest,
+		SetPowerRequest,
 		SetPresetRequest,
+		SetScriptRequest,
 		SetSegmentsRequest,
 		SetStateEffectRequest,
 		SetStateRequest,
+		ValidateLayoutRequest,
+		ValidateLayoutResponse,
 	},
-	runner::{ApiConfig, EffectAPI, EffectRunner},
+	metrics::GrpcMetricsLayer,
+	runner::{self, ApiConfig, EffectAPI, EffectRunner, EffectTarget, RunnerHandle, StateUpdate},
 };
 
 type DisplayStateStream = Pin<Box<dyn Stream<Item = Result<DisplayState, Status>> + Send>>;
 
 pub struct MyController {
-	pub(crate) runner: Arc<Mutex<EffectRunner>>,
+	pub(crate) runner: RunnerHandle,
+}
+
+impl MyController {
+	/// Runs `f` on the render thread, see [`RunnerHandle::call`].
+	async fn call<R, F>(&self, f: F) -> Result<R, Status>
+	where
+		R: Send + 'static,
+		F: FnOnce(&mut EffectRunner) -> R + Send + 'static,
+	{
+		self.runner
+			.call(f)
+			.await
+			.map_err(wrap_err("running command"))
+	}
+
+	/// Runs `f` on the render thread without publishing a snapshot, see [`RunnerHandle::read`].
+	async fn read<R, F>(&self, f: F) -> Result<R, Status>
+	where
+		R: Send + 'static,
+		F: FnOnce(&EffectRunner) -> R + Send + 'static,
+	{
+		self.runner
+			.read(f)
+			.await
+			.map_err(wrap_err("running command"))
+	}
 }
 
 fn wrap_err<E, D>(msg: D) -> impl FnOnce(E) -> Status
@@ -48,11 +93,72 @@ where
 {
 	move |err: E| {
 		let err = err.into().wrap_err(msg);
+
+		if let Some(err) = err.downcast_ref::<ValidationError>() {
+			return validation_status(err);
+		}
+
 		error!("request error: {:?}", err);
 		Status::internal(format!("{:#}", err))
 	}
 }
 
+/// `InvalidArgument` with a `BadRequest` detail listing the invalid fields.
+fn validation_status(err: &ValidationError) -> Status {
+	let violations = err
+		.0
+		.iter()
+		.map(|error| FieldViolation::new(&error.field, &error.description))
+		.collect::<Vec<_>>();
+
+	Status::with_error_details(
+		Code::InvalidArgument,
+		err.to_string(),
+		ErrorDetails::with_bad_request(violations),
+	)
+}
+
+/// Who made the request, for the history. The name of the token if one was used, otherwise clients
+/// can name themselves with the `x-actor` header.
+fn actor<T>(request: &Request<T>) -> String {
+	if let Some(name) = identity(request).and_then(|identity| identity.name.as_ref()) {
+		return name.clone();
+	}
+
+	if let Some(actor) = request
+		.metadata()
+		.get("x-actor")
+		.and_then(|value| value.to_str().ok())
+	{
+		return actor.to_string();
+	}
+
+	request
+		.remote_addr()
+		.map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string())
+}
+
+fn identity<T>(request: &Request<T>) -> Option<&Identity> {
+	request.extensions().get::<Identity>()
+}
+
+/// For checks that depend on more than the method, the rest is done by the [`AuthLayer`].
+fn require_role<T>(request: &Request<T>, required: Role) -> Result<(), Status> {
+	// requests that didn't pass the layer, e.g. in tests, aren't restricted
+	let Some(identity) = identity(request) else {
+		return Ok(());
+	};
+
+	if identity.role < required {
+		return Err(Status::permission_denied(format!(
+			"requires the {} role, the token has {}",
+			required, identity.role
+		)));
+	}
+
+	Ok(())
+}
+
 fn missing_field(field: &str) -> Status {
 	Status::invalid_argument(format!("{} is missing (default not accepted)", field))
 }
@@ -68,14 +174,11 @@ fn transcode<T: DeserializeOwned>(from: &impl Serialize) -> Result<T, Status> {
 impl Controller for MyController {
 	#[tracing::instrument(skip(self))]
 	async fn get_config(&self, _: Request<()>) -> Result<Response<Config>, Status> {
-		let runner = self.runner.lock().unwrap();
-		let cfg = runner
-			.get_global_config()
-			.map_err(wrap_err("getting global config"))?;
+		let snapshot = self.runner.snapshot();
 
 		let reply = Config {
-			brightness: cfg.brightness,
-			srgb:       cfg.srgb,
+			brightness: snapshot.config.brightness,
+			srgb:       snapshot.config.as_srgb,
 		};
 
 		Ok(Response::new(reply))
@@ -83,7 +186,6 @@ impl Controller for MyController {
 
 	#[tracing::instrument(skip(self, request))]
 	async fn set_config(&self, request: Request<Config>) -> Result<Response<Config>, Status> {
-		let mut runner = self.runner.lock().unwrap();
 		let req = request.into_inner();
 
 		let cfg = ApiConfig {
@@ -91,8 +193,8 @@ impl Controller for MyController {
 			srgb:       req.srgb,
 		};
 
-		runner
-			.set_global_config(cfg)
+		self.call(move |runner| runner.set_global_config(cfg))
+			.await?
 			.map_err(wrap_err("setting global config"))?;
 
 		Ok(Response::new(req))
@@ -100,10 +202,7 @@ impl Controller for MyController {
 
 	#[tracing::instrument(skip(self))]
 	async fn list_segments(&self, _: Request<()>) -> Result<Response<SegmentsResponse>, Status> {
-		let runner = self.runner.lock().unwrap();
-		let strips = runner
-			.list_segments()
-			.map_err(wrap_err("getting segments"))?;
+		let strips = self.runner.snapshot().config.strips.clone();
 
 		let mut proto_strips = Vec::with_capacity(strips.len());
 		for strip in strips {
@@ -111,7 +210,8 @@ impl Controller for MyController {
 		}
 
 		let reply = SegmentsResponse {
-			strips: proto_strips,
+			strips:   proto_strips,
+			warnings: vec![],
 		};
 
 		Ok(Response::new(reply))
@@ -122,8 +222,7 @@ impl Controller for MyController {
 		&self,
 		request: Request<SetSegmentsRequest>,
 	) -> Result<Response<SegmentsResponse>, Status> {
-		let mut runner = self.runner.lock().unwrap();
-
+		let actor = actor(&request);
 		let strips = request.into_inner().strips;
 
 		let mut config_strips = Vec::with_capacity(strips.len());
@@ -131,19 +230,54 @@ impl Controller for MyController {
 			config_strips.push(strip.try_into()?);
 		}
 
-		runner
-			.set_segments(config_strips)
+		let warnings = self
+			.call(move |runner| {
+				runner.track(&actor, "set segments", |runner| {
+					runner.set_segments(config_strips)
+				})
+			})
+			.await?
 			.map_err(wrap_err("setting segments"))?;
 
-		let reply = SegmentsResponse { strips };
+		let reply = SegmentsResponse {
+			strips,
+			warnings: warnings.into_iter().map(Into::into).collect(),
+		};
 
 		Ok(Response::new(reply))
 	}
 
-	async fn list_groups(&self, _: Request<()>) -> Result<Response<GroupsResponse>, Status> {
-		let runner = self.runner.lock().unwrap();
+	#[tracing::instrument(skip(self, request))]
+	async fn edit_segments(
+		&self,
+		request: Request<EditSegmentsRequest>,
+	) -> Result<Response<SegmentsResponse>, Status> {
+		let actor = actor(&request);
+		let edit: config::layout::SegmentEdit = request.into_inner().try_into()?;
+
+		let strips = self
+			.call(move |runner| {
+				runner.track(&actor, "edit segments", |runner| {
+					runner.edit_segments(edit)?;
+					runner.list_segments()
+				})
+			})
+			.await?
+			.map_err(wrap_err("editing segments"))?;
 
-		let groups = runner.list_groups().map_err(wrap_err("getting groups"))?;
+		let mut proto_strips = Vec::with_capacity(strips.len());
+		for strip in strips {
+			proto_strips.push(strip.try_into()?);
+		}
+
+		Ok(Response::new(SegmentsResponse {
+			strips:   proto_strips,
+			warnings: vec![],
+		}))
+	}
+
+	async fn list_groups(&self, _: Request<()>) -> Result<Response<GroupsResponse>, Status> {
+		let groups = self.runner.snapshot().config.groups.clone();
 
 		let mut proto_groups = Vec::with_capacity(groups.len());
 		for group in groups {
@@ -151,7 +285,8 @@ impl Controller for MyController {
 		}
 
 		let reply = GroupsResponse {
-			groups: proto_groups,
+			groups:   proto_groups,
+			warnings: vec![],
 		};
 
 		Ok(Response::new(reply))
@@ -162,7 +297,7 @@ impl Controller for MyController {
 		&self,
 		request: Request<SetGroupsRequest>,
 	) -> Result<Response<GroupsResponse>, Status> {
-		let mut runner = self.runner.lock().unwrap();
+		let actor = actor(&request);
 		let groups = request.into_inner().groups;
 
 		let mut config_groups = Vec::with_capacity(groups.len());
@@ -170,21 +305,62 @@ impl Controller for MyController {
 			config_groups.push(group.try_into()?);
 		}
 
-		runner
-			.set_groups(config_groups)
+		let warnings = self
+			.call(move |runner| {
+				runner.track(&actor, "set groups", |runner| {
+					runner.set_groups(config_groups)
+				})
+			})
+			.await?
 			.map_err(wrap_err("setting groups"))?;
 
-		let reply = GroupsResponse { groups };
+		let reply = GroupsResponse {
+			groups,
+			warnings: warnings.into_iter().map(Into::into).collect(),
+		};
 
 		Ok(Response::new(reply))
 	}
 
+	#[tracing::instrument(skip(self, request))]
+	async fn validate_layout(
+		&self,
+		request: Request<ValidateLayoutRequest>,
+	) -> Result<Response<ValidateLayoutResponse>, Status> {
+		let request = request.into_inner();
+
+		let strips = match request.segments {
+			Some(segments) => {
+				let mut strips = Vec::with_capacity(segments.strips.len());
+				for strip in segments.strips {
+					strips.push(strip.try_into()?);
+				}
+				Some(strips)
+			}
+			None => None,
+		};
+		let groups = match request.groups {
+			Some(groups) => {
+				let mut config_groups = Vec::with_capacity(groups.groups.len());
+				for group in groups.groups {
+					config_groups.push(group.try_into()?);
+				}
+				Some(config_groups)
+			}
+			None => None,
+		};
+
+		let report = self
+			.read(move |runner| runner.validate_layout(strips, groups))
+			.await?
+			.map_err(wrap_err("validating layout"))?;
+
+		Ok(Response::new(report.into()))
+	}
+
 	#[tracing::instrument(skip(self))]
 	async fn list_effects(&self, _: Request<()>) -> Result<Response<Effects>, Status> {
-		let runner = self.runner.lock().unwrap();
-		let effects = runner
-			.list_effects()
-			.map_err(wrap_err("getting global config"))?;
+		let effects = self.runner.effects();
 
 		let reply = effects.try_into()?;
 
@@ -193,13 +369,9 @@ impl Controller for MyController {
 
 	#[tracing::instrument(skip(self))]
 	async fn list_presets(&self, _: Request<()>) -> Result<Response<Presets>, Status> {
-		let runner = self.runner.lock().unwrap();
-
-		let presets = runner
-			.list_presets()
-			.map_err(wrap_err("setting global config"))?;
+		let presets = (*self.runner.snapshot().presets).clone();
 
-		let reply = presets.clone().try_into()?;
+		let reply = presets.try_into()?;
 
 		Ok(Response::new(reply))
 	}
@@ -209,13 +381,12 @@ impl Controller for MyController {
 		&self,
 		request: Request<SetPresetRequest>,
 	) -> Result<Response<DisplayState>, Status> {
-		let mut runner = self.runner.lock().unwrap();
-
 		let SetPresetRequest { name, data } = request.into_inner();
 		let data = data.ok_or(missing_field("SetPresetRequest.data"))?;
+		let preset = data.clone().try_into()?;
 
-		runner
-			.set_preset(name, data.clone().try_into()?)
+		self.call(move |runner| runner.set_preset(name, preset))
+			.await?
 			.map_err(wrap_err("setting preset"))?;
 
 		Ok(Response::new(data))
@@ -226,13 +397,11 @@ impl Controller for MyController {
 		&self,
 		request: Request<DeletePresetRequest>,
 	) -> Result<Response<()>, Status> {
-		let mut runner = self.runner.lock().unwrap();
-
 		let DeletePresetRequest { name } = request.into_inner();
 
-		runner
-			.delete_preset(name)
-			.map_err(wrap_err("setting preset"))?;
+		self.call(move |runner| runner.delete_preset(name))
+			.await?
+			.map_err(wrap_err("deleting preset"))?;
 
 		Ok(Response::new(()))
 	}
@@ -242,16 +411,20 @@ impl Controller for MyController {
 		&self,
 		request: Request<LoadPresetRequest>,
 	) -> Result<Response<DisplayState>, Status> {
-		let mut runner = self.runner.lock().unwrap();
-		runner
-			.load_preset(request.into_inner().name)
+		let actor = actor(&request);
+		let name = request.into_inner().name;
+
+		let state = self
+			.call(move |runner| -> Result<_> {
+				runner.track(&actor, &format!("load preset {}", name), |runner| {
+					runner.load_preset(name)
+				})?;
+
+				Ok(runner.get_state()?.clone())
+			})
+			.await?
 			.map_err(wrap_err("loading preset"))?;
 
-		let state = runner
-			.get_state()
-			.map_err(wrap_err("getting state"))?
-			.clone();
-
 		Ok(Response::new(state.try_into()?))
 	}
 
@@ -260,28 +433,29 @@ impl Controller for MyController {
 		&self,
 		request: Request<SavePresetRequest>,
 	) -> Result<Response<DisplayState>, Status> {
-		let mut runner = self.runner.lock().unwrap();
-		runner
-			.save_preset(request.into_inner().name)
-			.map_err(wrap_err("saving preset"))?;
+		let name = request.into_inner().name;
 
-		let state = runner
-			.get_state()
-			.map_err(wrap_err("getting state"))?
-			.clone();
+		let state = self
+			.call(move |runner| -> Result<_> {
+				runner.save_preset(name)?;
+
+				Ok(runner.get_state()?.clone())
+			})
+			.await?
+			.map_err(wrap_err("saving preset"))?;
 
 		Ok(Response::new(state.try_into()?))
 	}
 
 	#[tracing::instrument(skip(self))]
 	async fn get_state(&self, _: Request<()>) -> Result<Response<DisplayState>, Status> {
-		let runner = self.runner.lock().unwrap();
-		let state = runner
-			.get_state()
-			.map_err(wrap_err("getting state"))?
-			.clone();
+		let snapshot = self.runner.snapshot();
+		let update = StateUpdate {
+			state: (*snapshot.state).clone(),
+			power: snapshot.power,
+		};
 
-		Ok(Response::new(state.try_into()?))
+		Ok(Response::new(update.try_into()?))
 	}
 
 	#[tracing::instrument(skip(self, request))]
@@ -289,7 +463,7 @@ impl Controller for MyController {
 		&self,
 		request: Request<SetStateRequest>,
 	) -> Result<Response<DisplayState>, Status> {
-		let mut runner = self.runner.lock().unwrap();
+		let actor = actor(&request);
 
 		// protobuf kinda stupid, this really should not be optional.
 		let new_state = request
@@ -300,7 +474,11 @@ impl Controller for MyController {
 		let state = new_state.clone().try_into()?;
 		// println!("{:#?}", state);
 
-		runner.set_state(state).map_err(wrap_err("setting state"))?;
+		self.call(move |runner| {
+			runner.track(&actor, "set state", |runner| runner.set_state(state))
+		})
+		.await?
+		.map_err(wrap_err("setting state"))?;
 
 		Ok(Response::new(new_state))
 	}
@@ -310,31 +488,41 @@ impl Controller for MyController {
 		&self,
 		request: Request<SetStateEffectRequest>,
 	) -> Result<Response<DisplayState>, Status> {
-		let mut runner = self.runner.lock().unwrap();
-		let mut state = runner
-			.get_state()
-			.map_err(wrap_err("getting state"))?
-			.clone();
+		let actor = actor(&request);
 
 		let SetStateEffectRequest { index, effect } = request.into_inner();
 
 		let index: usize = index
 			.try_into()
 			.map_err(wrap_err("converting SetStateEffectRequest.index"))?;
+		let effect: config::DisplayStateEffect = effect
+			.ok_or(missing_field("SetStateEffectRequest.effect"))?
+			.try_into()?;
+
+		// read and written in one command, so concurrent changes to other effects aren't lost
+		let state = self
+			.call(move |runner| {
+				let mut state = runner
+					.get_state()
+					.map_err(wrap_err("getting state"))?
+					.clone();
+
+				let Some(state_effect) = state.effects.get_mut(index) else {
+					return Err(Status::invalid_argument(
+						"SetStateEffectRequest.index is not in the current state.",
+					));
+				};
+				*state_effect = effect;
+
+				runner
+					.track(&actor, &format!("set effect {}", index), |runner| {
+						runner.set_state(state.clone())
+					})
+					.map_err(wrap_err("setting state"))?;
 
-		if let Some(state_effect) = state.effects.get_mut(index) {
-			*state_effect = effect
-				.ok_or(missing_field("SetStateEffectRequest.effect"))?
-				.try_into()?;
-		} else {
-			return Err(Status::invalid_argument(
-				"SetStateEffectRequest.index is not in the current state.",
-			));
-		};
-
-		runner
-			.set_state(state.clone())
-			.map_err(wrap_err("setting state"))?;
+				Ok(state)
+			})
+			.await??;
 
 		Ok(Response::new(state.try_into()?))
 	}
@@ -346,23 +534,277 @@ impl Controller for MyController {
 		&self,
 		_: Request<()>,
 	) -> Result<Response<Self::StreamStateStream>, Status> {
-		let runner = self.runner.lock().unwrap();
-		let rx = runner.subscribe();
+		let rx = self.runner.subscribe();
 
 		Ok(Response::new(Box::pin(BroadcastStream::new(rx).map(
 			|res| {
-				let state = res.map_err(|err| Status::deadline_exceeded(err.to_string()))?;
-				let state: DisplayState = state.try_into()?;
+				let update = res.map_err(|err| Status::deadline_exceeded(err.to_string()))?;
+				let state: DisplayState = update.try_into()?;
 
 				Ok(state)
 			},
 		))))
 	}
+
+	#[tracing::instrument(skip(self))]
+	async fn get_power(&self, _: Request<()>) -> Result<Response<Power>, Status> {
+		let power = self.runner.snapshot().power;
+
+		Ok(Response::new(power.into()))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn set_power(
+		&self,
+		request: Request<SetPowerRequest>,
+	) -> Result<Response<Power>, Status> {
+		let request = request.into_inner();
+
+		let state = request
+			.state
+			.map(|state| {
+				PowerState::try_from(state)
+					.map_err(|_| Status::invalid_argument("SetPowerRequest.state is unknown"))
+			})
+			.transpose()?
+			.map(runner::PowerState::from);
+		let fade = request
+			.fade_ms
+			.map(|fade_ms| Duration::from_millis(fade_ms.into()));
+		let master = request.master;
+
+		let power = self
+			.call(move |runner| runner.set_power(state, master, fade))
+			.await?
+			.map_err(wrap_err("setting power"))?;
+
+		Ok(Response::new(power.into()))
+	}
+
+	#[tracing::instrument(skip(self))]
+	async fn list_scripts(&self, _: Request<()>) -> Result<Response<Scripts>, Status> {
+		let scripts = (*self.runner.snapshot().scripts).clone();
+
+		let reply = scripts.try_into()?;
+
+		Ok(Response::new(reply))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn set_script(
+		&self,
+		request: Request<SetScriptRequest>,
+	) -> Result<Response<Script>, Status> {
+		let SetScriptRequest { name, script } = request.into_inner();
+		let script = script.ok_or(missing_field("SetScriptRequest.script"))?;
+		let config_script = script.clone().try_into()?;
+
+		self.call(move |runner| runner.set_script(name, config_script))
+			.await?
+			.map_err(|err| {
+				let err = err.wrap_err("setting script");
+				error!("request error: {:?}", err);
+				Status::invalid_argument(format!("{:#}", err))
+			})?;
+
+		Ok(Response::new(script))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn delete_script(
+		&self,
+		request: Request<DeleteScriptRequest>,
+	) -> Result<Response<()>, Status> {
+		let DeleteScriptRequest { name } = request.into_inner();
+
+		self.call(move |runner| runner.delete_script(name))
+			.await?
+			.map_err(wrap_err("deleting script"))?;
+
+		Ok(Response::new(()))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn push_frames(
+		&self,
+		request: Request<Streaming<PixelFrame>>,
+	) -> Result<Response<PushFramesResponse>, Status> {
+		let streams = self.runner.streams();
+
+		let mut frames = request.into_inner();
+		let mut response = PushFramesResponse::default();
+
+		while let Some(frame) = frames.message().await? {
+			let target = match frame.target.ok_or_else(|| missing_field("target"))? {
+				pixel_frame::Target::SegmentId(segment_id) => {
+					EffectTarget::Segment(segment_id.try_into()?)
+				}
+				pixel_frame::Target::GroupId(group_id) => EffectTarget::Group(group_id),
+			};
+
+			if frame.pixels.len() % 3 != 0 {
+				return Err(Status::invalid_argument("pixels has to be 3 bytes per led"));
+			}
+
+			if streams.push(target, frame.sequence, &frame.pixels) {
+				response.accepted += 1;
+			} else {
+				response.dropped += 1;
+			}
+		}
+
+		Ok(Response::new(response))
+	}
+
+	#[tracing::instrument(skip(self))]
+	async fn list_backups(&self, _: Request<()>) -> Result<Response<Backups>, Status> {
+		let backups = self
+			.read(|runner| runner.list_backups())
+			.await?
+			.map_err(wrap_err("listing backups"))?;
+
+		Ok(Response::new(backups.try_into()?))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn restore_backup(
+		&self,
+		request: Request<RestoreBackupRequest>,
+	) -> Result<Response<()>, Status> {
+		let actor = actor(&request);
+		let RestoreBackupRequest { config, id } = request.into_inner();
+
+		self.call(move |runner| {
+			runner.track(&actor, &format!("restore backup {}", id), |runner| {
+				runner.restore_backup(config, id.clone())
+			})
+		})
+		.await?
+		.map_err(wrap_err("restoring backup"))?;
+
+		Ok(Response::new(()))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn undo(&self, request: Request<()>) -> Result<Response<DisplayState>, Status> {
+		let admin = require_role(&request, Role::Admin);
+
+		let state = self
+			.call(move |runner| {
+				let history = runner.list_history().map_err(wrap_err("getting history"))?;
+				if history
+					.next_undo()
+					.is_some_and(|entry| entry.changes_layout())
+				{
+					admin?;
+				}
+
+				if !runner.undo().map_err(wrap_err("undoing change"))? {
+					return Err(Status::failed_precondition("nothing to undo"));
+				}
+
+				Ok(runner
+					.get_state()
+					.map_err(wrap_err("getting state"))?
+					.clone())
+			})
+			.await??;
+
+		Ok(Response::new(state.try_into()?))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn redo(&self, request: Request<()>) -> Result<Response<DisplayState>, Status> {
+		let admin = require_role(&request, Role::Admin);
+
+		let state = self
+			.call(move |runner| {
+				let history = runner.list_history().map_err(wrap_err("getting history"))?;
+				if history
+					.next_redo()
+					.is_some_and(|entry| entry.changes_layout())
+				{
+					admin?;
+				}
+
+				if !runner.redo().map_err(wrap_err("redoing change"))? {
+					return Err(Status::failed_precondition("nothing to redo"));
+				}
+
+				Ok(runner
+					.get_state()
+					.map_err(wrap_err("getting state"))?
+					.clone())
+			})
+			.await??;
+
+		Ok(Response::new(state.try_into()?))
+	}
+
+	#[tracing::instrument(skip(self))]
+	async fn list_history(&self, _: Request<()>) -> Result<Response<History>, Status> {
+		let history = self
+			.read(|runner| runner.list_history().cloned())
+			.await?
+			.map_err(wrap_err("listing history"))?;
+
+		Ok(Response::new((&history).try_into()?))
+	}
+
+	#[tracing::instrument(skip(self))]
+	async fn get_render_stats(&self, _: Request<()>) -> Result<Response<RenderStats>, Status> {
+		let stats = self
+			.read(|runner| runner.get_render_stats())
+			.await?
+			.map_err(wrap_err("getting render stats"))?;
+
+		Ok(Response::new(stats.try_into()?))
+	}
+
+	#[tracing::instrument(skip(self))]
+	async fn export_bundle(&self, _: Request<()>) -> Result<Response<Bundle>, Status> {
+		let bundle = self
+			.read(|runner| runner.export_bundle())
+			.await?
+			.map_err(wrap_err("exporting bundle"))?;
+
+		Ok(Response::new(bundle.try_into()?))
+	}
+
+	#[tracing::instrument(skip(self, request))]
+	async fn import_bundle(
+		&self,
+		request: Request<ImportBundleRequest>,
+	) -> Result<Response<()>, Status> {
+		let actor = actor(&request);
+		let mut request = request.into_inner();
+
+		let bundle = request
+			.bundle
+			.take()
+			.ok_or(missing_field("ImportBundleRequest.bundle"))?
+			.try_into()?;
+		let options = request.try_into()?;
+
+		self.call(move |runner| {
+			runner.track(&actor, "import bundle", |runner| {
+				runner.import_bundle(bundle, options)
+			})
+		})
+		.await?
+		.map_err(wrap_err("importing bundle"))?;
+
+		Ok(Response::new(()))
+	}
 }
 
-pub async fn run(runner: Arc<Mutex<EffectRunner>>) -> Result<()> {
-	// let addr = "[::1]:4445".parse()?;
-	let addr = SocketAddr::from(([0, 0, 0, 0], 4445));
+/// Serves the api until `shutdown` resolves.
+pub async fn run(
+	runner: RunnerHandle,
+	addr: SocketAddr,
+	auth: Arc<Auth>,
+	shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
 	tracing::debug!("grpc listening on {}", addr);
 
 	let controller = MyController { runner };
@@ -370,8 +812,10 @@ pub async fn run(runner: Arc<Mutex<EffectRunner>>) -> Result<()> {
 
 	Server::builder()
 		.accept_http1(true)
+		.layer(GrpcMetricsLayer)
+		.layer(AuthLayer::new(auth))
 		.add_service(tonic_web::enable(controller))
-		.serve(addr)
+		.serve_with_shutdown(addr, shutdown)
 		.await?;
 
 	Ok(())
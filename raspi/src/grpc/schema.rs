@@ -1,12 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+	collections::{HashMap, HashSet},
+	time::UNIX_EPOCH,
+};
 
 use tonic::Status;
 
 pub use crate::grpc::schema::generated::*;
 use crate::{
+	color::Hsv,
 	config,
 	effects,
 	grpc::{missing_field, transcode, wrap_err},
+	runner,
 };
 
 mod generated {
@@ -22,7 +27,10 @@ impl TryFrom<config::DisplayState> for DisplayState {
 			effects.push(effect.try_into()?);
 		}
 
-		Ok(DisplayState { effects })
+		Ok(DisplayState {
+			effects,
+			power: None,
+		})
 	}
 }
 
@@ -56,6 +64,7 @@ impl TryFrom<config::DisplayStateEffect> for DisplayStateEffect {
 			),
 			segment_ids,
 			group_ids: value.group_ids.into_iter().collect(),
+			modifiers: Some(value.modifiers.try_into()?),
 		})
 	}
 }
@@ -77,8 +86,81 @@ impl TryFrom<DisplayStateEffect> for config::DisplayStateEffect {
 					.ok_or(missing_field("DisplayStateEffect.config"))?,
 			)
 			.map_err(wrap_err("converting from grpc struct"))?,
+			// set to the current version by the runner
+			config_version: 0,
 			segment_ids,
 			group_ids: value.group_ids.into_iter().collect(),
+			modifiers: value
+				.modifiers
+				.map(TryInto::try_into)
+				.transpose()?
+				.unwrap_or_default(),
+		})
+	}
+}
+
+impl TryFrom<config::EffectModifiers> for EffectModifiers {
+	type Error = Status;
+
+	fn try_from(value: config::EffectModifiers) -> Result<Self, Self::Error> {
+		Ok(EffectModifiers {
+			opacity:    value.opacity,
+			speed:      value.speed,
+			hue:        value.hue,
+			saturation: value.saturation,
+			mirror:     value.mirror,
+			reverse:    value.reverse,
+			repeat:     value
+				.repeat
+				.try_into()
+				.map_err(wrap_err("converting EffectModifiers"))?,
+			offset:     value.offset,
+			skip:       value
+				.skip
+				.try_into()
+				.map_err(wrap_err("converting EffectModifiers"))?,
+			start:      value
+				.start
+				.try_into()
+				.map_err(wrap_err("converting EffectModifiers"))?,
+			end:        value
+				.end
+				.map(u32::try_from)
+				.transpose()
+				.map_err(wrap_err("converting EffectModifiers"))?,
+		})
+	}
+}
+
+impl TryFrom<EffectModifiers> for config::EffectModifiers {
+	type Error = Status;
+
+	fn try_from(value: EffectModifiers) -> Result<Self, Self::Error> {
+		Ok(config::EffectModifiers {
+			opacity:    value.opacity,
+			speed:      value.speed,
+			hue:        value.hue,
+			saturation: value.saturation,
+			mirror:     value.mirror,
+			reverse:    value.reverse,
+			// left out by clients that don't know about it
+			repeat:     usize::try_from(value.repeat)
+				.map_err(wrap_err("converting EffectModifiers"))?
+				.max(1),
+			offset:     value.offset,
+			skip:       value
+				.skip
+				.try_into()
+				.map_err(wrap_err("converting EffectModifiers"))?,
+			start:      value
+				.start
+				.try_into()
+				.map_err(wrap_err("converting EffectModifiers"))?,
+			end:        value
+				.end
+				.map(usize::try_from)
+				.transpose()
+				.map_err(wrap_err("converting EffectModifiers"))?,
 		})
 	}
 }
@@ -265,6 +347,47 @@ impl TryFrom<Segment> for config::Segment {
 	}
 }
 
+impl TryFrom<EditSegmentsRequest> for config::layout::SegmentEdit {
+	type Error = Status;
+
+	fn try_from(value: EditSegmentsRequest) -> Result<Self, Self::Error> {
+		let edit = match value
+			.edit
+			.ok_or(missing_field("EditSegmentsRequest.edit"))?
+		{
+			edit_segments_request::Edit::Insert(insert) => config::layout::SegmentEdit::Insert {
+				id:      insert
+					.segment_id
+					.ok_or(missing_field("InsertSegment.segment_id"))?
+					.try_into()?,
+				segment: insert
+					.segment
+					.ok_or(missing_field("InsertSegment.segment"))?
+					.try_into()?,
+			},
+			edit_segments_request::Edit::Split(split) => config::layout::SegmentEdit::Split {
+				id:     split
+					.segment_id
+					.ok_or(missing_field("SplitSegment.segment_id"))?
+					.try_into()?,
+				length: split
+					.length
+					.try_into()
+					.map_err(wrap_err("converting SplitSegment.length"))?,
+				name:   split.name,
+			},
+			edit_segments_request::Edit::Merge(merge) => config::layout::SegmentEdit::Merge {
+				id: merge
+					.segment_id
+					.ok_or(missing_field("MergeSegments.segment_id"))?
+					.try_into()?,
+			},
+		};
+
+		Ok(edit)
+	}
+}
+
 impl TryFrom<config::Group> for Group {
 	type Error = Status;
 
@@ -299,6 +422,24 @@ impl TryFrom<Group> for config::Group {
 	}
 }
 
+impl From<effects::validate::FieldError> for LayoutProblem {
+	fn from(value: effects::validate::FieldError) -> Self {
+		LayoutProblem {
+			field:       value.field,
+			description: value.description,
+		}
+	}
+}
+
+impl From<config::layout::LayoutReport> for ValidateLayoutResponse {
+	fn from(value: config::layout::LayoutReport) -> Self {
+		ValidateLayoutResponse {
+			errors:   value.errors.into_iter().map(Into::into).collect(),
+			warnings: value.warnings.into_iter().map(Into::into).collect(),
+		}
+	}
+}
+
 impl TryFrom<HashMap<String, config::DisplayState>> for Presets {
 	type Error = Status;
 
@@ -324,3 +465,326 @@ impl TryFrom<Presets> for HashMap<String, config::DisplayState> {
 		Ok(presets)
 	}
 }
+
+impl TryFrom<HashMap<String, config::Script>> for Scripts {
+	type Error = Status;
+
+	fn try_from(value: HashMap<String, config::Script>) -> Result<Self, Self::Error> {
+		let mut scripts = HashMap::with_capacity(value.len());
+		for (name, script) in value {
+			scripts.insert(name, script.try_into()?);
+		}
+
+		Ok(Scripts { scripts })
+	}
+}
+
+impl TryFrom<config::Script> for Script {
+	type Error = Status;
+
+	fn try_from(value: config::Script) -> Result<Self, Self::Error> {
+		let mut params = Vec::with_capacity(value.params.len());
+		for param in value.params {
+			params.push(param.try_into()?);
+		}
+
+		Ok(Script {
+			source: value.source,
+			params,
+		})
+	}
+}
+
+impl TryFrom<Script> for config::Script {
+	type Error = Status;
+
+	fn try_from(value: Script) -> Result<Self, Self::Error> {
+		let mut params = Vec::with_capacity(value.params.len());
+		for param in value.params {
+			params.push(param.try_into()?);
+		}
+
+		Ok(config::Script {
+			source: value.source,
+			params,
+		})
+	}
+}
+
+impl TryFrom<config::ScriptParam> for ScriptParam {
+	type Error = Status;
+
+	fn try_from(value: config::ScriptParam) -> Result<Self, Self::Error> {
+		let kind = match value.kind {
+			config::ScriptParamKind::Number {
+				default,
+				minimum,
+				maximum,
+			} => script_param::Kind::Number(NumberParam {
+				default_value: default,
+				minimum,
+				maximum,
+			}),
+			config::ScriptParamKind::Bool { default } => script_param::Kind::Boolean(BoolParam {
+				default_value: default,
+			}),
+			config::ScriptParamKind::Color { default } => {
+				let color = default.value();
+
+				script_param::Kind::Color(ColorParam {
+					hue:        color.hue.into_positive_degrees(),
+					saturation: color.saturation,
+					value:      color.value,
+				})
+			}
+		};
+
+		Ok(ScriptParam {
+			name: value.name,
+			kind: Some(kind),
+		})
+	}
+}
+
+impl TryFrom<ScriptParam> for config::ScriptParam {
+	type Error = Status;
+
+	fn try_from(value: ScriptParam) -> Result<Self, Self::Error> {
+		let kind = match value.kind.ok_or(missing_field("ScriptParam.kind"))? {
+			script_param::Kind::Number(NumberParam {
+				default_value,
+				minimum,
+				maximum,
+			}) => config::ScriptParamKind::Number {
+				default: default_value,
+				minimum,
+				maximum,
+			},
+			script_param::Kind::Boolean(BoolParam { default_value }) => {
+				config::ScriptParamKind::Bool {
+					default: default_value,
+				}
+			}
+			script_param::Kind::Color(ColorParam {
+				hue,
+				saturation,
+				value,
+			}) => config::ScriptParamKind::Color {
+				default: Hsv::new(hue, saturation, value).into(),
+			},
+		};
+
+		Ok(config::ScriptParam {
+			name: value.name,
+			kind,
+		})
+	}
+}
+
+impl TryFrom<Vec<config::Backup>> for Backups {
+	type Error = Status;
+
+	fn try_from(value: Vec<config::Backup>) -> Result<Self, Self::Error> {
+		let mut backups = Vec::with_capacity(value.len());
+		for backup in value {
+			backups.push(backup.try_into()?);
+		}
+
+		Ok(Backups { backups })
+	}
+}
+
+impl TryFrom<config::Backup> for Backup {
+	type Error = Status;
+
+	fn try_from(value: config::Backup) -> Result<Self, Self::Error> {
+		let created = value
+			.created
+			.duration_since(UNIX_EPOCH)
+			.map_err(wrap_err("converting Backup.created"))?;
+
+		Ok(Backup {
+			config:     value.config,
+			id:         value.id,
+			created_ms: created
+				.as_millis()
+				.try_into()
+				.map_err(wrap_err("converting Backup.created"))?,
+			size:       value.size,
+		})
+	}
+}
+
+impl TryFrom<config::bundle::Bundle> for Bundle {
+	type Error = Status;
+
+	fn try_from(value: config::bundle::Bundle) -> Result<Self, Self::Error> {
+		let json = value.to_json().map_err(wrap_err("serializing bundle"))?;
+
+		Ok(Bundle {
+			json: String::from_utf8(json).map_err(wrap_err("serializing bundle"))?,
+		})
+	}
+}
+
+impl TryFrom<Bundle> for config::bundle::Bundle {
+	type Error = Status;
+
+	fn try_from(value: Bundle) -> Result<Self, Self::Error> {
+		config::bundle::Bundle::from_json(value.json.as_bytes())
+			.map_err(|err| Status::invalid_argument(format!("{:#}", err)))
+	}
+}
+
+/// The options of the request, the bundle is converted separately.
+impl TryFrom<ImportBundleRequest> for config::bundle::ImportOptions {
+	type Error = Status;
+
+	fn try_from(value: ImportBundleRequest) -> Result<Self, Self::Error> {
+		let mode = match ImportMode::try_from(value.mode)
+			.map_err(|_| Status::invalid_argument("ImportBundleRequest.mode is unknown"))?
+		{
+			ImportMode::Merge => config::bundle::ImportMode::Merge,
+			ImportMode::Replace => config::bundle::ImportMode::Replace,
+		};
+
+		let mut remap = config::bundle::SegmentMap::default();
+		for StripRemap { from, to } in value.strips {
+			remap.strips.insert(
+				from.try_into()
+					.map_err(wrap_err("converting StripRemap.from"))?,
+				to.try_into()
+					.map_err(wrap_err("converting StripRemap.to"))?,
+			);
+		}
+		for SegmentRemap { from, to } in value.segments {
+			remap.segments.insert(
+				from.ok_or(missing_field("SegmentRemap.from"))?.try_into()?,
+				to.ok_or(missing_field("SegmentRemap.to"))?.try_into()?,
+			);
+		}
+
+		Ok(config::bundle::ImportOptions {
+			mode,
+			remap,
+			dry_run: value.dry_run,
+		})
+	}
+}
+
+impl TryFrom<&config::History> for History {
+	type Error = Status;
+
+	fn try_from(value: &config::History) -> Result<Self, Self::Error> {
+		let mut entries = Vec::with_capacity(value.entries().len());
+		for entry in value.entries() {
+			entries.push(entry.try_into()?);
+		}
+
+		Ok(History {
+			entries,
+			position: value
+				.position()
+				.try_into()
+				.map_err(wrap_err("converting History.position"))?,
+		})
+	}
+}
+
+impl TryFrom<&config::history::HistoryEntry> for HistoryEntry {
+	type Error = Status;
+
+	fn try_from(value: &config::history::HistoryEntry) -> Result<Self, Self::Error> {
+		let time = value
+			.time
+			.duration_since(UNIX_EPOCH)
+			.map_err(wrap_err("converting HistoryEntry.time"))?;
+
+		Ok(HistoryEntry {
+			id:          value.id,
+			time_ms:     time
+				.as_millis()
+				.try_into()
+				.map_err(wrap_err("converting HistoryEntry.time"))?,
+			actor:       value.actor.clone(),
+			description: value.description.clone(),
+		})
+	}
+}
+
+impl TryFrom<runner::RenderStats> for RenderStats {
+	type Error = Status;
+
+	fn try_from(value: runner::RenderStats) -> Result<Self, Self::Error> {
+		let mut targets = Vec::with_capacity(value.targets.len());
+		for (target, stats) in value.targets {
+			let target = match target {
+				runner::EffectTarget::Segment(segment_id) => {
+					target_render_stats::Target::SegmentId(segment_id.try_into()?)
+				}
+				runner::EffectTarget::Group(group_id) => {
+					target_render_stats::Target::GroupId(group_id)
+				}
+			};
+
+			targets.push(TargetRenderStats {
+				target:    Some(target),
+				effect_id: stats.effect_id,
+				last_ms:   stats.last,
+				avg_ms:    stats.avg,
+				max_ms:    stats.max,
+				frames:    stats.frames,
+				skipped:   stats.skipped,
+				interval:  stats.interval,
+				error:     stats.error,
+			});
+		}
+
+		Ok(RenderStats {
+			frame_time_ms: value.frame.avg,
+			fps: 1000.0 / value.frame.avg,
+			budget_ms: value.budget.map(|budget| budget.as_secs_f32() * 1000.0),
+			targets,
+		})
+	}
+}
+
+impl From<runner::PowerState> for PowerState {
+	fn from(value: runner::PowerState) -> Self {
+		match value {
+			runner::PowerState::On => PowerState::On,
+			runner::PowerState::Standby => PowerState::Standby,
+			runner::PowerState::Blackout => PowerState::Blackout,
+		}
+	}
+}
+
+impl From<PowerState> for runner::PowerState {
+	fn from(value: PowerState) -> Self {
+		match value {
+			PowerState::On => runner::PowerState::On,
+			PowerState::Standby => runner::PowerState::Standby,
+			PowerState::Blackout => runner::PowerState::Blackout,
+		}
+	}
+}
+
+impl From<runner::Power> for Power {
+	fn from(value: runner::Power) -> Self {
+		Power {
+			state:  PowerState::from(value.state).into(),
+			master: value.master,
+		}
+	}
+}
+
+impl TryFrom<runner::StateUpdate> for DisplayState {
+	type Error = Status;
+
+	fn try_from(value: runner::StateUpdate) -> Result<Self, Self::Error> {
+		let mut state: DisplayState = value.state.try_into()?;
+		state.power = Some(value.power.into());
+
+		Ok(state)
+	}
+}
@@ -1,20 +1,32 @@
-use std::{
-	net::SocketAddr,
-	sync::{Arc, Mutex},
-};
+use std::{future::Future, net::SocketAddr, path::PathBuf, sync::Arc};
 
-use axum::Router;
+use axum::{
+	extract::{
+		ws::{Message, WebSocket},
+		State,
+		WebSocketUpgrade,
+	},
+	http::StatusCode,
+	response::{IntoResponse, Response},
+	routing::get,
+	Router,
+};
 use eyre::Result;
 use tower_http::{cors::CorsLayer, services::ServeDir};
+use tracing::{debug, error};
 
 use crate::{
+	auth::{Auth, AuthLayer},
 	grpc::{schema::controller_server::ControllerServer, MyController},
-	runner::EffectRunner,
+	metrics::metrics,
+	runner::RunnerHandle,
+	stream::PixelStreams,
 };
 
 #[derive(Clone)]
 struct AppState {
 	// runner: Arc<Mutex<EffectRunner>>,
+	streams: Arc<PixelStreams>,
 }
 //
 // // Make our own error that wraps `anyhow::Error`.
@@ -100,14 +112,21 @@ struct AppState {
 // async fn set_preset() {}
 // async fn load_preset() {}
 
-pub async fn run(runner: Arc<Mutex<EffectRunner>>) -> Result<()> {
+pub async fn run(
+	runner: RunnerHandle,
+	addr: SocketAddr,
+	static_dir: PathBuf,
+	auth: Arc<Auth>,
+	shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
 	let controller = MyController {
 		runner: runner.clone(),
 	};
 	let controller = ControllerServer::new(controller);
+	let streams = runner.streams();
 
 	let app = Router::new()
-		.fallback_service(ServeDir::new("public/").append_index_html_on_directories(true))
+		.fallback_service(ServeDir::new(static_dir).append_index_html_on_directories(true))
 		// .route("/api/config", get(config).put(set_config))
 		// .route("/api/config/segments", get(segments).put(set_segments))
 		// .route("/api/current", get(current).put(set_current))
@@ -116,20 +135,57 @@ pub async fn run(runner: Arc<Mutex<EffectRunner>>) -> Result<()> {
 		// .route("/api/presets", get(presets))
 		// .route("/api/presets/:preset", get(preset).put(set_preset))
 		// .route("/ws", get(ws_handler))
+		.route("/api/stream", get(stream_handler))
+		.route("/metrics", get(metrics_handler))
 		.layer(CorsLayer::permissive())
 		// .layer(TraceLayer::new_for_http().make_span_with(DefaultMakeSpan::default()))
 		.route_service("/api/grpc", controller)
-		.with_state(AppState {});
+		.layer(AuthLayer::new(auth))
+		.with_state(AppState { streams });
 
-	let addr = SocketAddr::from(([0, 0, 0, 0], 4444));
 	tracing::debug!("http listening on {}", addr);
 
 	let listener = tokio::net::TcpListener::bind(addr).await?;
-	axum::serve(listener, app).await?;
+	axum::serve(listener, app)
+		.with_graceful_shutdown(shutdown)
+		.await?;
 
 	Ok(())
 }
 
+async fn metrics_handler() -> Response {
+	match metrics().encode() {
+		Ok(metrics) => metrics.into_response(),
+		Err(err) => {
+			error!("encoding metrics: {:#}", err);
+			StatusCode::INTERNAL_SERVER_ERROR.into_response()
+		}
+	}
+}
+
+/// Accepts binary frames for the `stream` effect, see [`crate::stream`] for the format.
+async fn stream_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+	ws.on_upgrade(move |socket| handle_stream_socket(socket, state.streams))
+}
+
+async fn handle_stream_socket(mut socket: WebSocket, streams: Arc<PixelStreams>) {
+	metrics().pixel_streams.inc();
+
+	while let Some(Ok(msg)) = socket.recv().await {
+		match msg {
+			Message::Binary(packet) => {
+				if let Err(err) = streams.push_packet(&packet) {
+					debug!("invalid pixel stream packet: {:#}", err);
+				}
+			}
+			Message::Close(_) => break,
+			_ => {}
+		}
+	}
+
+	metrics().pixel_streams.dec();
+}
+
 // /// The handler for the HTTP request (this gets called when the HTTP GET lands at the start
 // /// of websocket negotiation). After this completes, the actual switching from HTTP to
 // /// websocket protocol will occur.
@@ -1,20 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fs, path::Path};
 
 use eyre::Result;
 use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
 use utoipa::ToSchema;
 
-use crate::effects::EffectFactory;
+use crate::effects::{
+	plugin::{plugin_effect_id, PluginEffectFactory},
+	EffectFactory,
+};
 
+pub mod audio;
+pub mod auth;
+pub mod cli;
 pub mod color;
 pub mod config;
 pub mod controller;
 pub mod effects;
 pub mod grpc;
 pub mod http;
+pub mod metrics;
 pub mod noise;
 pub mod runner;
 pub mod serde_transcode;
+pub mod stream;
+
+pub const APP_NAME: &str = "roboclub-led-controller";
 
 pub fn all_internal_effects() -> Result<HashMap<String, Box<dyn EffectFactory>>> {
 	let mut effect_map: HashMap<String, Box<dyn EffectFactory>> = HashMap::new();
@@ -22,21 +33,40 @@ pub fn all_internal_effects() -> Result<HashMap<String, Box<dyn EffectFactory>>>
 	fn add_effect<
 		C: Default + Serialize + DeserializeOwned + for<'a> ToSchema<'a> + Send + Sync + 'static,
 		S: Default + Send + Sync + 'static,
+		A: 'static,
 	>(
 		map: &mut HashMap<String, Box<dyn EffectFactory>>,
 		name: &str,
-		func: impl EffectFn<C, S> + Send + Sync + Clone + 'static,
+		func: impl EffectFn<C, S, A> + Send + Sync + Clone + 'static,
 	) -> Result<()> {
 		map.insert(name.to_string(), Box::new(FnEffectFactory::new(func)));
 		Ok(())
 	}
 
+	/// For effects that move by a fixed step every time they run, see [`effects::Effect::stepped`].
+	fn add_stepped_effect<
+		C: Default + Serialize + DeserializeOwned + for<'a> ToSchema<'a> + Send + Sync + 'static,
+		S: Default + Send + Sync + 'static,
+		A: 'static,
+	>(
+		map: &mut HashMap<String, Box<dyn EffectFactory>>,
+		name: &str,
+		func: impl EffectFn<C, S, A> + Send + Sync + Clone + 'static,
+	) -> Result<()> {
+		map.insert(
+			name.to_string(),
+			Box::new(FnEffectFactory::new(func).stepped()),
+		);
+		Ok(())
+	}
+
 	use effects::*;
 
-	add_effect(&mut effect_map, "balls", balls)?;
+	add_stepped_effect(&mut effect_map, "balls", balls)?;
 	add_effect(&mut effect_map, "solid", solid)?;
 	add_effect(&mut effect_map, "static_rainbow", static_rainbow)?;
-	add_effect(&mut effect_map, "explosions", explosions)?;
+	add_stepped_effect(&mut effect_map, "explosions", explosions)?;
+	add_effect(&mut effect_map, "expression", expression)?;
 	add_effect(&mut effect_map, "flash_rainbow", flash_rainbow)?;
 	add_effect(&mut effect_map, "flash_rainbow_noise", flash_rainbow_noise)?;
 	add_effect(
@@ -44,12 +74,46 @@ pub fn all_internal_effects() -> Result<HashMap<String, Box<dyn EffectFactory>>>
 		"flash_rainbow_random",
 		flash_rainbow_random,
 	)?;
-	add_effect(&mut effect_map, "meteors", meteors)?;
+	add_stepped_effect(&mut effect_map, "meteors", meteors)?;
 	add_effect(&mut effect_map, "moving_lights", moving_lights)?;
 	// add_effect(&mut effect_map, db, "police", |db| Police::new(db))?;
-	add_effect(&mut effect_map, "rainbow", rainbow)?;
-	add_effect(&mut effect_map, "random", random)?;
-	add_effect(&mut effect_map, "snake", snake)?;
+	add_stepped_effect(&mut effect_map, "rainbow", rainbow)?;
+	add_stepped_effect(&mut effect_map, "random", random)?;
+	add_stepped_effect(&mut effect_map, "snake", snake)?;
+	add_effect(&mut effect_map, "stream", stream)?;
+
+	// audio reactive
+	add_stepped_effect(&mut effect_map, "spectrum", spectrum)?;
+	add_effect(&mut effect_map, "vu_meter", vu_meter)?;
+	add_effect(&mut effect_map, "beat_flash", beat_flash)?;
+	add_stepped_effect(&mut effect_map, "beat_explosions", beat_explosions)?;
+
+	Ok(effect_map)
+}
+
+/// Loads all `*.wasm` files in the directory as plugin effects, see [`effects::plugin`].
+///
+/// Plugins that fail to load are logged and skipped.
+pub fn all_plugin_effects(dir: &Path) -> Result<HashMap<String, Box<dyn EffectFactory>>> {
+	let mut effect_map: HashMap<String, Box<dyn EffectFactory>> = HashMap::new();
+
+	if !dir.exists() {
+		return Ok(effect_map);
+	}
+
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.extension().and_then(|ext| ext.to_str()) != Some("wasm") {
+			continue;
+		}
+
+		match PluginEffectFactory::load(&path) {
+			Ok(factory) => {
+				effect_map.insert(plugin_effect_id(factory.name()), Box::new(factory));
+			}
+			Err(err) => error!("failed to load plugin {}: {:#}", path.display(), err),
+		}
+	}
 
 	Ok(effect_map)
 }
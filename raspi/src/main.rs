@@ -1,29 +1,207 @@
-use std::{
-	env::current_dir,
-	fs,
-	sync::{Arc, Mutex},
-	time::Duration,
+use std::{fs, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::{Parser, Subcommand};
+use eyre::{ensure, Result};
+use robolab::{
+	auth::{Auth, Role},
+	cli,
+	config::{
+		server::{LogFormat, ServerConfig},
+		store::{self, StoreKind},
+	},
+	controller::{Controller, OutputBackend},
+	grpc,
+	http,
+	runner::{EffectRunner, RunnerHandle},
+	stream,
 };
+use tokio::{signal, sync::watch, time};
+use tracing::{error, info, warn};
+
+/// How long open connections may keep the servers from stopping.
+const SERVER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Parser)]
+#[command(
+	version,
+	about = "Renders the effects and serves the api to control them"
+)]
+struct Args {
+	/// TOML file with the server config, the options below override it
+	#[arg(long, short, env = "ROBOLAB_CONFIG")]
+	config: Option<PathBuf>,
+
+	#[command(flatten)]
+	overrides: Overrides,
+
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(clap::Args)]
+struct Overrides {
+	/// Directory with the configs, plugins and the config db [default: .config]
+	#[arg(long, global = true)]
+	config_dir:       Option<PathBuf>,
+	/// Where the configs are stored [default: file]
+	#[arg(long, global = true)]
+	store:            Option<StoreKind>,
+	/// [default: 0.0.0.0:4444]
+	#[arg(long, global = true)]
+	http_addr:        Option<SocketAddr>,
+	/// [default: 0.0.0.0:4445]
+	#[arg(long, global = true)]
+	grpc_addr:        Option<SocketAddr>,
+	/// Address for the pixel stream packets [default: 0.0.0.0:4446]
+	#[arg(long, global = true)]
+	udp_addr:         Option<SocketAddr>,
+	/// Served by the http server [default: public]
+	#[arg(long, global = true)]
+	static_dir:       Option<PathBuf>,
+	/// Tokens for the apis, the apis are open without it [default: <config-dir>/auth.toml]
+	#[arg(long, global = true)]
+	auth_file:        Option<PathBuf>,
+	/// Where the frames are sent to [default: serial]
+	#[arg(long, global = true)]
+	output:           Option<OutputBackend>,
+	/// [default: full]
+	#[arg(long, global = true)]
+	log_format:       Option<LogFormat>,
+	/// Limits how often the effects are rendered [default: unlimited]
+	#[arg(long, global = true)]
+	fps:              Option<f32>,
+	/// Milliseconds each effect may take per frame, slower ones are rendered less often
+	#[arg(long, global = true)]
+	frame_budget_ms:  Option<f32>,
+	/// How long the lights fade out when stopping [default: 500]
+	#[arg(long, global = true)]
+	shutdown_fade_ms: Option<u64>,
+	/// Preset shown after fading out when stopping
+	#[arg(long, global = true)]
+	shutdown_preset:  Option<String>,
+}
 
-use eyre::Result;
-use robolab::{all_internal_effects, controller::Controller, grpc, http, runner::EffectRunner};
-use tracing::info;
+impl Overrides {
+	fn apply(self, config: &mut ServerConfig) {
+		let Overrides {
+			config_dir,
+			store,
+			http_addr,
+			grpc_addr,
+			udp_addr,
+			static_dir,
+			auth_file,
+			output,
+			log_format,
+			fps,
+			frame_budget_ms,
+			shutdown_fade_ms,
+			shutdown_preset,
+		} = self;
+
+		if let Some(config_dir) = config_dir {
+			config.config_dir = config_dir;
+		}
+		if let Some(store) = store {
+			config.store = store;
+		}
+		if let Some(http_addr) = http_addr {
+			config.http_addr = http_addr;
+		}
+		if let Some(grpc_addr) = grpc_addr {
+			config.grpc_addr = grpc_addr;
+		}
+		if let Some(udp_addr) = udp_addr {
+			config.udp_addr = udp_addr;
+		}
+		if let Some(static_dir) = static_dir {
+			config.static_dir = static_dir;
+		}
+		if auth_file.is_some() {
+			config.auth_file = auth_file;
+		}
+		if let Some(output) = output {
+			config.output = output;
+		}
+		if let Some(log_format) = log_format {
+			config.log_format = log_format;
+		}
+		if fps.is_some() {
+			config.fps = fps;
+		}
+		if frame_budget_ms.is_some() {
+			config.frame_budget_ms = frame_budget_ms;
+		}
+		if let Some(shutdown_fade_ms) = shutdown_fade_ms {
+			config.shutdown_fade_ms = shutdown_fade_ms;
+		}
+		if shutdown_preset.is_some() {
+			config.shutdown_preset = shutdown_preset;
+		}
+	}
+}
 
-pub const APP_NAME: &str = "roboclub-led-controller";
+#[derive(Subcommand)]
+enum Command {
+	/// Start the servers and render the effects (the default)
+	Serve,
+	/// Check the stored configs without changing them
+	ValidateConfig,
+	/// Print the ids of all effects, including plugins and scripts
+	ListEffects,
+	/// Print a new token for the auth file
+	GenerateToken {
+		/// Shown in the history for changes made with the token
+		name: String,
+		#[arg(long, value_enum, default_value_t = Role::Operator)]
+		role: Role,
+	},
+	/// Export the installation of a running server as a bundle
+	Export {
+		file:   PathBuf,
+		#[arg(long, default_value = cli::DEFAULT_SERVER)]
+		server: String,
+		/// Admin token from the auth file of the server
+		#[arg(long, env = "LIGHTCTL_TOKEN", hide_env_values = true)]
+		token:  Option<String>,
+	},
+	/// Import a bundle into a running server
+	Import {
+		file:    PathBuf,
+		/// Replace everything instead of merging groups, presets and scripts
+		#[arg(long)]
+		replace: bool,
+		/// Only validate the bundle
+		#[arg(long)]
+		dry_run: bool,
+		/// Map strips (`0=2`) or segments (`0:1=2:0`) of the bundle, can be repeated
+		#[arg(long = "map", value_name = "FROM=TO")]
+		maps:    Vec<String>,
+		#[arg(long, default_value = cli::DEFAULT_SERVER)]
+		server:  String,
+		/// Admin token from the auth file of the server
+		#[arg(long, env = "LIGHTCTL_TOKEN", hide_env_values = true)]
+		token:   Option<String>,
+	},
+}
 
-fn install_tracing() {
+fn install_tracing(format: LogFormat, default_level: &str) {
 	use tracing_error::ErrorLayer;
 	use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-	// fmt::layer().pretty().compact().with_target(false);
-	let fmt_layer = fmt::layer().with_target(false);
+	let fmt_layer = match format {
+		LogFormat::Full => fmt::layer().with_target(false).boxed(),
+		LogFormat::Compact => fmt::layer().compact().with_target(false).boxed(),
+		LogFormat::Pretty => fmt::layer().pretty().with_target(false).boxed(),
+		LogFormat::Json => fmt::layer().json().with_target(false).boxed(),
+	};
 	let filter_layer = EnvFilter::try_from_default_env()
-		.or_else(|_| EnvFilter::try_new("info"))
+		.or_else(|_| EnvFilter::try_new(default_level))
 		.unwrap();
 
 	tracing_subscriber::registry()
-		.with(filter_layer)
 		.with(fmt_layer)
+		.with(filter_layer)
 		.with(ErrorLayer::default())
 		.init();
 }
@@ -31,33 +209,158 @@ fn install_tracing() {
 #[tokio::main]
 async fn main() -> Result<()> {
 	color_eyre::install()?;
-	install_tracing();
 
-	let config_dir = current_dir()?.join(".config");
-	fs::create_dir_all(&config_dir)?;
+	let args = Args::parse();
+
+	let mut config = match &args.config {
+		Some(path) => ServerConfig::load(path)?,
+		None => ServerConfig::default(),
+	};
+	args.overrides.apply(&mut config);
+
+	if let Some(fps) = config.fps {
+		ensure!(fps > 0.0, "fps has to be positive");
+	}
+	if let Some(budget) = config.frame_budget_ms {
+		ensure!(budget > 0.0, "the frame budget has to be positive");
+	}
+
+	let command = args.command.unwrap_or(Command::Serve);
+
+	// only warnings for the subcommands, so they don't drown the output
+	let default_level = match command {
+		Command::Serve => "info",
+		_ => "warn",
+	};
+	install_tracing(config.log_format, default_level);
+
+	match command {
+		Command::Serve => serve(config).await,
+		Command::ValidateConfig => cli::validate_config(&config),
+		Command::ListEffects => cli::list_effects(&config),
+		Command::GenerateToken { name, role } => {
+			cli::generate_token(&name, role);
+			Ok(())
+		}
+		Command::Export {
+			file,
+			server,
+			token,
+		} => cli::export_bundle(server, token, &file).await,
+		Command::Import {
+			file,
+			replace,
+			dry_run,
+			maps,
+			server,
+			token,
+		} => cli::import_bundle(server, token, &file, replace, dry_run, &maps).await,
+	}
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+	let ctrl_c = async {
+		signal::ctrl_c()
+			.await
+			.expect("listening for ctrl-c should work");
+	};
+
+	#[cfg(unix)]
+	let terminate = async {
+		signal::unix::signal(signal::unix::SignalKind::terminate())
+			.expect("listening for SIGTERM should work")
+			.recv()
+			.await;
+	};
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		_ = ctrl_c => {}
+		_ = terminate => {}
+	}
+}
+
+async fn serve(config: ServerConfig) -> Result<()> {
+	fs::create_dir_all(&config.config_dir)?;
+
+	let store = store::open(config.store, &config.config_dir)?;
 
-	let controller = Controller::new()?;
+	let auth = Arc::new(Auth::load(&config.auth_file())?);
+
+	let controller = Controller::new(config.output)?;
 
 	let runner = {
-		let effect_map = all_internal_effects()?;
+		let effect_map = cli::load_effects(&config)?;
+
+		let mut runner = EffectRunner::new(store, effect_map, controller)?;
+		runner.set_frame_budget(
+			config
+				.frame_budget_ms
+				.map(|budget| Duration::from_secs_f32(budget / 1000.0)),
+		);
+		let frame_time = config.fps.map(|fps| Duration::from_secs_f32(1.0 / fps));
+
+		RunnerHandle::spawn(runner, frame_time)?
+	};
+
+	let streams = runner.streams();
 
-		let runner = EffectRunner::new(&config_dir, effect_map, controller)?;
-		Arc::new(Mutex::new(runner))
+	// resolves for every server once the shutdown starts
+	let (stop, stopped) = watch::channel(());
+	let stopped = move || {
+		let mut stopped = stopped.clone();
+		async move {
+			stopped.changed().await.ok();
+		}
 	};
 
-	let _handle = {
-		let runner = runner.clone();
-		std::thread::spawn(move || {
-			info!("starting effect loop");
-			loop {
-				runner.lock().unwrap().tick();
-				// std::thread::yield_now();
-				std::thread::sleep(Duration::from_micros(100));
-			}
-		})
+	// udp packets can't carry a token, only the configured senders may stream with auth enabled
+	let udp_senders = auth.is_enabled().then(|| config.udp_senders.clone());
+
+	let http = http::run(
+		runner.clone(),
+		config.http_addr,
+		config.static_dir,
+		auth.clone(),
+		stopped(),
+	);
+	let grpc = grpc::run(runner.clone(), config.grpc_addr, auth, stopped());
+	let udp = stream::run_udp(streams, config.udp_addr, udp_senders, stopped());
+	let servers = async move { tokio::try_join!(http, grpc, udp) };
+	tokio::pin!(servers);
+
+	tokio::select! {
+		result = &mut servers => {
+			result?;
+			return Ok(());
+		}
+		_ = shutdown_signal() => {}
+	}
+
+	info!("shutting down, stop again to exit right away");
+	stop.send(()).ok();
+
+	let fade = Duration::from_millis(config.shutdown_fade_ms);
+	let shutdown = async {
+		// open connections like state streams keep the servers running
+		match time::timeout(SERVER_SHUTDOWN_TIMEOUT, &mut servers).await {
+			Ok(Err(err)) => error!("server failed while stopping: {:?}", err),
+			Ok(Ok(_)) => {}
+			Err(_) => warn!(
+				"servers didn't stop within {:?}, closing the connections",
+				SERVER_SHUTDOWN_TIMEOUT
+			),
+		}
+
+		runner.shutdown(fade, config.shutdown_preset).await
 	};
 
-	tokio::try_join!(http::run(runner.clone()), grpc::run(runner.clone()))?;
+	tokio::select! {
+		result = shutdown => result?,
+		_ = shutdown_signal() => warn!("exiting without waiting for the lights"),
+	}
 
 	// let mut io = IoHandler::default();
 	// io.add_sync_method("say_hello", |_params| {
@@ -0,0 +1,211 @@
+//! Prometheus metrics for the render loop and the apis, served on `/metrics` by the http server.
+
+use std::{
+	sync::LazyLock,
+	task::{Context, Poll},
+	time::Instant,
+};
+
+use axum::http::{Request, Response};
+use eyre::Result;
+use futures::{future::BoxFuture, FutureExt};
+use prometheus::{
+	exponential_buckets,
+	Encoder,
+	Gauge,
+	Histogram,
+	HistogramOpts,
+	HistogramVec,
+	IntCounterVec,
+	IntGauge,
+	Opts,
+	Registry,
+	TextEncoder,
+};
+use tower::{Layer, Service};
+
+pub struct Metrics {
+	registry: Registry,
+
+	/// Time between the start of two frames.
+	pub frame_time:         Histogram,
+	/// Frames per second, averaged over the last few seconds.
+	pub fps:                Gauge,
+	pub effect_render_time: HistogramVec,
+	pub serial_write_time:  Histogram,
+	/// Errors by where they happened, e.g. `serial` or `render`.
+	pub errors:             IntCounterVec,
+	/// Time commands waited for the render thread to run them.
+	pub command_wait_time:  Histogram,
+
+	pub grpc_requests:     IntCounterVec,
+	pub grpc_request_time: HistogramVec,
+
+	pub state_subscribers: IntGauge,
+	pub pixel_streams:     IntGauge,
+}
+
+impl Metrics {
+	fn new() -> Result<Self> {
+		let registry = Registry::new_custom(Some("robolab".to_string()), None)?;
+
+		let frame_time = Histogram::with_opts(
+			HistogramOpts::new("frame_time_seconds", "Time between the start of two frames")
+				.buckets(exponential_buckets(0.0005, 2.0, 10)?),
+		)?;
+		let fps = Gauge::new("fps", "Rendered frames per second")?;
+		let effect_render_time = HistogramVec::new(
+			HistogramOpts::new(
+				"effect_render_time_seconds",
+				"Time an effect takes to render a segment",
+			)
+			.buckets(exponential_buckets(0.00005, 2.0, 12)?),
+			&["effect"],
+		)?;
+		let serial_write_time = Histogram::with_opts(
+			HistogramOpts::new(
+				"serial_write_time_seconds",
+				"Time to send a frame to the controller",
+			)
+			.buckets(exponential_buckets(0.0005, 2.0, 10)?),
+		)?;
+		let errors =
+			IntCounterVec::new(Opts::new("errors_total", "Errors by source"), &["source"])?;
+		let command_wait_time = Histogram::with_opts(
+			HistogramOpts::new(
+				"runner_command_wait_seconds",
+				"Time commands waited for the render thread",
+			)
+			.buckets(exponential_buckets(0.00001, 4.0, 9)?),
+		)?;
+
+		let grpc_requests = IntCounterVec::new(
+			Opts::new("grpc_requests_total", "Handled grpc requests"),
+			&["method", "code"],
+		)?;
+		let grpc_request_time = HistogramVec::new(
+			HistogramOpts::new(
+				"grpc_request_time_seconds",
+				"Time until the response headers of grpc requests were sent",
+			),
+			&["method"],
+		)?;
+
+		let state_subscribers =
+			IntGauge::new("state_subscribers", "Clients subscribed to state changes")?;
+		let pixel_streams =
+			IntGauge::new("pixel_streams", "Connected websockets pushing pixel frames")?;
+
+		registry.register(Box::new(frame_time.clone()))?;
+		registry.register(Box::new(fps.clone()))?;
+		registry.register(Box::new(effect_render_time.clone()))?;
+		registry.register(Box::new(serial_write_time.clone()))?;
+		registry.register(Box::new(errors.clone()))?;
+		registry.register(Box::new(command_wait_time.clone()))?;
+		registry.register(Box::new(grpc_requests.clone()))?;
+		registry.register(Box::new(grpc_request_time.clone()))?;
+		registry.register(Box::new(state_subscribers.clone()))?;
+		registry.register(Box::new(pixel_streams.clone()))?;
+
+		Ok(Metrics {
+			registry,
+			frame_time,
+			fps,
+			effect_render_time,
+			serial_write_time,
+			errors,
+			command_wait_time,
+			grpc_requests,
+			grpc_request_time,
+			state_subscribers,
+			pixel_streams,
+		})
+	}
+
+	/// All metrics in the prometheus text format.
+	pub fn encode(&self) -> Result<String> {
+		let mut buffer = vec![];
+		TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+		Ok(String::from_utf8(buffer)?)
+	}
+
+	pub fn error(&self, source: &str) {
+		self.errors.with_label_values(&[source]).inc();
+	}
+}
+
+static METRICS: LazyLock<Metrics> =
+	LazyLock::new(|| Metrics::new().expect("metric definitions should be valid"));
+
+pub fn metrics() -> &'static Metrics {
+	&METRICS
+}
+
+/// Counts grpc requests by method and status code.
+#[derive(Clone, Default)]
+pub struct GrpcMetricsLayer;
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+	type Service = GrpcMetricsService<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		GrpcMetricsService { inner }
+	}
+}
+
+#[derive(Clone)]
+pub struct GrpcMetricsService<S> {
+	inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcMetricsService<S>
+where
+	S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+	S::Future: Send + 'static,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+		// only known paths, so requests to random paths can't create new series
+		let method = request
+			.uri()
+			.path()
+			.strip_prefix("/lighting.Controller/")
+			.unwrap_or("unknown")
+			.to_string();
+		let start = Instant::now();
+
+		self.inner
+			.call(request)
+			.map(move |result| {
+				if let Ok(response) = &result {
+					// errors are sent without a body and the status in the headers, successful
+					// responses have it in the trailers
+					let code = response
+						.headers()
+						.get("grpc-status")
+						.and_then(|value| value.to_str().ok())
+						.unwrap_or("0");
+
+					let metrics = metrics();
+					metrics
+						.grpc_requests
+						.with_label_values(&[&method, code])
+						.inc();
+					metrics
+						.grpc_request_time
+						.with_label_values(&[&method])
+						.observe(start.elapsed().as_secs_f64());
+				}
+
+				result
+			})
+			.boxed()
+	}
+}
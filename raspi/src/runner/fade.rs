@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use crate::effects::prelude::lerp;
+
+/// A level that moves linearly to a target over time, like the output level while shutting down.
+#[derive(Copy, Clone, Debug)]
+pub struct Fade {
+	from:     f32,
+	to:       f32,
+	start:    Instant,
+	duration: Duration,
+}
+
+impl Fade {
+	/// Stays at `level` until faded somewhere else.
+	pub fn new(level: f32) -> Self {
+		Fade {
+			from:     level,
+			to:       level,
+			start:    Instant::now(),
+			duration: Duration::ZERO,
+		}
+	}
+
+	/// The level right now.
+	pub fn level(&self) -> f32 {
+		if self.duration.is_zero() {
+			return self.to;
+		}
+
+		let progress = (self.start.elapsed().as_secs_f32() / self.duration.as_secs_f32()).min(1.0);
+		lerp(self.from, self.to, progress)
+	}
+
+	pub fn target(&self) -> f32 {
+		self.to
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.start.elapsed() >= self.duration
+	}
+
+	/// Fades from the current level to `target`, a zero `duration` jumps there.
+	pub fn fade_to(&mut self, target: f32, duration: Duration) {
+		*self = Fade {
+			from: self.level(),
+			to: target,
+			start: Instant::now(),
+			duration,
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+
+	use super::*;
+	use crate::runner::PowerState;
+
+	const FADE: Duration = Duration::from_millis(200);
+
+	#[test]
+	fn stays() {
+		let fade = Fade::new(0.5);
+		assert_eq!(fade.level(), 0.5);
+		assert_eq!(fade.target(), 0.5);
+		assert!(fade.is_done());
+	}
+
+	#[test]
+	fn jumps_without_duration() {
+		let mut fade = Fade::new(1.0);
+		fade.fade_to(0.25, Duration::ZERO);
+		assert_eq!(fade.level(), 0.25);
+		assert!(fade.is_done());
+	}
+
+	#[test]
+	fn fades() {
+		let mut fade = Fade::new(1.0);
+		fade.fade_to(0.0, FADE);
+		assert_eq!(fade.target(), 0.0);
+		assert!(!fade.is_done());
+
+		thread::sleep(FADE / 2);
+		let halfway = fade.level();
+		assert!(halfway <= 0.5, "{}", halfway);
+
+		// turning around starts from the current level instead of jumping back
+		fade.fade_to(1.0, FADE);
+		assert!((fade.level() - halfway).abs() < 0.1, "{}", fade.level());
+
+		thread::sleep(FADE);
+		assert!(fade.is_done());
+		assert_eq!(fade.level(), 1.0);
+	}
+
+	#[test]
+	fn power_states() {
+		for (state, level, duration) in [
+			(PowerState::On, 1.0, FADE),
+			(PowerState::Standby, 0.0, FADE),
+			// doesn't wait for the fade
+			(PowerState::Blackout, 0.0, Duration::ZERO),
+		] {
+			assert_eq!(state.output(FADE), (level, duration), "{:?}", state);
+		}
+
+		let mut output = Fade::new(1.0);
+		let (level, fade) = PowerState::Blackout.output(FADE);
+		output.fade_to(level, fade);
+		assert_eq!(output.level(), 0.0);
+		assert!(output.is_done());
+	}
+}
@@ -0,0 +1,352 @@
+//! The render thread owns the [`EffectRunner`], the apis send it commands through a
+//! [`RunnerHandle`] and answer queries from a [`RunnerSnapshot`], so neither waits for the other.
+
+use std::{
+	collections::HashMap,
+	panic::{self, AssertUnwindSafe},
+	sync::{
+		mpsc::{self, RecvTimeoutError, TryRecvError},
+		Arc,
+	},
+	thread,
+	time::{Duration, Instant},
+};
+
+use eyre::{eyre, Result, WrapErr};
+use tokio::sync::{broadcast, oneshot, watch};
+use tracing::{error, info};
+
+use crate::{
+	config::{DisplayState, GlobalConfig, Script},
+	effects::{script::SCRIPT_EFFECT_PREFIX, EffectData},
+	metrics::metrics,
+	runner::{panic_message, EffectAPI, EffectRunner, Power, StateUpdate},
+	stream::PixelStreams,
+};
+
+/// How long the render loop waits for commands if the fps aren't limited.
+const IDLE_WAIT: Duration = Duration::from_micros(100);
+
+/// Sends the result of a command to the caller.
+type Reply = Box<dyn FnOnce() + Send>;
+
+struct Command {
+	run:     Box<dyn FnOnce(&mut EffectRunner) -> Reply + Send>,
+	/// Read-only commands don't publish a new snapshot.
+	changes: bool,
+	sent:    Instant,
+}
+
+/// Everything the read-only apis need, published after every command that changes the runner.
+///
+/// Parts that didn't change are shared with the previous snapshot. The built-in effects never
+/// change and are kept by the [`RunnerHandle`], the history is only read when it's listed.
+#[derive(Debug)]
+pub struct RunnerSnapshot {
+	pub config:         Arc<GlobalConfig>,
+	pub state:          Arc<DisplayState>,
+	pub presets:        Arc<HashMap<String, DisplayState>>,
+	pub scripts:        Arc<HashMap<String, Script>>,
+	/// The effects of the scripts.
+	pub script_effects: Arc<HashMap<String, EffectData>>,
+	pub power:          Power,
+	revisions:          Revisions,
+}
+
+/// The revisions of the configs a snapshot was taken from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Revisions {
+	config:  u64,
+	state:   u64,
+	presets: u64,
+	scripts: u64,
+}
+
+impl Revisions {
+	fn of(runner: &EffectRunner) -> Self {
+		Revisions {
+			config:  runner.config.revision(),
+			state:   runner.state.revision(),
+			presets: runner.presets.revision(),
+			scripts: runner.scripts.revision(),
+		}
+	}
+}
+
+impl RunnerSnapshot {
+	fn new(runner: &EffectRunner) -> Result<Self> {
+		Ok(RunnerSnapshot {
+			config:         Arc::new((*runner.config).clone()),
+			state:          Arc::new((*runner.state).clone()),
+			presets:        Arc::new(runner.list_presets()?.clone()),
+			scripts:        Arc::new(runner.list_scripts()?.clone()),
+			script_effects: Arc::new(runner.effect_data(is_script)?),
+			power:          runner.get_power()?,
+			revisions:      Revisions::of(runner),
+		})
+	}
+
+	/// Copies the parts of the runner that changed since this snapshot.
+	fn update(&self, runner: &EffectRunner) -> Result<Self> {
+		let revisions = Revisions::of(runner);
+
+		Ok(RunnerSnapshot {
+			config: updated(
+				&self.config,
+				revisions.config != self.revisions.config,
+				|| Ok((*runner.config).clone()),
+			)?,
+			state: updated(&self.state, revisions.state != self.revisions.state, || {
+				Ok((*runner.state).clone())
+			})?,
+			presets: updated(
+				&self.presets,
+				revisions.presets != self.revisions.presets,
+				|| Ok(runner.list_presets()?.clone()),
+			)?,
+			scripts: updated(
+				&self.scripts,
+				revisions.scripts != self.revisions.scripts,
+				|| Ok(runner.list_scripts()?.clone()),
+			)?,
+			// the effects of the scripts are only loaded together with the scripts
+			script_effects: updated(
+				&self.script_effects,
+				revisions.scripts != self.revisions.scripts,
+				|| runner.effect_data(is_script),
+			)?,
+			power: runner.get_power()?,
+			revisions,
+		})
+	}
+}
+
+/// Shares the part of the previous snapshot unless it changed.
+fn updated<T>(
+	previous: &Arc<T>,
+	changed: bool,
+	current: impl FnOnce() -> Result<T>,
+) -> Result<Arc<T>> {
+	if changed {
+		Ok(Arc::new(current()?))
+	} else {
+		Ok(previous.clone())
+	}
+}
+
+fn is_script(effect_id: &str) -> bool {
+	effect_id.starts_with(SCRIPT_EFFECT_PREFIX)
+}
+
+#[derive(Clone)]
+pub struct RunnerHandle {
+	commands:       mpsc::Sender<Command>,
+	snapshot:       watch::Receiver<Arc<RunnerSnapshot>>,
+	/// The built-in effects, without the scripts.
+	effects:        Arc<HashMap<String, EffectData>>,
+	streams:        Arc<PixelStreams>,
+	state_notifier: broadcast::Sender<StateUpdate>,
+}
+
+impl RunnerHandle {
+	/// Starts the render loop on its own thread, rendering a frame every `frame_time` (or as fast as
+	/// possible) and running the commands in between.
+	pub fn spawn(runner: EffectRunner, frame_time: Option<Duration>) -> Result<Self> {
+		let (commands, receiver) = mpsc::channel();
+		let (publisher, snapshot) = watch::channel(Arc::new(RunnerSnapshot::new(&runner)?));
+
+		let handle = RunnerHandle {
+			commands,
+			snapshot,
+			effects: Arc::new(runner.effect_data(|effect_id| !is_script(effect_id))?),
+			streams: runner.streams(),
+			state_notifier: runner.state_notifier.clone(),
+		};
+
+		thread::Builder::new()
+			.name("render".to_string())
+			.spawn(move || {
+				info!("starting effect loop");
+				// panics of effects and commands are caught, anything else (like losing the serial
+				// connection) can't be recovered from without the lights frozen in the last frame
+				let result = panic::catch_unwind(AssertUnwindSafe(|| {
+					render_loop(runner, receiver, publisher, frame_time)
+				}));
+
+				if result.is_err() {
+					error!("effect loop panicked, exiting");
+					std::process::exit(1);
+				}
+			})?;
+
+		Ok(handle)
+	}
+
+	/// Runs `f` on the render thread between two frames and publishes a new snapshot afterwards.
+	pub async fn call<R, F>(&self, f: F) -> Result<R>
+	where
+		R: Send + 'static,
+		F: FnOnce(&mut EffectRunner) -> R + Send + 'static,
+	{
+		self.send(true, f).await
+	}
+
+	/// Like [`RunnerHandle::call`] for reads that aren't in the snapshot, like the render stats.
+	pub async fn read<R, F>(&self, f: F) -> Result<R>
+	where
+		R: Send + 'static,
+		F: FnOnce(&EffectRunner) -> R + Send + 'static,
+	{
+		self.send(false, |runner| f(runner)).await
+	}
+
+	async fn send<R, F>(&self, changes: bool, f: F) -> Result<R>
+	where
+		R: Send + 'static,
+		F: FnOnce(&mut EffectRunner) -> R + Send + 'static,
+	{
+		let (reply, response) = oneshot::channel();
+
+		self.commands
+			.send(Command {
+				run: Box::new(move |runner| {
+					let result = f(runner);
+					// the caller might have given up waiting
+					Box::new(move || {
+						reply.send(result).ok();
+					})
+				}),
+				changes,
+				sent: Instant::now(),
+			})
+			.map_err(|_| eyre!("the render thread stopped"))?;
+
+		response
+			.await
+			.wrap_err("the command panicked or the render thread stopped")
+	}
+
+	/// Fades out the lights (and fades in `preset` if given), flushes the configs and stops the
+	/// render thread.
+	pub async fn shutdown(&self, fade: Duration, preset: Option<String>) -> Result<()> {
+		let (done, stopped) = oneshot::channel();
+
+		self.call(move |runner| runner.begin_shutdown(fade, preset, done))
+			.await?;
+
+		stopped
+			.await
+			.wrap_err("the render thread stopped before the lights were off")?
+	}
+
+	/// The latest published state of the runner.
+	pub fn snapshot(&self) -> Arc<RunnerSnapshot> {
+		self.snapshot.borrow().clone()
+	}
+
+	/// All effects, the built-in ones and those of the scripts in the latest snapshot.
+	pub fn effects(&self) -> HashMap<String, EffectData> {
+		let mut effects = (*self.effects).clone();
+		effects.extend(
+			self.snapshot()
+				.script_effects
+				.iter()
+				.map(|(effect_id, effect)| (effect_id.clone(), effect.clone())),
+		);
+
+		effects
+	}
+
+	/// The frames pushed by clients for the `stream` effect.
+	pub fn streams(&self) -> Arc<PixelStreams> {
+		self.streams.clone()
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<StateUpdate> {
+		self.state_notifier.subscribe()
+	}
+}
+
+fn render_loop(
+	mut runner: EffectRunner,
+	commands: mpsc::Receiver<Command>,
+	publisher: watch::Sender<Arc<RunnerSnapshot>>,
+	frame_time: Option<Duration>,
+) {
+	loop {
+		let start = Instant::now();
+		runner.tick();
+
+		if runner.advance_shutdown() {
+			info!("lights are off, stopping the effect loop");
+			runner.finish_shutdown();
+			return;
+		}
+
+		let next_frame = start + frame_time.unwrap_or(IDLE_WAIT);
+
+		// everything that queued up during the frame runs even if the frame took too long
+		loop {
+			match commands.try_recv() {
+				Ok(command) => run_command(&mut runner, &publisher, command),
+				Err(TryRecvError::Empty) => break,
+				Err(TryRecvError::Disconnected) => return,
+			}
+		}
+
+		// then the rest of the frame time is spent waiting for more
+		while let Some(timeout) = next_frame.checked_duration_since(Instant::now()) {
+			match commands.recv_timeout(timeout) {
+				Ok(command) => run_command(&mut runner, &publisher, command),
+				Err(RecvTimeoutError::Timeout) => break,
+				Err(RecvTimeoutError::Disconnected) => return,
+			}
+		}
+	}
+}
+
+fn run_command(
+	runner: &mut EffectRunner,
+	publisher: &watch::Sender<Arc<RunnerSnapshot>>,
+	command: Command,
+) {
+	metrics()
+		.command_wait_time
+		.observe(command.sent.elapsed().as_secs_f64());
+
+	// a command that panics halfway could leave the state, layout, presets or scripts partly
+	// changed
+	let before = command.changes.then(|| runner.checkpoint());
+
+	let reply = panic::catch_unwind(AssertUnwindSafe(|| (command.run)(runner)))
+		.inspect_err(|payload| {
+			error!("command panicked: {}", panic_message(payload.as_ref()));
+			metrics().error("command_panic");
+		})
+		// without a reply the caller gets an error
+		.ok();
+
+	if let (None, Some(before)) = (&reply, before) {
+		if let Err(err) = runner.restore_checkpoint(before) {
+			error!(
+				"failed to restore the state after a panicked command: {:?}",
+				err
+			);
+		}
+	}
+
+	// published before replying, so callers read their own changes
+	if command.changes {
+		let snapshot = publisher.borrow().update(runner);
+		match snapshot {
+			Ok(snapshot) => {
+				publisher.send_replace(Arc::new(snapshot));
+			}
+			Err(err) => error!("failed to update the snapshot: {:?}", err),
+		}
+	}
+
+	if let Some(reply) = reply {
+		reply();
+	}
+}
@@ -0,0 +1,1818 @@
+use std::{
+	any::Any,
+	collections::{HashMap, HashSet},
+	fmt,
+	mem,
+	panic::{self, AssertUnwindSafe},
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use eyre::{bail, ContextCompat, Result, WrapErr};
+use tokio::sync::{
+	broadcast::{channel, Receiver, Sender},
+	oneshot,
+};
+use tracing::{debug, error, info, warn};
+
+pub use crate::runner::{
+	fade::Fade,
+	handle::{RunnerHandle, RunnerSnapshot},
+};
+use crate::{
+	audio::Audio,
+	color::Rgba,
+	config::{
+		bundle::{Bundle, ImportMode, ImportOptions, BUNDLE_VERSION},
+		history::Snapshot,
+		layout::{self, LayoutReport, SegmentEdit},
+		save_all,
+		Backup,
+		Config,
+		ConfigStore,
+		DisplayState,
+		DisplayStateEffect,
+		EffectModifiers,
+		GlobalConfig,
+		Group,
+		History,
+		Presets,
+		Script,
+		Scripts,
+		SegmentId,
+		Strip,
+	},
+	controller::{Controller, LedController, Section},
+	effects::{
+		prelude::{Stats, Timer},
+		script::{script_effect_id, ScriptEffectFactory, SCRIPT_EFFECT_PREFIX},
+		validate::{merge_with_default, validate, FieldError, ValidationError},
+		Effect,
+		EffectData,
+		EffectFactory,
+		FailedEffect,
+		FrameContext,
+	},
+	metrics::metrics,
+	runner::modifiers::{check_modifiers, EffectClock},
+	stream::PixelStreams,
+};
+
+pub mod fade;
+pub mod handle;
+pub mod modifiers;
+
+pub type EffectsMap = HashMap<String, Box<dyn EffectFactory>>;
+
+#[derive(Clone, Debug, Hash, Ord, PartialOrd, PartialEq, Eq)]
+pub enum EffectTarget {
+	Segment(SegmentId),
+	Group(String),
+}
+
+impl fmt::Display for EffectTarget {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EffectTarget::Segment(id) => write!(f, "segment {}:{}", id.strip_idx, id.segment_idx),
+			EffectTarget::Group(id) => write!(f, "group {}", id),
+		}
+	}
+}
+
+/// Effects over the budget are rendered at least every this many frames.
+const MAX_RENDER_INTERVAL: u32 = 60;
+
+/// Render times of the effect on a target in milliseconds, for all of its segments together.
+#[derive(Clone, Debug, Default)]
+pub struct TargetStats {
+	pub effect_id: String,
+	pub last:      f32,
+	/// Exponential moving average.
+	pub avg:       f32,
+	pub max:       f32,
+	pub frames:    u64,
+	/// Frames that weren't rendered because the effect is over the budget.
+	pub skipped:   u64,
+	/// The effect is rendered every `interval` frames, more than 1 if it's over the budget.
+	pub interval:  u32,
+	/// Message of the panic if the effect failed and was replaced by a black fallback.
+	pub error:     Option<String>,
+	/// Frames to skip until it's rendered again.
+	wait:          u32,
+}
+
+impl TargetStats {
+	fn new(effect_id: &str) -> Self {
+		TargetStats {
+			effect_id: effect_id.to_string(),
+			interval: 1,
+			..Default::default()
+		}
+	}
+
+	/// Adds the time of a frame and updates the interval from the average.
+	fn record(&mut self, time: Duration, budget: Option<Duration>) {
+		let ms = time.as_secs_f32() * 1000.0;
+
+		self.last = ms;
+		self.avg = if self.frames == 0 {
+			ms
+		} else {
+			self.avg * 0.9 + ms * 0.1
+		};
+		self.max = self.max.max(ms);
+		self.frames += 1;
+
+		self.interval = budget.map_or(1, |budget| {
+			let budget = budget.as_secs_f32() * 1000.0;
+			((self.avg / budget).ceil() as u32).clamp(1, MAX_RENDER_INTERVAL)
+		});
+		self.wait = self.interval - 1;
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderStats {
+	pub frame:   Stats,
+	/// Time each effect may take per frame.
+	pub budget:  Option<Duration>,
+	pub targets: Vec<(EffectTarget, TargetStats)>,
+}
+
+/// The effect running on a target.
+struct Instance {
+	effect_id: String,
+	effect:    Box<dyn Effect>,
+	/// The config the effect was last configured with.
+	config:    serde_json::Value,
+	modifiers: EffectModifiers,
+	clock:     EffectClock,
+	/// What the effect rendered for each window before the modifiers were applied, effects that
+	/// fade their last frame don't see the modifiers.
+	canvas:    Vec<Vec<Rgba>>,
+}
+
+impl Instance {
+	fn new(
+		effect_id: String,
+		effect: Box<dyn Effect>,
+		config: serde_json::Value,
+		modifiers: EffectModifiers,
+	) -> Self {
+		Instance {
+			effect_id,
+			effect,
+			config,
+			modifiers,
+			clock: EffectClock::default(),
+			canvas: vec![],
+		}
+	}
+}
+
+/// A change to the instance of a target, prepared by [`EffectRunner::build_instances`].
+struct InstanceChange {
+	target:    EffectTarget,
+	effect_id: String,
+	config:    serde_json::Value,
+	modifiers: EffectModifiers,
+	/// The new instance, `None` sets the config of the running one.
+	effect:    Option<Box<dyn Effect>>,
+}
+
+/// Everything a command can change, taken before running it, see [`EffectRunner::checkpoint`].
+struct Checkpoint {
+	snapshot: Snapshot,
+	/// Revision and data, the data is only written back if the revision changed.
+	presets:  (u64, Presets),
+	scripts:  (u64, Scripts),
+}
+
+/// A shutdown in progress, see [`EffectRunner::begin_shutdown`].
+struct Shutdown {
+	fade:   Duration,
+	/// Faded in once the output is black.
+	preset: Option<DisplayState>,
+	done:   oneshot::Sender<Result<()>>,
+}
+
+pub struct EffectRunner {
+	store:         Arc<dyn ConfigStore>,
+	effects:       EffectsMap,
+	effects_state: HashMap<EffectTarget, Instance>,
+	controller:    Controller,
+	audio:         Audio,
+	streams:       Arc<PixelStreams>,
+
+	config:  Config<GlobalConfig>,
+	state:   Config<DisplayState>,
+	presets: Config<Presets>,
+	scripts: Config<Scripts>,
+	history: Config<History>,
+
+	state_notifier: Sender<StateUpdate>,
+	timer:          Timer,
+	counter:        usize,
+	frame_stats:    Stats,
+	target_stats:   HashMap<EffectTarget, TargetStats>,
+	frame_budget:   Option<Duration>,
+	/// Panic messages of instances that were replaced by a [`FailedEffect`].
+	failures:       HashMap<EffectTarget, String>,
+	power:          Power,
+	/// Multiplied with the brightness, faded by the power state and when shutting down.
+	output:         Fade,
+	master:         Fade,
+	shutdown:       Option<Shutdown>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ApiConfig {
+	pub brightness: f32,
+	pub srgb:       bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PowerState {
+	#[default]
+	On,
+	/// Faded out, the effects are paused if [`GlobalConfig::pause_when_off`] is set.
+	Standby,
+	/// Off right away without fading, otherwise like standby.
+	Blackout,
+}
+
+impl PowerState {
+	/// The output level of the state and how long fading there takes, given the requested fade.
+	fn output(self, fade: Duration) -> (f32, Duration) {
+		match self {
+			PowerState::On => (1.0, fade),
+			PowerState::Standby => (0.0, fade),
+			PowerState::Blackout => (0.0, Duration::ZERO),
+		}
+	}
+}
+
+/// Switches the lights at runtime, unlike the brightness it isn't saved.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Power {
+	pub state:  PowerState,
+	/// Multiplied with the brightness, from 0 to 1.
+	pub master: f32,
+}
+
+impl Default for Power {
+	fn default() -> Self {
+		Power {
+			state:  PowerState::On,
+			master: 1.0,
+		}
+	}
+}
+
+/// Sent to the subscribers whenever the state or the power changes.
+#[derive(Clone, Debug)]
+pub struct StateUpdate {
+	pub state: DisplayState,
+	pub power: Power,
+}
+
+pub trait EffectAPI {
+	fn get_global_config(&self) -> Result<ApiConfig>;
+	fn set_global_config(&mut self, config: ApiConfig) -> Result<()>;
+
+	fn list_segments(&self) -> Result<Vec<Strip>>;
+	/// Replaces the strips, returns the warnings of [`layout::check_layout`].
+	fn set_segments(&mut self, strips: Vec<Strip>) -> Result<Vec<FieldError>>;
+	/// Changes the segments of a strip, the ids in groups, the state and presets are migrated.
+	fn edit_segments(&mut self, edit: SegmentEdit) -> Result<()>;
+	fn list_groups(&self) -> Result<Vec<Group>>;
+	/// Replaces the groups, returns the warnings of [`layout::check_layout`].
+	fn set_groups(&mut self, config: Vec<Group>) -> Result<Vec<FieldError>>;
+	/// Checks the strips and groups without saving them, the current ones are used for the ones
+	/// that aren't given.
+	fn validate_layout(
+		&self,
+		strips: Option<Vec<Strip>>,
+		groups: Option<Vec<Group>>,
+	) -> Result<LayoutReport>;
+
+	fn list_effects(&self) -> Result<HashMap<String, EffectData>>;
+
+	fn list_presets(&self) -> Result<&HashMap<String, DisplayState>>;
+	fn set_preset(&mut self, name: String, preset: DisplayState) -> Result<()>;
+	fn delete_preset(&mut self, name: String) -> Result<()>;
+	fn load_preset(&mut self, name: String) -> Result<()>;
+	fn save_preset(&mut self, name: String) -> Result<()>;
+
+	fn get_state(&self) -> Result<&DisplayState>;
+	fn set_state(&mut self, state: DisplayState) -> Result<()>;
+
+	fn list_scripts(&self) -> Result<&HashMap<String, Script>>;
+	fn set_script(&mut self, name: String, script: Script) -> Result<()>;
+	fn delete_script(&mut self, name: String) -> Result<()>;
+
+	fn list_backups(&self) -> Result<Vec<Backup>>;
+	fn restore_backup(&mut self, config: String, id: String) -> Result<()>;
+
+	/// Reverts the last change in the history, returns `false` if there is nothing to undo.
+	fn undo(&mut self) -> Result<bool>;
+	/// Reapplies the last undone change, returns `false` if there is nothing to redo.
+	fn redo(&mut self) -> Result<bool>;
+	fn list_history(&self) -> Result<&History>;
+
+	fn get_render_stats(&self) -> Result<RenderStats>;
+
+	fn get_power(&self) -> Result<Power>;
+	/// Changes the given parts of the power, fading over `fade` or the time in the config.
+	fn set_power(
+		&mut self,
+		state: Option<PowerState>,
+		master: Option<f32>,
+		fade: Option<Duration>,
+	) -> Result<Power>;
+
+	fn export_bundle(&self) -> Result<Bundle>;
+	fn import_bundle(&mut self, bundle: Bundle, options: ImportOptions) -> Result<()>;
+
+	fn subscribe(&self) -> Receiver<StateUpdate>;
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+	if let Some(message) = payload.downcast_ref::<&str>() {
+		return message.to_string();
+	}
+	if let Some(message) = payload.downcast_ref::<String>() {
+		return message.clone();
+	}
+	"unknown panic".to_string()
+}
+
+fn default_effect(effects: &EffectsMap) -> DisplayStateEffect {
+	let default_effect = effects
+		.keys()
+		.next()
+		.expect("should always have 1 effect in the effects map")
+		.clone();
+
+	let factory = effects.get(&default_effect).unwrap();
+
+	DisplayStateEffect {
+		config:         factory.default_config().unwrap(),
+		config_version: factory.config_version(),
+		effect_id:      default_effect,
+		segment_ids:    HashSet::new(),
+		group_ids:      HashSet::new(),
+		modifiers:      EffectModifiers::default(),
+	}
+}
+
+/// Validates the effects of the state, `path` is the prefix for the fields in the errors.
+///
+/// `scripts` replace the script effects in `effects`, for checking a state together with scripts
+/// that aren't loaded yet.
+fn check_effects(
+	effects: &EffectsMap,
+	state: &DisplayState,
+	path: &str,
+	scripts: Option<&EffectsMap>,
+) -> Vec<FieldError> {
+	let mut errors = vec![];
+
+	for (i, effect) in state.effects.iter().enumerate() {
+		let factory = match scripts {
+			Some(scripts) if effect.effect_id.starts_with(SCRIPT_EFFECT_PREFIX) => {
+				scripts.get(&effect.effect_id)
+			}
+			_ => effects.get(&effect.effect_id),
+		};
+
+		let Some(factory) = factory else {
+			errors.push(FieldError::new(
+				format!("{}effects[{}].effect_id", path, i),
+				format!("unknown effect {}", effect.effect_id),
+			));
+			continue;
+		};
+
+		let config_path = format!("{}effects[{}].config", path, i);
+		let config_errors = validate(&factory.schema(), &effect.config, &config_path);
+		if config_errors.is_empty() {
+			if let Err(err) = factory.check_config(&effect.config) {
+				errors.push(FieldError::new(config_path, format!("{:#}", err)));
+			}
+		}
+		errors.extend(config_errors);
+		errors.extend(check_modifiers(
+			&effect.modifiers,
+			&format!("{}effects[{}].modifiers", path, i),
+		));
+	}
+
+	errors
+}
+
+/// Builds the effects of the scripts, scripts that don't compile are reported in `errors`.
+pub fn script_effects(
+	scripts: &HashMap<String, Script>,
+	errors: &mut Vec<FieldError>,
+) -> EffectsMap {
+	let mut effects: EffectsMap = HashMap::new();
+
+	for (name, script) in scripts.iter() {
+		match ScriptEffectFactory::new(script) {
+			Ok(factory) => {
+				effects.insert(script_effect_id(name), Box::new(factory));
+			}
+			Err(err) => errors.push(FieldError::new(
+				format!("scripts.{}", name),
+				format!("{:#}", err),
+			)),
+		}
+	}
+
+	effects
+}
+
+/// Checks a complete set of configs before it replaces the current one, `effects` are the loaded
+/// effects without the scripts.
+pub fn check_installation(
+	effects: &EffectsMap,
+	config: &GlobalConfig,
+	state: &DisplayState,
+	presets: &HashMap<String, DisplayState>,
+	scripts: &HashMap<String, Script>,
+) -> Vec<FieldError> {
+	let mut errors = check_layout(config, state);
+
+	let scripts = script_effects(scripts, &mut errors);
+
+	errors.extend(check_effects(effects, state, "state.", Some(&scripts)));
+	errors.extend(check_targets(config, state, "state."));
+	for (name, preset) in presets.iter() {
+		let path = format!("presets.{}.", name);
+		errors.extend(check_effects(effects, preset, &path, Some(&scripts)));
+		errors.extend(check_targets(config, preset, &path));
+	}
+
+	errors
+}
+
+/// Reports the errors of [`layout::check_layout`] and groups referencing segments that don't
+/// exist, the problems `validate_config` would fix by dropping them.
+fn check_layout(config: &GlobalConfig, state: &DisplayState) -> Vec<FieldError> {
+	let mut errors = layout::check_layout(config, state)
+		.errors
+		.into_iter()
+		.map(|error| FieldError::new(format!("config.{}", error.field), error.description))
+		.collect::<Vec<_>>();
+
+	for (i, group) in config.groups.iter().enumerate() {
+		for segment_id in group.segment_ids.iter() {
+			if !segment_exists(config, segment_id) {
+				errors.push(FieldError::new(
+					format!("config.groups[{}].segment_ids", i),
+					format!(
+						"segment {} of strip {} doesn't exist",
+						segment_id.segment_idx, segment_id.strip_idx
+					),
+				));
+			}
+		}
+	}
+
+	errors
+}
+
+/// Reports effects targeting segments or groups that don't exist in the config.
+fn check_targets(config: &GlobalConfig, state: &DisplayState, path: &str) -> Vec<FieldError> {
+	let mut errors = vec![];
+
+	for (i, effect) in state.effects.iter().enumerate() {
+		for segment_id in effect.segment_ids.iter() {
+			if !segment_exists(config, segment_id) {
+				errors.push(FieldError::new(
+					format!("{}effects[{}].segment_ids", path, i),
+					format!(
+						"segment {} of strip {} doesn't exist",
+						segment_id.segment_idx, segment_id.strip_idx
+					),
+				));
+			}
+		}
+
+		for group_id in effect.group_ids.iter() {
+			if !config.groups.iter().any(|group| group.id == *group_id) {
+				errors.push(FieldError::new(
+					format!("{}effects[{}].group_ids", path, i),
+					format!("group {} doesn't exist", group_id),
+				));
+			}
+		}
+	}
+
+	errors
+}
+
+fn segment_exists(config: &GlobalConfig, segment_id: &SegmentId) -> bool {
+	config.segment(*segment_id).is_some()
+}
+
+/// Brings the config of a stored effect up to date, by running the migrations of the effect and
+/// merging it with the default config, returns whether anything changed.
+///
+/// Falls back to the default config if a migration fails.
+fn migrate_effect(effects: &EffectsMap, effect: &mut DisplayStateEffect) -> Result<bool> {
+	match effects.get(&effect.effect_id) {
+		Some(factory) => migrate_effect_config(&**factory, effect),
+		None => Ok(false),
+	}
+}
+
+fn migrate_effect_config(
+	factory: &dyn EffectFactory,
+	effect: &mut DisplayStateEffect,
+) -> Result<bool> {
+	let mut changed = false;
+
+	while effect.config_version < factory.config_version() {
+		if let Err(err) = factory.migrate_config(effect.config_version, &mut effect.config) {
+			error!(
+				"failed to migrate config of effect {} from version {}, using the default: {:#}",
+				effect.effect_id, effect.config_version, err
+			);
+			effect.config = factory.default_config()?;
+			effect.config_version = factory.config_version();
+			return Ok(true);
+		}
+
+		info!(
+			"migrated config of effect {} from version {}",
+			effect.effect_id, effect.config_version
+		);
+		effect.config_version += 1;
+		changed = true;
+	}
+
+	let changes = merge_with_default(
+		&factory.schema(),
+		&mut effect.config,
+		&factory.default_config()?,
+	);
+	if !changes.is_empty() {
+		info!(
+			"updated config of effect {}: {}",
+			effect.effect_id,
+			changes.join(", ")
+		);
+		changed = true;
+	}
+
+	Ok(changed)
+}
+
+impl EffectRunner {
+	pub fn new(
+		store: Arc<dyn ConfigStore>,
+		effects: EffectsMap,
+		controller: Controller,
+	) -> Result<Self> {
+		let config = Config::<GlobalConfig>::load(store.clone())?;
+		let state = Config::<DisplayState>::load(store.clone())?;
+		let presets = Config::<Presets>::load(store.clone())?;
+		let scripts = Config::<Scripts>::load(store.clone())?;
+		let mut history = Config::<History>::load(store.clone())?;
+		if !config.persist_history {
+			history.set(Default::default());
+		}
+
+		let audio = Audio::start(&config.audio).unwrap_or_else(|err| {
+			error!("failed to start audio analysis: {:#}", err);
+			Audio::disabled()
+		});
+
+		let mut runner = EffectRunner {
+			store,
+			effects,
+			effects_state: HashMap::new(),
+			controller,
+			audio,
+			streams: Default::default(),
+
+			config,
+			state,
+			presets,
+			scripts,
+			history,
+
+			state_notifier: channel(1).0,
+			timer: Timer::new(),
+			counter: 0,
+			frame_stats: Stats::default(),
+			target_stats: HashMap::new(),
+			frame_budget: None,
+			failures: HashMap::new(),
+			power: Power::default(),
+			output: Fade::new(1.0),
+			master: Fade::new(1.0),
+			shutdown: None,
+		};
+
+		runner.load_scripts();
+
+		let mut presets_changed = false;
+		for preset in runner.presets.0.values_mut() {
+			for effect in preset.effects.iter_mut() {
+				presets_changed |= migrate_effect(&runner.effects, effect)?;
+			}
+		}
+		if presets_changed {
+			runner.presets.save()?;
+		}
+
+		runner.validate_state()?;
+
+		Ok(runner)
+	}
+
+	/// Effects that take longer per frame are rendered less often, `None` renders every effect
+	/// every frame.
+	pub fn set_frame_budget(&mut self, budget: Option<Duration>) {
+		self.frame_budget = budget;
+	}
+
+	/// The frames pushed by clients for the `stream` effect.
+	pub fn streams(&self) -> Arc<PixelStreams> {
+		self.streams.clone()
+	}
+
+	/// Checks that all effects exist and their configs match the schema, so invalid input gets
+	/// rejected before anything is changed.
+	fn check_state(&self, state: &DisplayState) -> Result<()> {
+		let errors = check_effects(&self.effects, state, "", None);
+
+		if !errors.is_empty() {
+			return Err(ValidationError(errors).into());
+		}
+
+		Ok(())
+	}
+
+	/// Configs from the api always have the current version.
+	fn stamp_config_versions(&self, state: &mut DisplayState) {
+		for effect in state.effects.iter_mut() {
+			if let Some(factory) = self.effects.get(&effect.effect_id) {
+				effect.config_version = factory.config_version();
+			}
+		}
+	}
+
+	/// Replaces the state without checking it, unknown effects get replaced by `validate_state`.
+	///
+	/// The previous state and its instances are kept if the instances for the new one can't be
+	/// built.
+	fn apply_state(&mut self, state: DisplayState) -> Result<()> {
+		let previous = (*self.state).clone();
+		self.state.set(state);
+		if let Err(err) = self.validate_state() {
+			self.state.set(previous);
+			return Err(err);
+		}
+		self.state.save()?;
+
+		self.notify();
+
+		Ok(())
+	}
+
+	fn notify(&self) {
+		let update = StateUpdate {
+			state: (*self.state).clone(),
+			power: self.power,
+		};
+
+		// error only means there's no receiver, we don't care if that's the case.
+		self.state_notifier.send(update).ok();
+	}
+
+	fn snapshot(&self) -> Snapshot {
+		Snapshot {
+			state:  (*self.state).clone(),
+			strips: self.config.strips.clone(),
+			groups: self.config.groups.clone(),
+		}
+	}
+
+	/// Goes back to the state and layout of the snapshot, they're saved together.
+	fn restore_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+		let layout_changed =
+			self.config.strips != snapshot.strips || self.config.groups != snapshot.groups;
+		let previous = self.snapshot();
+
+		self.config.strips = snapshot.strips;
+		self.config.groups = snapshot.groups;
+		self.state.set(snapshot.state);
+		if let Err(err) = self.build_state() {
+			self.config.strips = previous.strips;
+			self.config.groups = previous.groups;
+			self.state.set(previous.state);
+			return Err(err);
+		}
+
+		let mut configs = vec![self.state.staged()?];
+		if layout_changed {
+			configs.push(self.config.staged()?);
+		}
+		save_all(&*self.store, &configs)?;
+
+		self.notify();
+
+		Ok(())
+	}
+
+	fn checkpoint(&self) -> Checkpoint {
+		Checkpoint {
+			snapshot: self.snapshot(),
+			presets:  (self.presets.revision(), self.presets.clone()),
+			scripts:  (self.scripts.revision(), self.scripts.clone()),
+		}
+	}
+
+	/// Goes back to the checkpoint after a command failed halfway, the presets and scripts are
+	/// only written back if they were touched.
+	fn restore_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<()> {
+		let Checkpoint {
+			snapshot,
+			presets: (presets_revision, presets),
+			scripts: (scripts_revision, scripts),
+		} = checkpoint;
+
+		if self.presets.revision() != presets_revision {
+			self.presets.set(presets);
+			self.presets.save()?;
+		}
+		if self.scripts.revision() != scripts_revision {
+			self.scripts.set(scripts);
+			self.scripts.save()?;
+			// the instances of the scripts are built again with the state
+			self.load_scripts();
+		}
+
+		self.restore_snapshot(snapshot)
+	}
+
+	fn save_history(&self) {
+		if !self.config.persist_history {
+			return;
+		}
+
+		// losing the history is not worth failing the change for
+		if let Err(err) = self.history.save() {
+			error!("failed to save the history: {:?}", err);
+		}
+	}
+
+	/// Runs a change made through the api and records it in the history if it changed the state,
+	/// segments or groups.
+	pub fn track<R>(
+		&mut self,
+		actor: &str,
+		description: &str,
+		change: impl FnOnce(&mut Self) -> Result<R>,
+	) -> Result<R> {
+		let before = self.snapshot();
+		let result = change(self)?;
+		let after = self.snapshot();
+
+		if before != after {
+			self.history.push(actor, description, before, after);
+			self.save_history();
+		}
+
+		Ok(result)
+	}
+
+	/// Registers an effect for every script, replacing the effects of previously loaded scripts.
+	fn load_scripts(&mut self) {
+		let loaded = self
+			.effects
+			.keys()
+			.filter(|id| id.starts_with(SCRIPT_EFFECT_PREFIX))
+			.cloned()
+			.collect::<Vec<_>>();
+		for effect_id in loaded {
+			self.effects.remove(&effect_id);
+			self.drop_instances(&effect_id);
+		}
+
+		for (name, script) in self.scripts.0.iter() {
+			match ScriptEffectFactory::new(script) {
+				Ok(factory) => {
+					self.effects
+						.insert(script_effect_id(name), Box::new(factory));
+				}
+				Err(err) => error!("failed to load script {}: {:#}", name, err),
+			}
+		}
+	}
+
+	/// Lists the loaded effects whose id matches `filter`.
+	fn effect_data(&self, filter: impl Fn(&str) -> bool) -> Result<HashMap<String, EffectData>> {
+		let mut map = HashMap::with_capacity(self.effects.len());
+
+		for (name, effect_factory) in self.effects.iter().filter(|(name, _)| filter(name)) {
+			map.insert(
+				name.clone(),
+				EffectData {
+					// TODO: add an actual ID?
+					id:             name.clone(),
+					name:           name.clone(),
+					schema:         effect_factory.schema(),
+					default_config: effect_factory.default_config()?,
+				},
+			);
+		}
+
+		Ok(map)
+	}
+
+	/// Drops all running instances of an effect, so they get rebuilt by `validate_state`.
+	fn drop_instances(&mut self, effect_id: &str) {
+		self.effects_state
+			.retain(|_, instance| instance.effect_id != effect_id);
+	}
+
+	#[tracing::instrument(skip(self))]
+	pub fn validate_config(&mut self) -> Result<()> {
+		self.fit_config();
+		self.config.save()?;
+
+		Ok(())
+	}
+
+	/// Like [`EffectRunner::validate_config`] without saving, drops strips, segments and group
+	/// members that don't fit the controller.
+	fn fit_config(&mut self) {
+		let GlobalConfig { strips, groups, .. } = &mut *self.config;
+
+		let ctrl_state = &*self.controller.state_mut();
+
+		if strips.len() > ctrl_state.len() {
+			error!(
+				"configured strips are longer than supported ({} > {}), reducing",
+				strips.len(),
+				ctrl_state.len(),
+			);
+
+			strips.drain(ctrl_state.len()..);
+		}
+
+		for (strip_idx, strip) in strips.iter_mut().enumerate() {
+			let mut led_idx = strip.offset;
+
+			for (idx, segment) in strip.segments.clone().into_iter().enumerate() {
+				if led_idx + segment.length > ctrl_state[0].len() {
+					error!(
+						"configured strip {} segment {} ({}) goes over the max number of LEDs ({} > {}), reducing and dropping any additional segments",
+						strip_idx,
+						idx,
+						segment.name,
+						led_idx + segment.length,
+						ctrl_state[0].len()
+					);
+
+					strip.segments.drain(idx..);
+
+					break;
+				}
+
+				led_idx += segment.length;
+			}
+		}
+
+		for group in groups.iter_mut() {
+			for segment_id in group.segment_ids.clone() {
+				let Some(strip) = strips.get(segment_id.strip_idx) else {
+					error!(
+						"group {} is referencing an invalid strip {} (removed now)",
+						group.name, segment_id.strip_idx
+					);
+
+					group.segment_ids.remove(&segment_id);
+					continue;
+				};
+
+				if strip.segments.get(segment_id.segment_idx).is_none() {
+					error!(
+						"group {} is referencing an invalid segment {} of strip {} (removed now)",
+						group.name, segment_id.segment_idx, segment_id.strip_idx
+					);
+
+					group.segment_ids.remove(&segment_id);
+					continue;
+				}
+			}
+		}
+	}
+
+	#[tracing::instrument(skip(self))]
+	pub fn validate_state(&mut self) -> Result<()> {
+		self.build_state()?;
+		self.state.save()?;
+
+		Ok(())
+	}
+
+	/// Like [`EffectRunner::validate_state`] without saving, for changes that save the state
+	/// together with other configs.
+	fn build_state(&mut self) -> Result<()> {
+		let mut state = mem::take(&mut *self.state);
+		let result = self.build_instances(&mut state);
+		self.state.set(state);
+
+		result
+	}
+
+	/// Fixes references to missing effects, segments and groups in the state and builds the
+	/// instances for it.
+	///
+	/// The running instances are only changed once all new ones could be built, so they keep
+	/// matching the previous state if it fails.
+	fn build_instances(&mut self, state: &mut DisplayState) -> Result<()> {
+		let mut changes = vec![];
+
+		if state.effects.is_empty() {
+			let mut effect = default_effect(&self.effects);
+
+			effect.segment_ids = self.config.segments().map(|(id, ..)| id).collect();
+
+			state.effects = vec![effect];
+		}
+
+		for effect in state.effects.iter_mut() {
+			if !self.effects.contains_key(&effect.effect_id) {
+				let default = default_effect(&self.effects);
+
+				effect.effect_id = default.effect_id;
+				effect.config = default.config;
+				effect.config_version = default.config_version;
+			}
+
+			migrate_effect(&self.effects, effect)?;
+
+			let mut targets: Vec<EffectTarget> = vec![];
+
+			for segment_id in effect.segment_ids.clone() {
+				let Some(strip) = self.config.strips.get(segment_id.strip_idx) else {
+					error!(
+						"effect {} is referencing an invalid strip {}",
+						effect.effect_id, segment_id.strip_idx
+					);
+
+					effect.segment_ids.remove(&segment_id);
+					continue;
+				};
+
+				if strip.segments.get(segment_id.segment_idx).is_none() {
+					error!(
+						"effect {} is referencing an invalid segment {} of strip {}",
+						effect.effect_id, segment_id.segment_idx, segment_id.strip_idx
+					);
+
+					effect.segment_ids.remove(&segment_id);
+					continue;
+				}
+
+				targets.push(EffectTarget::Segment(segment_id));
+			}
+
+			for group_id in effect.group_ids.clone() {
+				if !self.config.groups.iter().any(|group| group.id == group_id) {
+					error!(
+						"effect {} is referencing group {} not found in the config",
+						effect.effect_id, group_id,
+					);
+
+					effect.group_ids.remove(&group_id);
+					continue;
+				};
+
+				targets.push(EffectTarget::Group(group_id));
+			}
+
+			let factory = self
+				.effects
+				.get(&effect.effect_id)
+				.wrap_err("effect factory should exist")?;
+
+			for target in targets {
+				// failed instances get another chance with a new one
+				let reuse = self
+					.effects_state
+					.get(&target)
+					.is_some_and(|instance| instance.effect_id == effect.effect_id)
+					&& !self.failures.contains_key(&target);
+
+				let effect_instance = if reuse {
+					None
+				} else {
+					Some(factory.build(effect.config.clone())?)
+				};
+
+				changes.push(InstanceChange {
+					target,
+					effect_id: effect.effect_id.clone(),
+					config: effect.config.clone(),
+					modifiers: effect.modifiers.clone(),
+					effect: effect_instance,
+				});
+			}
+		}
+
+		self.apply_instance_changes(changes)
+	}
+
+	/// Swaps in the instances prepared by `build_instances` and drops the ones of targets that
+	/// aren't in the changes. If a reused instance rejects its new config, the ones configured
+	/// before it get their previous config back and nothing else is changed.
+	fn apply_instance_changes(&mut self, changes: Vec<InstanceChange>) -> Result<()> {
+		let mut configured: Vec<(&EffectTarget, serde_json::Value)> = vec![];
+
+		for change in changes.iter().filter(|change| change.effect.is_none()) {
+			let instance = self
+				.effects_state
+				.get_mut(&change.target)
+				.wrap_err("reused instance should exist")?;
+
+			if let Err(err) = instance.effect.set_config(change.config.clone()) {
+				let err = err.wrap_err(format!("setting config for effect {}", instance.effect_id));
+
+				for (target, previous) in configured {
+					let instance = self.effects_state.get_mut(target).unwrap();
+					if let Err(err) = instance.effect.set_config(previous.clone()) {
+						error!(
+							"failed to restore the config of effect {} on {}: {:?}",
+							instance.effect_id, target, err
+						);
+					}
+					instance.config = previous;
+				}
+
+				return Err(err);
+			}
+
+			let previous = mem::replace(&mut instance.config, change.config.clone());
+			configured.push((&change.target, previous));
+		}
+
+		// targets that left the state stop rendering
+		let targets = changes
+			.iter()
+			.map(|change| change.target.clone())
+			.collect::<HashSet<_>>();
+
+		for change in changes {
+			let InstanceChange {
+				target,
+				effect_id,
+				config,
+				modifiers,
+				effect,
+			} = change;
+
+			let Some(effect) = effect else {
+				if let Some(instance) = self.effects_state.get_mut(&target) {
+					instance.modifiers = modifiers;
+				}
+				continue;
+			};
+
+			self.failures.remove(&target);
+			match self.effects_state.get_mut(&target) {
+				Some(instance) => {
+					instance.effect = effect;
+					instance.effect_id = effect_id;
+					instance.config = config;
+					instance.modifiers = modifiers;
+					instance.canvas.clear();
+				}
+				None => {
+					self.effects_state
+						.insert(target, Instance::new(effect_id, effect, config, modifiers));
+				}
+			}
+		}
+
+		self.effects_state
+			.retain(|target, _| targets.contains(target));
+		self.failures.retain(|target, _| targets.contains(target));
+		self.target_stats
+			.retain(|target, _| targets.contains(target));
+
+		Ok(())
+	}
+
+	/// Starts fading out the output, then the `preset` is faded in if it exists. The render loop
+	/// keeps going until [`EffectRunner::advance_shutdown`] says the lights are off.
+	pub fn begin_shutdown(
+		&mut self,
+		fade: Duration,
+		preset: Option<String>,
+		done: oneshot::Sender<Result<()>>,
+	) {
+		let preset = preset.and_then(|name| {
+			let preset = self.presets.0.get(&name).cloned();
+			if preset.is_none() {
+				warn!("shutdown preset {} doesn't exist, fading to black", name);
+			}
+			preset
+		});
+
+		info!("fading out over {:?}", fade);
+		self.output.fade_to(0.0, fade);
+		self.shutdown = Some(Shutdown { fade, preset, done });
+	}
+
+	/// Switches to the shutdown preset once the output faded out, true when the shutdown is done.
+	pub fn advance_shutdown(&mut self) -> bool {
+		let Some(shutdown) = &mut self.shutdown else {
+			return false;
+		};
+		if !self.output.is_done() {
+			return false;
+		}
+		let Some(mut preset) = shutdown.preset.take() else {
+			return true;
+		};
+		let fade = shutdown.fade;
+
+		// only shown until the process exits, the saved state is loaded on the next start
+		self.power.state = PowerState::On;
+		match self.build_instances(&mut preset) {
+			Ok(()) => self.output.fade_to(1.0, fade),
+			Err(err) => error!("failed to show the shutdown preset: {:?}", err),
+		}
+
+		false
+	}
+
+	/// Writes the last frame, saves what isn't saved on every change and tells the caller of
+	/// [`EffectRunner::begin_shutdown`] that the lights are off.
+	pub fn finish_shutdown(&mut self) {
+		let Some(shutdown) = self.shutdown.take() else {
+			return;
+		};
+
+		// the last frame might have been rendered just before the fade was done
+		self.controller
+			.write_state(&self.config, self.output.target() * self.master.target());
+
+		self.save_history();
+		shutdown.done.send(self.store.flush()).ok();
+	}
+
+	/// Renders every effect into the controller, unless it is throttled or failed.
+	fn render_effects(&mut self) {
+		let audio = self.audio.frame();
+		let mut views = self.controller.views_mut(&self.config);
+
+		for (target, instance) in self.effects_state.iter_mut() {
+			let name = &instance.effect_id;
+			let segments_ids = match target {
+				EffectTarget::Segment(segment_id) => vec![*segment_id],
+				EffectTarget::Group(group_id) => {
+					let Some(group) = self
+						.config
+						.groups
+						.iter()
+						.find(|group| group.id == *group_id)
+					else {
+						error!(
+							"effect {} is referencing group {} not found in the config",
+							name, group_id,
+						);
+						metrics().error("render");
+						continue;
+					};
+
+					// sorted, so the order is stable for effects spanning the whole group
+					let mut segment_ids: Vec<_> = group.segment_ids.iter().copied().collect();
+					segment_ids.sort();
+					segment_ids
+				}
+			};
+
+			let stats = self
+				.target_stats
+				.entry(target.clone())
+				.or_insert_with(|| TargetStats::new(name));
+			if stats.effect_id != *name {
+				*stats = TargetStats::new(name);
+			}
+
+			// over the budget, keeps the pixels of the last frame it rendered
+			if stats.wait > 0 {
+				stats.wait -= 1;
+				stats.skipped += 1;
+				continue;
+			}
+
+			// segment and length of every window
+			let mut windows = Vec::with_capacity(segments_ids.len());
+			for segment_id in segments_ids {
+				let Some(section) = views.get(segment_id) else {
+					error!(
+						"effect {} is referencing an invalid segment {} of strip {}",
+						name, segment_id.segment_idx, segment_id.strip_idx
+					);
+					metrics().error("render");
+					continue;
+				};
+
+				windows.push((segment_id, section.len()));
+			}
+
+			let modifiers = &instance.modifiers;
+			instance.canvas.resize_with(windows.len(), Vec::new);
+			for (canvas, (_, len)) in instance.canvas.iter_mut().zip(windows.iter()) {
+				canvas.resize(modifiers::window_len(modifiers, *len), Rgba::default());
+			}
+
+			let steps = instance
+				.clock
+				.advance(modifiers.speed, instance.effect.stepped());
+			let mut render_time = Duration::ZERO;
+			'render: for _ in 0..steps {
+				for (window_idx, canvas) in instance.canvas.iter_mut().enumerate() {
+					// nothing left of the segment after the modifiers
+					if canvas.is_empty() {
+						continue;
+					}
+
+					let ctx = FrameContext {
+						audio: &audio,
+						streams: &self.streams,
+						target,
+						segment: window_idx,
+						now: instance.clock.now(),
+					};
+					let window = Section::new(canvas, modifiers.reverse);
+
+					let start = Instant::now();
+					let result =
+						panic::catch_unwind(AssertUnwindSafe(|| instance.effect.run(window, &ctx)));
+					let elapsed = start.elapsed();
+					metrics()
+						.effect_render_time
+						.with_label_values(&[name])
+						.observe(elapsed.as_secs_f64());
+					render_time += elapsed;
+
+					if let Err(payload) = result {
+						let message = panic_message(payload.as_ref());
+						error!(
+							"effect {} on {} panicked, replacing it with black: {}",
+							name, target, message
+						);
+						metrics().error("effect_panic");
+
+						instance.effect = Box::new(FailedEffect);
+						self.failures.insert(target.clone(), message);
+						break 'render;
+					}
+				}
+			}
+
+			for (canvas, (segment_id, _)) in instance.canvas.iter().zip(windows.into_iter()) {
+				if let Some(section) = views.get_mut(segment_id) {
+					modifiers::apply(modifiers, canvas, section);
+				}
+			}
+
+			// frozen or slowed down effects don't render every frame
+			if steps == 0 {
+				continue;
+			}
+
+			let interval = stats.interval;
+			stats.record(render_time, self.frame_budget);
+			if stats.interval > 1 && interval == 1 {
+				warn!(
+					"effect {} on {} is over the budget ({:.2}ms), rendering it every {} frames",
+					name, target, stats.avg, stats.interval
+				);
+			} else if stats.interval == 1 && interval > 1 {
+				info!("effect {} on {} is within the budget again", name, target);
+			}
+		}
+	}
+
+	pub fn tick(&mut self) {
+		// nothing to see while off, the effects continue where they stopped
+		let paused = self.config.pause_when_off
+			&& self.power.state != PowerState::On
+			&& self.output.is_done();
+		if !paused {
+			self.render_effects();
+		}
+
+		self.controller
+			.write_state(&self.config, self.output.level() * self.master.level());
+
+		let stats = self.timer.tick();
+		self.frame_stats = stats;
+		let metrics = metrics();
+		metrics.frame_time.observe(stats.dt as f64 / 1000.0);
+		metrics.fps.set(1000.0 / stats.avg as f64);
+		metrics
+			.state_subscribers
+			.set(self.state_notifier.receiver_count() as i64);
+		if self.counter == 0 {
+			debug!(
+				"avg time to update: {:.2}ms (now {:.2}ms, min {:.2}ms, max {:.2}ms)",
+				stats.avg, stats.dt, stats.min, stats.max
+			);
+		}
+		self.counter = (self.counter + 1) % 60;
+	}
+}
+
+impl EffectAPI for EffectRunner {
+	#[tracing::instrument(skip(self))]
+	fn get_global_config(&self) -> Result<ApiConfig> {
+		Ok(ApiConfig {
+			brightness: self.config.brightness,
+			srgb:       self.config.as_srgb,
+		})
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn set_global_config(&mut self, config: ApiConfig) -> Result<()> {
+		self.config.brightness = config.brightness;
+		self.config.as_srgb = config.srgb;
+		self.config.save()?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_segments(&self) -> Result<Vec<Strip>> {
+		return Ok(self.config.strips.clone());
+	}
+
+	#[tracing::instrument(skip(self, strips))]
+	fn set_segments(&mut self, strips: Vec<Strip>) -> Result<Vec<FieldError>> {
+		let warnings = self
+			.validate_layout(Some(strips.clone()), None)?
+			.into_result()?;
+
+		self.config.strips = strips;
+		self.build_state()?;
+		save_all(&*self.store, &[self.config.staged()?, self.state.staged()?])?;
+
+		Ok(warnings)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn edit_segments(&mut self, edit: SegmentEdit) -> Result<()> {
+		let mut strips = self.config.strips.clone();
+		edit.apply(&mut strips)?;
+		self.config.strips = strips;
+
+		for group in self.config.groups.iter_mut() {
+			edit.migrate_ids(&mut group.segment_ids);
+		}
+		for effect in self.state.effects.iter_mut() {
+			edit.migrate_ids(&mut effect.segment_ids);
+		}
+		for preset in self.presets.0.values_mut() {
+			for effect in preset.effects.iter_mut() {
+				edit.migrate_ids(&mut effect.segment_ids);
+			}
+		}
+
+		// instances are keyed by the old ids, the ones on groups are fine
+		self.effects_state
+			.retain(|target, _| matches!(target, EffectTarget::Group(_)));
+
+		self.build_state()?;
+		save_all(
+			&*self.store,
+			&[
+				self.config.staged()?,
+				self.state.staged()?,
+				self.presets.staged()?,
+			],
+		)?;
+		self.notify();
+
+		info!("edited segments: {:?}", edit);
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_groups(&self) -> Result<Vec<Group>> {
+		return Ok(self.config.groups.clone());
+	}
+
+	#[tracing::instrument(skip(self, groups))]
+	fn set_groups(&mut self, groups: Vec<Group>) -> Result<Vec<FieldError>> {
+		let warnings = self
+			.validate_layout(None, Some(groups.clone()))?
+			.into_result()?;
+
+		self.config.groups = groups;
+		self.build_state()?;
+		save_all(&*self.store, &[self.config.staged()?, self.state.staged()?])?;
+
+		Ok(warnings)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn validate_layout(
+		&self,
+		strips: Option<Vec<Strip>>,
+		groups: Option<Vec<Group>>,
+	) -> Result<LayoutReport> {
+		let mut config = (*self.config).clone();
+		if let Some(strips) = strips {
+			config.strips = strips;
+		}
+		if let Some(groups) = groups {
+			config.groups = groups;
+		}
+
+		Ok(layout::check_layout(&config, &self.state))
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_effects(&self) -> Result<HashMap<String, EffectData>> {
+		self.effect_data(|_| true)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_presets(&self) -> Result<&HashMap<String, DisplayState>> {
+		Ok(&self.presets.0)
+	}
+
+	#[tracing::instrument(skip(self, preset))]
+	fn set_preset(&mut self, name: String, mut preset: DisplayState) -> Result<()> {
+		self.check_state(&preset)?;
+		self.stamp_config_versions(&mut preset);
+
+		self.presets.0.insert(name, preset);
+		self.presets.save()?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn delete_preset(&mut self, name: String) -> Result<()> {
+		self.presets.0.remove(&name);
+		self.presets.save()?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn load_preset(&mut self, name: String) -> Result<()> {
+		let Some(state) = self.presets.0.get(&name) else {
+			bail!("preset not found: {}", name);
+		};
+		// presets were checked when they were saved, but effects might be gone since then
+		self.apply_state(state.clone())?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn save_preset(&mut self, name: String) -> Result<()> {
+		self.presets.0.insert(name, self.state.clone());
+		self.presets.save()?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn get_state(&self) -> Result<&DisplayState> {
+		Ok(&self.state)
+	}
+
+	#[tracing::instrument(skip(self, state))]
+	fn set_state(&mut self, mut state: DisplayState) -> Result<()> {
+		self.check_state(&state)?;
+		self.stamp_config_versions(&mut state);
+		self.apply_state(state)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_scripts(&self) -> Result<&HashMap<String, Script>> {
+		Ok(&self.scripts.0)
+	}
+
+	#[tracing::instrument(skip(self, script))]
+	fn set_script(&mut self, name: String, script: Script) -> Result<()> {
+		let factory = ScriptEffectFactory::new(&script)
+			.wrap_err_with(|| format!("loading script {}", name))?;
+
+		let effect_id = script_effect_id(&name);
+		self.effects.insert(effect_id.clone(), Box::new(factory));
+		self.drop_instances(&effect_id);
+
+		self.scripts.0.insert(name, script);
+		self.scripts.save()?;
+
+		self.validate_state()?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn delete_script(&mut self, name: String) -> Result<()> {
+		let effect_id = script_effect_id(&name);
+		self.effects.remove(&effect_id);
+		self.drop_instances(&effect_id);
+
+		self.scripts.0.remove(&name);
+		self.scripts.save()?;
+
+		self.validate_state()?;
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_backups(&self) -> Result<Vec<Backup>> {
+		let mut backups = self.config.backups()?;
+		backups.extend(self.state.backups()?);
+		backups.extend(self.presets.backups()?);
+		backups.extend(self.scripts.backups()?);
+
+		backups.sort_by(|a, b| b.created.cmp(&a.created));
+
+		Ok(backups)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn restore_backup(&mut self, config: String, id: String) -> Result<()> {
+		if config == self.config.name() {
+			self.config.restore(&id)?;
+			self.validate_state()?;
+		} else if config == self.state.name() {
+			self.state.restore(&id)?;
+			self.validate_state()?;
+
+			self.notify();
+		} else if config == self.presets.name() {
+			self.presets.restore(&id)?;
+		} else if config == self.scripts.name() {
+			self.scripts.restore(&id)?;
+			self.load_scripts();
+			self.validate_state()?;
+		} else {
+			bail!("unknown config: {}", config);
+		}
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn undo(&mut self) -> Result<bool> {
+		let Some(entry) = self.history.next_undo() else {
+			return Ok(false);
+		};
+		let (id, before) = (entry.id, entry.before.clone());
+
+		// the cursor only moves once the snapshot is applied, a failed undo can be retried
+		self.restore_snapshot(before)?;
+		self.history.undo();
+		self.save_history();
+
+		info!("undid change {}", id);
+
+		Ok(true)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn redo(&mut self) -> Result<bool> {
+		let Some(entry) = self.history.next_redo() else {
+			return Ok(false);
+		};
+		let (id, after) = (entry.id, entry.after.clone());
+
+		// the cursor only moves once the snapshot is applied, a failed redo can be retried
+		self.restore_snapshot(after)?;
+		self.history.redo();
+		self.save_history();
+
+		info!("redid change {}", id);
+
+		Ok(true)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn list_history(&self) -> Result<&History> {
+		Ok(&self.history)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn get_render_stats(&self) -> Result<RenderStats> {
+		let mut targets = self
+			.target_stats
+			.iter()
+			.filter(|(target, _)| self.effects_state.contains_key(*target))
+			.map(|(target, stats)| {
+				let mut stats = stats.clone();
+				stats.error = self.failures.get(target).cloned();
+				(target.clone(), stats)
+			})
+			.collect::<Vec<_>>();
+		targets.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+		Ok(RenderStats {
+			frame: self.frame_stats,
+			budget: self.frame_budget,
+			targets,
+		})
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn export_bundle(&self) -> Result<Bundle> {
+		Ok(Bundle {
+			version: BUNDLE_VERSION,
+			config:  (*self.config).clone(),
+			state:   (*self.state).clone(),
+			presets: self.presets.0.clone(),
+			scripts: self.scripts.0.clone(),
+		})
+	}
+
+	#[tracing::instrument(skip(self, bundle))]
+	fn import_bundle(&mut self, mut bundle: Bundle, options: ImportOptions) -> Result<()> {
+		// merging keeps the current strips, the segment ids of the bundle only fit them if
+		// they're the same or get mapped onto them
+		if options.mode == ImportMode::Merge
+			&& options.remap.is_empty()
+			&& bundle.config.strips != self.config.strips
+		{
+			return Err(ValidationError(vec![FieldError::new(
+				"config.strips",
+				"the strips of the bundle differ from the current ones, map its segments or replace the config",
+			)])
+			.into());
+		}
+
+		bundle.remap(&options.remap);
+
+		let (config, mut presets, scripts) = match options.mode {
+			ImportMode::Replace => (bundle.config, bundle.presets, bundle.scripts),
+			ImportMode::Merge => {
+				let mut config = (*self.config).clone();
+				for group in bundle.config.groups {
+					match config
+						.groups
+						.iter_mut()
+						.find(|existing| existing.id == group.id)
+					{
+						Some(existing) => *existing = group,
+						None => config.groups.push(group),
+					}
+				}
+
+				let mut presets = self.presets.0.clone();
+				presets.extend(bundle.presets);
+				let mut scripts = self.scripts.0.clone();
+				scripts.extend(bundle.scripts);
+
+				(config, presets, scripts)
+			}
+		};
+		let state = bundle.state;
+
+		let errors = check_installation(&self.effects, &config, &state, &presets, &scripts);
+		if !errors.is_empty() {
+			return Err(ValidationError(errors).into());
+		}
+
+		if options.dry_run {
+			return Ok(());
+		}
+
+		// the presets are migrated with the effects of the imported scripts, before anything
+		// is replaced
+		let imported_scripts = script_effects(&scripts, &mut vec![]);
+		for preset in presets.values_mut() {
+			for effect in preset.effects.iter_mut() {
+				let factory = if effect.effect_id.starts_with(SCRIPT_EFFECT_PREFIX) {
+					imported_scripts.get(&effect.effect_id)
+				} else {
+					self.effects.get(&effect.effect_id)
+				};
+				if let Some(factory) = factory {
+					migrate_effect_config(&**factory, effect)?;
+				}
+			}
+		}
+
+		let previous = (
+			(*self.config).clone(),
+			(*self.state).clone(),
+			self.presets.0.clone(),
+			self.scripts.0.clone(),
+		);
+
+		self.config.set(config);
+		self.state.set(state);
+		self.presets.set(Presets(presets));
+		self.scripts.set(Scripts(scripts));
+		self.load_scripts();
+		self.fit_config();
+
+		let result = self.build_state().and_then(|()| {
+			save_all(
+				&*self.store,
+				&[
+					self.config.staged()?,
+					self.state.staged()?,
+					self.presets.staged()?,
+					self.scripts.staged()?,
+				],
+			)
+		});
+		if let Err(err) = result {
+			let (config, state, presets, scripts) = previous;
+			self.config.set(config);
+			self.state.set(state);
+			self.presets.set(Presets(presets));
+			self.scripts.set(Scripts(scripts));
+			self.load_scripts();
+			// loading the scripts dropped the instances of the script effects
+			if let Err(err) = self.build_state() {
+				error!(
+					"failed to restore the state after a failed import: {:?}",
+					err
+				);
+			}
+
+			return Err(err);
+		}
+
+		self.notify();
+
+		info!("imported bundle ({:?})", options.mode);
+
+		Ok(())
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn get_power(&self) -> Result<Power> {
+		Ok(self.power)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn set_power(
+		&mut self,
+		state: Option<PowerState>,
+		master: Option<f32>,
+		fade: Option<Duration>,
+	) -> Result<Power> {
+		if self.shutdown.is_some() {
+			bail!("the lights are shutting down");
+		}
+
+		if let Some(master) = master {
+			if !(0.0..=1.0).contains(&master) {
+				return Err(ValidationError(vec![FieldError::new(
+					"master",
+					"has to be between 0 and 1",
+				)])
+				.into());
+			}
+		}
+
+		let fade = fade.unwrap_or(Duration::from_millis(self.config.power_fade_ms));
+
+		if let Some(master) = master {
+			self.power.master = master;
+			self.master.fade_to(master, fade);
+		}
+
+		if let Some(state) = state {
+			self.power.state = state;
+			let (level, fade) = state.output(fade);
+			self.output.fade_to(level, fade);
+		}
+
+		info!(
+			"power {:?}, master at {:.2}",
+			self.power.state, self.power.master
+		);
+		self.notify();
+
+		Ok(self.power)
+	}
+
+	fn subscribe(&self) -> Receiver<StateUpdate> {
+		self.state_notifier.subscribe()
+	}
+}
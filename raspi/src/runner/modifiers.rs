@@ -0,0 +1,557 @@
+//! The [`EffectModifiers`] of a target, applied by the runner to whatever the effect renders.
+
+use std::time::Instant;
+
+use palette::{encoding, FromColor, Hsv, LinSrgb, WithAlpha};
+
+use crate::{
+	color::Rgba,
+	config::EffectModifiers,
+	controller::Section,
+	effects::validate::FieldError,
+};
+
+/// Effects run at most this many times faster.
+pub const MAX_SPEED: f32 = 8.0;
+
+type LinHsv = Hsv<encoding::Linear<encoding::Srgb>>;
+
+/// The time of a target, runs faster or slower than the real time with the speed modifier.
+#[derive(Copy, Clone, Debug)]
+pub struct EffectClock {
+	real:  Instant,
+	now:   Instant,
+	/// Steps of stepped effects that are due, only whole steps are rendered.
+	steps: f32,
+}
+
+impl Default for EffectClock {
+	fn default() -> Self {
+		let now = Instant::now();
+
+		EffectClock {
+			real: now,
+			now,
+			steps: 0.0,
+		}
+	}
+}
+
+impl EffectClock {
+	/// The time passed to the effect in the [`crate::effects::FrameContext`].
+	pub fn now(&self) -> Instant {
+		self.now
+	}
+
+	/// Moves the clock forward by the real time since the last frame times `speed`, returns how
+	/// many times the effect is run this frame.
+	///
+	/// Effects following the time run once per frame unless they're frozen. Effects that move by
+	/// a fixed step every time they run (see [`crate::effects::Effect::stepped`]) only get faster
+	/// by running more often.
+	pub fn advance(&mut self, speed: f32, stepped: bool) -> usize {
+		let speed = speed.clamp(0.0, MAX_SPEED);
+
+		let real = Instant::now();
+		self.now += (real - self.real).mul_f32(speed);
+		self.real = real;
+
+		if !stepped {
+			return usize::from(speed > 0.0);
+		}
+
+		self.steps += speed;
+		let steps = self.steps.floor();
+		self.steps -= steps;
+
+		steps as usize
+	}
+}
+
+/// Which LEDs of a segment show which pixel of the window, see [`Layout::source`].
+#[derive(Copy, Clone, Debug)]
+struct Layout {
+	start:   usize,
+	end:     usize,
+	skip:    usize,
+	/// LEDs between `start` and `end` that aren't skipped.
+	visible: usize,
+	/// Visible LEDs per repetition.
+	tile:    usize,
+	/// Length of the window the effect renders.
+	window:  usize,
+	offset:  usize,
+	mirror:  bool,
+}
+
+impl Layout {
+	fn new(modifiers: &EffectModifiers, len: usize) -> Self {
+		let end = modifiers.end.unwrap_or(len).min(len);
+		let start = modifiers.start.min(end);
+
+		let skip = if modifiers.skip > 1 {
+			modifiers.skip
+		} else {
+			0
+		};
+		let skipped = if skip > 0 { (end - start) / skip } else { 0 };
+		let visible = end - start - skipped;
+
+		let tile = visible.div_ceil(modifiers.repeat.max(1));
+		let window = if modifiers.mirror {
+			tile.div_ceil(2)
+		} else {
+			tile
+		};
+
+		Layout {
+			start,
+			end,
+			skip,
+			visible,
+			tile,
+			window,
+			offset: (modifiers.offset as i64).rem_euclid(visible.max(1) as i64) as usize,
+			mirror: modifiers.mirror,
+		}
+	}
+
+	/// Index in the window shown by the `visible`-th visible LED.
+	fn source(&self, visible: usize) -> usize {
+		// rotated, then tiled and mirrored
+		let idx = (visible + self.visible - self.offset) % self.visible;
+		let idx = idx % self.tile;
+
+		if self.mirror && idx >= self.window {
+			self.tile - 1 - idx
+		} else {
+			idx
+		}
+	}
+}
+
+/// Length of the window the effect renders for a segment of `len` LEDs.
+pub fn window_len(modifiers: &EffectModifiers, len: usize) -> usize {
+	Layout::new(modifiers, len).window
+}
+
+/// Copies what the effect rendered onto the segment, transformed and with the colors changed,
+/// LEDs outside of `start` and `end` are left alone and skipped ones are turned off.
+pub fn apply(modifiers: &EffectModifiers, canvas: &[Rgba], section: &mut Section) {
+	let layout = Layout::new(modifiers, section.len());
+	if layout.start == layout.end || canvas.len() != layout.window {
+		return;
+	}
+
+	let mut visible = 0;
+	for (i, led) in section
+		.range(layout.start..layout.end)
+		.iter_mut()
+		.enumerate()
+	{
+		if layout.skip > 0 && (i + 1) % layout.skip == 0 {
+			*led = Rgba::default();
+			continue;
+		}
+
+		*led = modify(modifiers, canvas[layout.source(visible)]);
+		visible += 1;
+	}
+}
+
+fn modify(modifiers: &EffectModifiers, color: Rgba) -> Rgba {
+	let (mut rgb, alpha) = color.split();
+
+	if modifiers.hue != 0.0 || modifiers.saturation != 1.0 {
+		let mut hsv = LinHsv::from_color(rgb);
+		hsv.hue += modifiers.hue;
+		hsv.saturation = (hsv.saturation * modifiers.saturation).clamp(0.0, 1.0);
+		rgb = LinSrgb::from_color(hsv);
+	}
+
+	rgb.with_alpha(alpha * modifiers.opacity.clamp(0.0, 1.0))
+		.into()
+}
+
+/// Reports modifiers out of range, `path` is the prefix for the fields in the errors.
+pub fn check_modifiers(modifiers: &EffectModifiers, path: &str) -> Vec<FieldError> {
+	let mut errors = vec![];
+
+	let ranges = [
+		("opacity", modifiers.opacity, 0.0, 1.0),
+		("speed", modifiers.speed, 0.0, MAX_SPEED),
+		("hue", modifiers.hue, -360.0, 360.0),
+		("saturation", modifiers.saturation, 0.0, 2.0),
+	];
+	for (field, value, min, max) in ranges {
+		if !(min..=max).contains(&value) {
+			errors.push(FieldError::new(
+				format!("{}.{}", path, field),
+				format!("has to be between {} and {}", min, max),
+			));
+		}
+	}
+
+	if modifiers.repeat == 0 {
+		errors.push(FieldError::new(
+			format!("{}.repeat", path),
+			"has to be at least 1",
+		));
+	}
+	if modifiers.skip == 1 {
+		errors.push(FieldError::new(
+			format!("{}.skip", path),
+			"would turn off every LED",
+		));
+	}
+	if modifiers.end.is_some_and(|end| end <= modifiers.start) {
+		errors.push(FieldError::new(
+			format!("{}.end", path),
+			"has to be after the start",
+		));
+	}
+
+	errors
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{thread, time::Duration};
+
+	use super::*;
+
+	/// A canvas where every pixel has its index plus one as red value, see [`indices`].
+	fn canvas(len: usize) -> Vec<Rgba> {
+		(0..len)
+			.map(|i| Rgba::new(i as f32 + 1.0, 0.0, 0.0, 1.0))
+			.collect()
+	}
+
+	/// The canvas index shown by every LED, `None` for LEDs that are off.
+	fn indices(leds: &[Rgba]) -> Vec<Option<usize>> {
+		leds.iter()
+			.map(|led| (led.red.round() as usize).checked_sub(1))
+			.collect()
+	}
+
+	#[test]
+	fn window_lengths() {
+		for (len, modifiers, window) in [
+			(10, EffectModifiers::default(), 10),
+			(0, EffectModifiers::default(), 0),
+			(
+				10,
+				EffectModifiers {
+					repeat: 3,
+					..Default::default()
+				},
+				4,
+			),
+			(
+				10,
+				EffectModifiers {
+					mirror: true,
+					..Default::default()
+				},
+				5,
+			),
+			(
+				9,
+				EffectModifiers {
+					mirror: true,
+					..Default::default()
+				},
+				5,
+			),
+			(
+				9,
+				EffectModifiers {
+					skip: 3,
+					..Default::default()
+				},
+				6,
+			),
+			(
+				10,
+				EffectModifiers {
+					start: 2,
+					end: Some(8),
+					..Default::default()
+				},
+				6,
+			),
+			// past the end of the segment
+			(
+				10,
+				EffectModifiers {
+					start: 20,
+					..Default::default()
+				},
+				0,
+			),
+			(
+				10,
+				EffectModifiers {
+					end: Some(20),
+					..Default::default()
+				},
+				10,
+			),
+			(
+				10,
+				EffectModifiers {
+					start: 5,
+					end: Some(3),
+					..Default::default()
+				},
+				0,
+			),
+		] {
+			assert_eq!(window_len(&modifiers, len), window, "{:?}", modifiers);
+		}
+	}
+
+	#[test]
+	fn sources() {
+		for (len, modifiers, sources) in [
+			(4, EffectModifiers::default(), vec![0, 1, 2, 3]),
+			(
+				4,
+				EffectModifiers {
+					offset: 1,
+					..Default::default()
+				},
+				vec![3, 0, 1, 2],
+			),
+			(
+				4,
+				EffectModifiers {
+					offset: -1,
+					..Default::default()
+				},
+				vec![1, 2, 3, 0],
+			),
+			(
+				4,
+				EffectModifiers {
+					offset: 9,
+					..Default::default()
+				},
+				vec![3, 0, 1, 2],
+			),
+			(
+				6,
+				EffectModifiers {
+					repeat: 2,
+					..Default::default()
+				},
+				vec![0, 1, 2, 0, 1, 2],
+			),
+			(
+				5,
+				EffectModifiers {
+					repeat: 2,
+					..Default::default()
+				},
+				vec![0, 1, 2, 0, 1],
+			),
+			(
+				6,
+				EffectModifiers {
+					mirror: true,
+					..Default::default()
+				},
+				vec![0, 1, 2, 2, 1, 0],
+			),
+			(
+				5,
+				EffectModifiers {
+					mirror: true,
+					..Default::default()
+				},
+				vec![0, 1, 2, 1, 0],
+			),
+			(
+				8,
+				EffectModifiers {
+					mirror: true,
+					repeat: 2,
+					..Default::default()
+				},
+				vec![0, 1, 1, 0, 0, 1, 1, 0],
+			),
+			// skipped LEDs don't take a pixel
+			(
+				6,
+				EffectModifiers {
+					skip: 3,
+					..Default::default()
+				},
+				vec![0, 1, 2, 3],
+			),
+			(
+				10,
+				EffectModifiers {
+					start: 2,
+					end: Some(5),
+					..Default::default()
+				},
+				vec![0, 1, 2],
+			),
+		] {
+			let layout = Layout::new(&modifiers, len);
+			let actual = (0..layout.visible)
+				.map(|visible| layout.source(visible))
+				.collect::<Vec<_>>();
+			assert_eq!(actual, sources, "{:?}", modifiers);
+		}
+	}
+
+	#[test]
+	fn apply_layout() {
+		let mut leds = vec![Rgba::default(); 6];
+		let modifiers = EffectModifiers {
+			skip: 3,
+			..Default::default()
+		};
+		apply(&modifiers, &canvas(4), &mut Section::new(&mut leds, false));
+		assert_eq!(
+			indices(&leds),
+			[Some(0), Some(1), None, Some(2), Some(3), None]
+		);
+
+		// LEDs outside of the range are left alone
+		let mut leds = vec![Rgba::new(10.0, 0.0, 0.0, 1.0); 6];
+		let modifiers = EffectModifiers {
+			start: 2,
+			end: Some(4),
+			..Default::default()
+		};
+		apply(&modifiers, &canvas(2), &mut Section::new(&mut leds, false));
+		assert_eq!(
+			indices(&leds),
+			[Some(9), Some(9), Some(0), Some(1), Some(9), Some(9)]
+		);
+
+		// reversed segments show the window from their end
+		let mut leds = vec![Rgba::default(); 4];
+		apply(
+			&EffectModifiers::default(),
+			&canvas(4),
+			&mut Section::new(&mut leds, true),
+		);
+		assert_eq!(indices(&leds), [Some(3), Some(2), Some(1), Some(0)]);
+
+		let mut leds = vec![Rgba::default(); 6];
+		let modifiers = EffectModifiers {
+			mirror: true,
+			..Default::default()
+		};
+		apply(&modifiers, &canvas(3), &mut Section::new(&mut leds, false));
+		assert_eq!(
+			indices(&leds),
+			[Some(0), Some(1), Some(2), Some(2), Some(1), Some(0)]
+		);
+	}
+
+	#[test]
+	fn apply_ignores_mismatched_windows() {
+		// the canvas wasn't resized for the segment yet
+		let mut leds = vec![Rgba::default(); 4];
+		apply(
+			&EffectModifiers::default(),
+			&canvas(3),
+			&mut Section::new(&mut leds, false),
+		);
+		assert_eq!(indices(&leds), [None; 4]);
+
+		let mut leds: Vec<Rgba> = vec![];
+		apply(
+			&EffectModifiers::default(),
+			&[],
+			&mut Section::new(&mut leds, false),
+		);
+
+		let mut leds = vec![Rgba::default(); 4];
+		let modifiers = EffectModifiers {
+			start: 10,
+			..Default::default()
+		};
+		apply(&modifiers, &[], &mut Section::new(&mut leds, false));
+		assert_eq!(indices(&leds), [None; 4]);
+	}
+
+	#[test]
+	fn colors() {
+		let red = Rgba::new(1.0, 0.0, 0.0, 1.0);
+
+		let color = modify(
+			&EffectModifiers {
+				opacity: 0.5,
+				..Default::default()
+			},
+			red,
+		);
+		assert_eq!(color.alpha, 0.5);
+		assert_eq!(color.red, 1.0);
+
+		let color = modify(
+			&EffectModifiers {
+				opacity: 2.0,
+				..Default::default()
+			},
+			red,
+		);
+		assert_eq!(color.alpha, 1.0);
+
+		let color = modify(
+			&EffectModifiers {
+				hue: 120.0,
+				..Default::default()
+			},
+			red,
+		);
+		assert!(color.red < 0.001 && color.green > 0.999 && color.blue < 0.001);
+
+		let color = modify(
+			&EffectModifiers {
+				saturation: 0.0,
+				..Default::default()
+			},
+			red,
+		);
+		assert!((color.red - color.green).abs() < 0.001);
+		assert!((color.green - color.blue).abs() < 0.001);
+
+		assert_eq!(*modify(&EffectModifiers::default(), red), *red);
+	}
+
+	#[test]
+	fn clock_runs() {
+		let mut clock = EffectClock::default();
+		for speed in [0.25, 1.0, 8.0, 100.0] {
+			assert_eq!(clock.advance(speed, false), 1, "{}", speed);
+		}
+		assert_eq!(clock.advance(0.0, false), 0);
+
+		let mut clock = EffectClock::default();
+		let steps = (0..8).map(|_| clock.advance(0.5, true)).collect::<Vec<_>>();
+		assert_eq!(steps, [0, 1, 0, 1, 0, 1, 0, 1]);
+		assert_eq!(clock.advance(8.0, true), 8);
+		assert_eq!(clock.advance(100.0, true), 8);
+		assert_eq!(clock.advance(0.0, true), 0);
+	}
+
+	#[test]
+	fn clock_scales_time() {
+		let mut clock = EffectClock::default();
+		let start = clock.now();
+		thread::sleep(Duration::from_millis(20));
+		clock.advance(0.0, false);
+		assert_eq!(clock.now(), start);
+
+		thread::sleep(Duration::from_millis(20));
+		clock.advance(4.0, false);
+		assert!(clock.now() - start >= Duration::from_millis(80));
+	}
+}
@@ -0,0 +1,186 @@
+//! Frames rendered by clients, pushed through gRPC, a WebSocket or UDP and shown by the `stream`
+//! effect.
+//!
+//! The binary packet format (WebSocket and UDP) is:
+//!
+//! - `u8` target kind, `0` for a segment, `1` for a group
+//! - segment: `u16` strip index and `u16` segment index, group: `u8` length and the utf-8 group id
+//! - `u32` sequence number, `0` disables the order check
+//! - 3 bytes (RGB) per led until the end of the packet
+//!
+//! All numbers are little endian. A frame for a group covers its segments ordered by strip and
+//! segment index.
+//!
+//! UDP packets can't carry a token, with auth enabled they're only accepted from the hosts in
+//! `udp_senders` of the server config.
+
+use std::{
+	collections::HashMap,
+	future::Future,
+	net::{IpAddr, SocketAddr},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use eyre::{bail, ensure, ContextCompat, Result};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::{config::SegmentId, runner::EffectTarget};
+
+/// Upper limit for the number of leds in a single frame.
+pub const MAX_LEDS: usize = 10_000;
+
+/// Frames with a lower sequence number than the previous one are dropped, unless the previous one
+/// is older than this (e.g. the client restarted).
+const SEQUENCE_RESET_AFTER: Duration = Duration::from_secs(1);
+
+/// Frames for targets that haven't been updated in this long are removed.
+const FORGET_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct StreamFrame {
+	pub sequence: u64,
+	/// 3 bytes (RGB) per led.
+	pub pixels:   Vec<u8>,
+	pub received: Instant,
+}
+
+/// The latest frame pushed for every target.
+///
+/// Only the newest frame is kept, so clients sending faster than the render loop just drop frames
+/// instead of building up latency.
+#[derive(Default)]
+pub struct PixelStreams {
+	frames: Mutex<HashMap<EffectTarget, StreamFrame>>,
+}
+
+impl PixelStreams {
+	/// Stores the frame as the latest for the target, returns `false` if it was dropped because it is
+	/// older than the current one.
+	pub fn push(&self, target: EffectTarget, sequence: u64, pixels: &[u8]) -> bool {
+		let mut frames = self.frames.lock().unwrap_or_else(|err| err.into_inner());
+
+		if let Some(current) = frames.get(&target) {
+			if sequence != 0
+				&& sequence <= current.sequence
+				&& current.received.elapsed() < SEQUENCE_RESET_AFTER
+			{
+				return false;
+			}
+		}
+
+		let len = pixels.len().min(MAX_LEDS * 3) / 3 * 3;
+
+		frames.retain(|_, frame| frame.received.elapsed() < FORGET_AFTER);
+		frames.insert(
+			target,
+			StreamFrame {
+				sequence,
+				pixels: pixels[..len].to_vec(),
+				received: Instant::now(),
+			},
+		);
+
+		true
+	}
+
+	/// Calls `f` with the latest frame of the target, if there is one.
+	pub fn with_frame<T>(
+		&self,
+		target: &EffectTarget,
+		f: impl FnOnce(&StreamFrame) -> T,
+	) -> Option<T> {
+		let frames = self.frames.lock().unwrap_or_else(|err| err.into_inner());
+		frames.get(target).map(f)
+	}
+
+	/// Decodes a binary packet and pushes the frame in it.
+	pub fn push_packet(&self, packet: &[u8]) -> Result<bool> {
+		let (target, sequence, pixels) = decode_packet(packet)?;
+		Ok(self.push(target, sequence, pixels))
+	}
+}
+
+fn take<'a>(packet: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+	ensure!(packet.len() >= len, "packet is too short");
+	let (head, tail) = packet.split_at(len);
+	*packet = tail;
+	Ok(head)
+}
+
+/// Decodes a binary packet into the target, sequence number and pixels.
+pub fn decode_packet(mut packet: &[u8]) -> Result<(EffectTarget, u64, &[u8])> {
+	let kind = take(&mut packet, 1)?[0];
+
+	let target = match kind {
+		0 => {
+			let ids = take(&mut packet, 4)?;
+			EffectTarget::Segment(SegmentId {
+				strip_idx:   u16::from_le_bytes([ids[0], ids[1]]) as usize,
+				segment_idx: u16::from_le_bytes([ids[2], ids[3]]) as usize,
+			})
+		}
+		1 => {
+			let len = take(&mut packet, 1)?[0] as usize;
+			let id = std::str::from_utf8(take(&mut packet, len)?)
+				.ok()
+				.wrap_err("group id is not valid utf-8")?;
+			EffectTarget::Group(id.to_string())
+		}
+		kind => bail!("unknown target kind {}", kind),
+	};
+
+	let sequence = take(&mut packet, 4)?;
+	let sequence = u32::from_le_bytes([sequence[0], sequence[1], sequence[2], sequence[3]]);
+
+	ensure!(
+		packet.len() % 3 == 0,
+		"pixel data has to be 3 bytes per led"
+	);
+
+	Ok((target, sequence as u64, packet))
+}
+
+/// Receives packets until `shutdown` resolves, only from the `senders` if given. Nothing is
+/// received if `senders` is empty.
+pub async fn run_udp(
+	streams: Arc<PixelStreams>,
+	addr: SocketAddr,
+	senders: Option<Vec<IpAddr>>,
+	shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+	if senders.as_ref().is_some_and(|senders| senders.is_empty()) {
+		warn!("auth is enabled and no udp senders are allowed, not listening for pixel streams on udp");
+		shutdown.await;
+		return Ok(());
+	}
+
+	let socket = UdpSocket::bind(addr).await?;
+	info!("pixel stream listening on udp {}", addr);
+
+	tokio::pin!(shutdown);
+
+	let mut buf = vec![0; 65536];
+	loop {
+		let (len, from) = tokio::select! {
+			received = socket.recv_from(&mut buf) => received?,
+			_ = &mut shutdown => return Ok(()),
+		};
+
+		if senders
+			.as_ref()
+			.is_some_and(|senders| !senders.contains(&from.ip()))
+		{
+			debug!(
+				"ignoring pixel stream packet from {}, it's not an allowed sender",
+				from
+			);
+			continue;
+		}
+
+		if let Err(err) = streams.push_packet(&buf[..len]) {
+			debug!("invalid pixel stream packet from {}: {:#}", from, err);
+		}
+	}
+}